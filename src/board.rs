@@ -0,0 +1,45 @@
+//! Board support.
+//!
+//! Everything that differs between the BCM2711 of the Raspberry Pi 4 and the
+//! BCM2712 of the Raspberry Pi 5 is described by the module of the board that
+//! the kernel is built for, which is selected by the `board` configuration
+//! option set to either `rpi4` or `rpi5` by the `build` script, and defaults
+//! to `rpi5` in host tests.  Device addresses and interrupts are only used
+//! until the device tree is probed, while register offsets, address
+//! translations and the memory map are used throughout.
+
+#[cfg(not(any(board = "rpi4", board = "rpi5", test)))]
+compile_error!("No board selected, build with `--cfg board=\"rpi4\"` or `--cfg board=\"rpi5\"`");
+
+#[cfg(board = "rpi4")]
+#[path = "board/rpi4.rs"]
+mod rpi4;
+#[cfg(any(board = "rpi5", all(test, not(board = "rpi4"))))]
+#[path = "board/rpi5.rs"]
+mod rpi5;
+
+#[cfg(board = "rpi4")]
+pub use self::rpi4::*;
+#[cfg(any(board = "rpi5", all(test, not(board = "rpi4"))))]
+pub use self::rpi5::*;
+
+/// Method used to start the secondary cores.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+// Each board only uses one of the methods.
+#[allow(dead_code)]
+pub enum EnableMethod
+{
+    /// The cores are powered on and off through PSCI.
+    Psci,
+    /// The cores are parked by the firmware until their entry points are
+    /// written to a table of 64 bit release addresses, and parked by the kernel
+    /// in the same way once they finish.
+    SpinTable
+    {
+        /// Physical address of the release addresses, indexed by core.
+        table: usize,
+        /// Physical address outside the kernel image where the kernel copies
+        /// the loop that parks the cores.
+        park: usize,
+    },
+}
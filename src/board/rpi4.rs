@@ -0,0 +1,158 @@
+//! Raspberry Pi 4 (BCM2711).
+//!
+//! The peripherals are expected in the low peripheral mode set up by the
+//! firmware by default, in which they appear right below the 4GB mark.
+
+use super::EnableMethod;
+
+/// Board name.
+pub const NAME: &str = "Raspberry Pi 4";
+
+/// Memory map.
+pub mod mmu
+{
+    /// Start of each peripheral window, which includes the interrupt
+    /// controller.
+    pub const PERIPHERALS: [usize; 1] = [0xFC00_0000];
+    /// Length of each peripheral window.
+    pub const PERIPHERALS_LEN: usize = 64 << 20;
+}
+
+/// Interrupt controller.
+pub mod irq
+{
+    /// Distributor base address.
+    pub const GICD_BASE: usize = 0xFF841000;
+    /// CPU interface base address.
+    pub const GICC_BASE: usize = 0xFF842000;
+}
+
+/// Power management watchdog.
+pub mod pm
+{
+    /// Base address.
+    pub const BASE: usize = 0xFE100000;
+}
+
+/// Secondary cores.
+pub mod smp
+{
+    use super::EnableMethod;
+
+    /// Bit position of the affinity level 0 field of MPIDR_EL1, which
+    /// identifies the core.
+    pub const CORE_SHIFT: usize = 0;
+    /// Method used to start the secondary cores, which the firmware's ARM stub
+    /// parks waiting for the release addresses right after its own code, with
+    /// the kernel's parking loop past the end of the stub, in the first page
+    /// that the firmware reserves for it.
+    pub const ENABLE_METHOD: EnableMethod = EnableMethod::SpinTable { table: 0xD8,
+                                                                      park: 0x100 };
+}
+
+/// PL011 UART, which is only connected to the GPIO header when Bluetooth is
+/// disabled.
+pub mod uart
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "arm,pl011";
+    /// Base address.
+    pub const BASE: usize = 0xFE201000;
+    /// Interrupt ID at the GIC.
+    pub const IRQ: u32 = 153;
+    /// Clock rate assumed if the firmware doesn't report it.
+    pub const CLOCK_RATE: u32 = 48000000;
+}
+
+/// Video core mailbox.
+pub mod mbox
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "brcm,bcm2835-mbox";
+    /// Base address of the mailbox registers.
+    pub const BASE: usize = 0xFE00B880;
+
+    /// Converts an address handed out by the firmware to a physical address
+    /// by stripping the cache alias from the video core bus address.
+    ///
+    /// * `addr`: Address handed out by the firmware.
+    ///
+    /// Returns the physical address.
+    pub const fn phys_address(addr: usize) -> usize
+    {
+        addr & 0x3FFFFFFF
+    }
+}
+
+/// DMA controller.
+pub mod dma
+{
+    use super::mmu::PERIPHERALS;
+
+    /// Compatible strings of the device tree node, in order of preference,
+    /// which exclude the 40 bit controller since it has its own registers.
+    pub const COMPATIBLES: &[&str] = &["brcm,bcm2835-dma"];
+    /// Base address.
+    pub const BASE: usize = 0xFE007000;
+    /// Channel 0 interrupt ID at the GIC.
+    pub const IRQ: u32 = 112;
+
+    /// Converts a physical address to the 32 bit video core bus address at
+    /// which the DMA controller sees it, with peripherals at 0x7C000000 and
+    /// memory in the first 1GB through the uncached alias at 0xC0000000.
+    ///
+    /// * `phys`: Physical address.
+    ///
+    /// Returns the bus address.
+    pub const fn bus_address(phys: usize) -> usize
+    {
+        if phys >= PERIPHERALS[0] {
+            phys - PERIPHERALS[0] + 0x7C000000
+        } else {
+            phys | 0xC0000000
+        }
+    }
+
+    /// Encodes the address of a control block for the control block register
+    /// and the next control block field, which take its bus address.
+    ///
+    /// * `phys`: Physical address of the control block.
+    ///
+    /// Returns the encoded address.
+    pub const fn control_block_address(phys: usize) -> u32
+    {
+        bus_address(phys) as u32
+    }
+}
+
+/// HDMI 0 controller.
+pub mod hdmi
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "brcm,bcm2711-hdmi0";
+    /// Core register block base.
+    pub const BASE: usize = 0xFEF00700;
+    /// Info frame packet register block base.
+    pub const IF_BASE: usize = 0xFEF01B00;
+    /// HD register block base.
+    pub const HD_BASE: usize = 0xFEF20000;
+    /// Data request device ID of the audio FIFO.
+    pub const DREQ: u32 = 10;
+    /// Audio channel map register in the core block.
+    pub const AU_CHMAP: usize = 0x90;
+    /// Audio configuration register in the core block.
+    pub const AU_CFG: usize = 0x94;
+    /// Packet configuration register in the core block.
+    pub const AU_PKTCFG: usize = 0xB8;
+    /// Info frame configuration register in the core block.
+    pub const IF_CFG: usize = 0xBC;
+    /// Info frame status register in the core block.
+    pub const IF_STATUS: usize = 0xC4;
+    /// Content type reporting packet configuration register in the core
+    /// block.
+    pub const CRP_CFG: usize = 0xC8;
+    /// Clock to service register 0 in the core block.
+    pub const CTS0: usize = 0xCC;
+    /// Clock to service register 1 in the core block.
+    pub const CTS1: usize = 0xD0;
+}
@@ -0,0 +1,143 @@
+//! Raspberry Pi 5 (BCM2712).
+
+use super::EnableMethod;
+
+/// Board name.
+pub const NAME: &str = "Raspberry Pi 5";
+
+/// Memory map.
+pub mod mmu
+{
+    /// Start of each peripheral window, the last of which includes the
+    /// interrupt controller.
+    pub const PERIPHERALS: [usize; 2] = [0x10_0000_0000, 0x10_7C00_0000];
+    /// Length of each peripheral window.
+    pub const PERIPHERALS_LEN: usize = 64 << 20;
+}
+
+/// Interrupt controller.
+pub mod irq
+{
+    /// Distributor base address.
+    pub const GICD_BASE: usize = 0x107FFF9000;
+    /// CPU interface base address.
+    pub const GICC_BASE: usize = 0x107FFFA000;
+}
+
+/// Power management watchdog.
+pub mod pm
+{
+    /// Base address.
+    pub const BASE: usize = 0x107D200000;
+}
+
+/// Secondary cores.
+pub mod smp
+{
+    use super::EnableMethod;
+
+    /// Bit position of the affinity level 1 field of MPIDR_EL1, which
+    /// identifies the core.
+    pub const CORE_SHIFT: usize = 8;
+    /// Method used to start the secondary cores.
+    pub const ENABLE_METHOD: EnableMethod = EnableMethod::Psci;
+}
+
+/// PL011 UART.
+pub mod uart
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "arm,pl011";
+    /// Base address.
+    pub const BASE: usize = 0x107D001000;
+    /// Interrupt ID at the GIC.
+    pub const IRQ: u32 = 153;
+    /// Clock rate assumed if the firmware doesn't report it.
+    pub const CLOCK_RATE: u32 = 9216000;
+}
+
+/// Video core mailbox.
+pub mod mbox
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "brcm,bcm2835-mbox";
+    /// Base address of the mailbox registers.
+    pub const BASE: usize = 0x107C013880;
+
+    /// Converts an address handed out by the firmware to a physical address,
+    /// which it already is.
+    ///
+    /// * `addr`: Address handed out by the firmware.
+    ///
+    /// Returns the physical address.
+    pub const fn phys_address(addr: usize) -> usize
+    {
+        addr
+    }
+}
+
+/// DMA controller.
+pub mod dma
+{
+    /// Compatible strings of the device tree node, in order of preference.
+    pub const COMPATIBLES: &[&str] = &["brcm,bcm2835-dma", "brcm,bcm2712-dma"];
+    /// Base address.
+    pub const BASE: usize = 0x1000010000;
+    /// Channel 0 interrupt ID at the GIC.
+    pub const IRQ: u32 = 112;
+
+    /// Converts a physical address to the address at which the DMA controller
+    /// sees it, which is the same 40 bit address.
+    ///
+    /// * `phys`: Physical address.
+    ///
+    /// Returns the bus address.
+    pub const fn bus_address(phys: usize) -> usize
+    {
+        phys
+    }
+
+    /// Encodes the address of a control block for the control block register
+    /// and the next control block field, which take it shifted 5 bits to the
+    /// right.
+    ///
+    /// * `phys`: Physical address of the control block.
+    ///
+    /// Returns the encoded address.
+    pub const fn control_block_address(phys: usize) -> u32
+    {
+        (phys >> 5) as u32
+    }
+}
+
+/// HDMI 0 controller.
+pub mod hdmi
+{
+    /// Compatible string of the device tree node.
+    pub const COMPATIBLE: &str = "brcm,bcm2712-hdmi0";
+    /// Core register block base.
+    pub const BASE: usize = 0x107C701400;
+    /// Info frame packet register block base.
+    pub const IF_BASE: usize = 0x107C703800;
+    /// HD register block base.
+    pub const HD_BASE: usize = 0x107C720000;
+    /// Data request device ID of the audio FIFO.
+    pub const DREQ: u32 = 10;
+    /// Audio channel map register in the core block.
+    pub const AU_CHMAP: usize = 0xA4;
+    /// Audio configuration register in the core block.
+    pub const AU_CFG: usize = 0xA8;
+    /// Packet configuration register in the core block.
+    pub const AU_PKTCFG: usize = 0xC0;
+    /// Info frame configuration register in the core block.
+    pub const IF_CFG: usize = 0xC4;
+    /// Info frame status register in the core block.
+    pub const IF_STATUS: usize = 0xCC;
+    /// Content type reporting packet configuration register in the core
+    /// block.
+    pub const CRP_CFG: usize = 0xD0;
+    /// Clock to service register 0 in the core block.
+    pub const CTS0: usize = 0xD4;
+    /// Clock to service register 1 in the core block.
+    pub const CTS1: usize = 0xD8;
+}
@@ -1,34 +1,50 @@
 .bss
 
-.balign 0x1000
-// Stack for both EL1 and EL2.
-eln_stack:
-.zero 0x1000
-// Stack for EL0.
-el0_stack:
-.zero 0x1000
-// Translation tables initialized with invalid records.
-root_tt:
-.zero 0x1000
-static_tt:
-.zero 0x1000
-low_perry_tt:
-.zero 0x1000
-high_perry_tt:
-.zero 0x1000
-static_detail_tt:
-.zero 0x1000
+.balign 0x2000
+// Stacks of every core, each made of a guard page followed by the ELn stack, used for both EL1
+// and EL2, and another guard page followed by the EL0 stack.  The guard pages are left unmapped
+// so that overflows fault, and the ELn stacks start at odd pages so that the vectors can detect
+// overflows by checking bit 12 of the stack pointer.
+//
+// The layout must match `smp.rs`.
+.globl stacks
+stacks:
+.zero 0x4000 * 4
+// Stacks for reporting overflows of the ELn stacks, one per core.
+overflow_stacks:
+.zero 0x1000 * 4
 
 .text
 
 .section .text.boot
 
 // Boot code.
+//
+// x0: Argument for the Rust entry point, which is the device tree address on the boot core.
+// x19: Rust entry point of the core (preserved).
+// x20: Argument for the Rust entry point (preserved).
+//
+// Cores are identified by the MPIDR_EL1 affinity field at bit `core_shift`, which depends on the
+// board.
 .globl boot
+.type boot, %function
 boot:
-    // Set up the ELN stack.
-    adrp fp, eln_stack
-    add fp, fp, #1 << 12
+    adr x19, start
+    b 0f
+
+// Secondary core boot code, entered through PSCI.
+.globl secondary_boot
+.type secondary_boot, %function
+secondary_boot:
+    adr x19, secondary_start
+0:
+    mov x20, x0
+    // Set up the ELN stack of this core.
+    mrs x0, mpidr_el1
+    ubfx x0, x0, #{core_shift}, #8
+    adrp fp, stacks
+    add fp, fp, x0, lsl #14
+    add fp, fp, #0x2000
     mov sp, fp
     // Execute boot code depending on the current exception level.
     mrs x0, currentel
@@ -55,8 +71,7 @@ boot:
     msr vmpidr_el2, x0
     mov x0, #0xc4
     msr spsr_el2, x0
-    adr x0, start
-    msr elr_el2, x0
+    msr elr_el2, x19
     msr sp_el1, fp
 1:
     // Set up EL1 registers.
@@ -69,8 +84,11 @@ boot:
     msr vbar_el1, x0
     mov x0, #0xc4
     msr spsr_el1, x0
+    msr elr_el1, x19
+    // Only the boot core sets up the memory shared by all the cores.
     adr x0, start
-    msr elr_el1, x0
+    cmp x19, x0
+    bne 1f
     // Clean up the BSS.
     adrp x0, bss_start
     adrp x1, bss_end
@@ -80,165 +98,232 @@ boot:
     stp xzr, xzr, [x0], #0x10
     b 0b
 0:
-    // Initialize the translation tables.
-    adrp x0, root_tt
-    adrp x1, static_tt
-    mov x2, #0x8000 << 48
-    movk x2, #0x403
-    orr x1, x1, x2
-    str x1, [x0]
-    adrp x1, low_perry_tt
-    orr x1, x1, x2
-    str x1, [x0, 0x200]
-    adrp x1, high_perry_tt
-    orr x1, x1, x2
-    str x1, [x0, 0x208]
-    adrp x0, static_tt
-    adrp x1, static_detail_tt
-    orr x1, x1, x2
-    str x1, [x0]
-    adrp x0, boot_start
-    mov x1, x0
-    adrp x2, boot_end
-    sub x2, x2, x1
-    mov x3, #0x4a3
-    adrp x4, static_detail_tt
-    mov x5, #1 << 12
-    bl map
-    adrp x0, text_start
-    mov x1, x0
-    adrp x2, text_end
-    sub x2, x2, x1
-    bl map
-    adrp x0, rodata_start
-    mov x1, x0
-    adrp x2, rodata_end
-    sub x2, x2, x1
-    movk x3, #0x20, lsl 48
-    bl map
-    adrp x0, data_start
-    mov x1, x0
-    adrp x2, data_end
-    sub x2, x2, x1
-    movk x3, #0x723
-    bl map
-    adrp x0, bss_start
-    mov x1, x0
-    adrp x2, bss_end
-    sub x2, x2, x1
-    bl map
-    mov x0, xzr
-    mov x1, #0x10 << 32
-    mov x2, #64 << 20
-    mov x3, #0x30 << 48
-    movk x3, #0x429
-    adrp x4, low_perry_tt
-    mov x5, #1 << 21
-    bl map
-    mov x0, #0x3c << 24
-    mov x1, #0x10 << 32
-    movk x1, #0x7c00, lsl 16
-    mov x2, #64 << 20
-    adrp x4, high_perry_tt
-    bl map
-    adrp x0, heap_start
-    mov x1, x0
-    adrp x2, heap_end
-    sub x2, x2, x1
-    mov x3, #0x30 << 48
-    movk x3, #0x425
-    adrp x4, static_tt
-    bl map
-    mov x0, 0x3c << 24
-    mov x1, x0
-    mov x2, #64 << 20
-    bl map
-    // Configure and enable the MMu.
-    adrp x0, root_tt
-    msr ttbr0_el1, x0
-    mov x0, #0x2 << 32
-    movk x0, #0xa59d, lsl 16
-    movk x0, #0x251a
-    msr tcr_el1, x0
-    mov x0, #0x44ff
-    msr mair_el1, x0
-    mov x0, #0x30d0 << 16
+    // Build the translation tables.
+    bl map_kernel
+1:
+    bl configure_mmu
+    // Enable the MMU with writable memory never executable.
+    mov x0, #0x30d8 << 16
     movk x0, #0x1b9f
     msr sctlr_el1, x0
     isb
-    // Jump to Rust code at EL1 with SP_EL0.
-    adrp fp, el0_stack
-    add fp, fp, #0x1000
+    // Jump to Rust code at EL1 with SP_EL0, whose stack ends 0x2000 bytes after the ELn stack.
+    add fp, fp, #0x2000
     msr sp_el0, fp
     mov fp, xzr
+    mov x0, x20
     eret
 
-// Map function.
+// Saves the registers other than x0, x1, ELR and SPSR in the trap frame at the top of the stack,
+// clobbering x2.
 //
-// x0: Start virtual offset (clobbered).
-// x1: Start physical address (clobbered.
-// x2: Length (clobbered).
-// x3: Descriptor template.
-// x4: Table.
-// x5: Stride.
-// x6: Temporary (clobbered).
-map:
-    // Compute the address of the first record.
-    udiv x0, x0, x5
-    lsl x0, x0, #3
-    add x0, x0, x4
-    // Compute the end physical address.
-    add x2, x1, x2
-    // Write the records.
-0:
-    cmp x1, x2
-    beq 0f
-    orr x6, x3, x1
-    str x6, [x0], #0x8
-    add x1, x1, x5
-    b 0b
+// The trap frame layout must match `TrapFrame` in `exception.rs`.
+.macro save_regs
+    stp x2, x3, [sp, #0x10]
+    stp x4, x5, [sp, #0x20]
+    stp x6, x7, [sp, #0x30]
+    stp x8, x9, [sp, #0x40]
+    stp x10, x11, [sp, #0x50]
+    stp x12, x13, [sp, #0x60]
+    stp x14, x15, [sp, #0x70]
+    stp x16, x17, [sp, #0x80]
+    stp x18, x19, [sp, #0x90]
+    stp x20, x21, [sp, #0xa0]
+    stp x22, x23, [sp, #0xb0]
+    stp x24, x25, [sp, #0xc0]
+    stp x26, x27, [sp, #0xd0]
+    stp x28, x29, [sp, #0xe0]
+    str x30, [sp, #0xf0]
+    stp q0, q1, [sp, #0x110]
+    stp q2, q3, [sp, #0x130]
+    stp q4, q5, [sp, #0x150]
+    stp q6, q7, [sp, #0x170]
+    stp q8, q9, [sp, #0x190]
+    stp q10, q11, [sp, #0x1b0]
+    stp q12, q13, [sp, #0x1d0]
+    stp q14, q15, [sp, #0x1f0]
+    stp q16, q17, [sp, #0x210]
+    stp q18, q19, [sp, #0x230]
+    stp q20, q21, [sp, #0x250]
+    stp q22, q23, [sp, #0x270]
+    stp q24, q25, [sp, #0x290]
+    stp q26, q27, [sp, #0x2b0]
+    stp q28, q29, [sp, #0x2d0]
+    stp q30, q31, [sp, #0x2f0]
+    mrs x2, fpcr
+    str x2, [sp, #0x310]
+    mrs x2, fpsr
+    str x2, [sp, #0x318]
+.endm
+
+// Restores all the general purpose and SIMD registers from the trap frame at the top of the stack
+// and frees it.
+.macro restore_regs
+    ldr x2, [sp, #0x310]
+    msr fpcr, x2
+    ldr x2, [sp, #0x318]
+    msr fpsr, x2
+    ldp q30, q31, [sp, #0x2f0]
+    ldp q28, q29, [sp, #0x2d0]
+    ldp q26, q27, [sp, #0x2b0]
+    ldp q24, q25, [sp, #0x290]
+    ldp q22, q23, [sp, #0x270]
+    ldp q20, q21, [sp, #0x250]
+    ldp q18, q19, [sp, #0x230]
+    ldp q16, q17, [sp, #0x210]
+    ldp q14, q15, [sp, #0x1f0]
+    ldp q12, q13, [sp, #0x1d0]
+    ldp q10, q11, [sp, #0x1b0]
+    ldp q8, q9, [sp, #0x190]
+    ldp q6, q7, [sp, #0x170]
+    ldp q4, q5, [sp, #0x150]
+    ldp q2, q3, [sp, #0x130]
+    ldp q0, q1, [sp, #0x110]
+    ldr x30, [sp, #0xf0]
+    ldp x28, x29, [sp, #0xe0]
+    ldp x26, x27, [sp, #0xd0]
+    ldp x24, x25, [sp, #0xc0]
+    ldp x22, x23, [sp, #0xb0]
+    ldp x20, x21, [sp, #0xa0]
+    ldp x18, x19, [sp, #0x90]
+    ldp x16, x17, [sp, #0x80]
+    ldp x14, x15, [sp, #0x70]
+    ldp x12, x13, [sp, #0x60]
+    ldp x10, x11, [sp, #0x50]
+    ldp x8, x9, [sp, #0x40]
+    ldp x6, x7, [sp, #0x30]
+    ldp x4, x5, [sp, #0x20]
+    ldp x2, x3, [sp, #0x10]
+    ldp x0, x1, [sp]
+    add sp, sp, #0x320
+.endm
+
+// Interrupt vector entry.
+//
+// Allocates a trap frame on the stack, saves x0 and x1 in it, and passes the kind of the entry in
+// x0 to the target.  Entries taken with SP_ELx check whether the trap frame landed on the guard
+// page first, swapping x0 with sp to test it without touching the stack, and report the overflow
+// from a separate stack with x0 stashed in TPIDR_EL1, which is reserved for this.
+.macro vector_entry kind, target
+    sub sp, sp, #0x320
+.if ((\kind) & 0xc) == 0x4
+    add sp, sp, x0
+    sub x0, sp, x0
+    tbz x0, #12, 0f
+    sub x0, sp, x0
+    sub sp, sp, x0
+    b 1f
 0:
-    ret
+    sub x0, sp, x0
+    sub sp, sp, x0
+    msr tpidr_el1, x0
+    mov x0, #(\kind) + 0x10
+    b overflow_entry
+1:
+.endif
+    stp x0, x1, [sp]
+    mov x0, #\kind
+    b \target
+.balign 0x80
+.endm
 
 // Interrupt vector.
 //
-// Panics on any EL2 interrupts, any Sync or SError EL1 interrupts, and does nothing for FIQs and IRQs
-// since those are handled synchronously.
+// Any EL2 exceptions and any Sync or SError EL1 exceptions are reported as faults, EL1 IRQs are
+// dispatched, and FIQs are masked since those are not used.
 .balign 0x800
+.type ivec, %function
 ivec:
-.irp kind,0,4,8,c
-    mov x0, #0x\kind
-    mov fp, sp
-    b fault
-.balign 0x80
-    stp x0, fp, [sp, #-0x10]!
-    mov fp, sp
-    mrs x0, currentel
-    cmp x0, #0x4
-    mov x0, #0x\kind + 1
-    bne fault
-    mrs x0, spsr_el1
-    orr x0, x0, #0xc0
-    msr spsr_el1, x0
-    ldp x0, fp, [sp], #0x10
+.irp origin,0x0,0x4,0x8,0xc
+    vector_entry \origin, fault_entry
+    vector_entry \origin + 1, irq_entry
+    vector_entry \origin + 2, fiq_entry
+    vector_entry \origin + 3, fault_entry
+.endr
+
+// Fault entry.
+//
+// Completes the trap frame and reports the fault, never returning.
+.type fault_entry, %function
+fault_entry:
+    save_regs
+    mrs x2, currentel
+    cmp x2, #0x8
+    beq 0f
+    mrs x2, elr_el1
+    mrs x3, spsr_el1
+    b 1f
+0:
+    mrs x2, elr_el2
+    mrs x3, spsr_el2
+1:
+    stp x2, x3, [sp, #0xf8]
+    // The saved x29 and x30 form a frame record linking back to the interrupted code.
+    add fp, sp, #0xe8
+    mov x1, sp
+    bl fault
+
+// Overflow entry.
+//
+// Moves the trap frame to the overflow stack of the core, restoring x0 from TPIDR_EL1, and
+// reports the fault.  The overflowed stack pointer holds x1 meanwhile, and the kind of the entry
+// rides in the low bits of the page aligned offset of the overflow stack, so that no other
+// register is clobbered.
+.type overflow_entry, %function
+overflow_entry:
+    mov sp, x1
+    mrs x1, mpidr_el1
+    ubfx x1, x1, #{core_shift}, #8
+    add x1, x1, #1
+    lsl x1, x1, #12
+    orr x0, x0, x1
+    adrp x1, overflow_stacks
+    add x1, x1, x0
+    and x1, x1, #~0xfff
+    and x0, x0, #0xfff
+    add sp, sp, x1
+    sub x1, sp, x1
+    sub sp, sp, x1
+    sub sp, sp, #0x320
+    str x1, [sp, #0x8]
+    mrs x1, tpidr_el1
+    str x1, [sp]
+    b fault_entry
+
+// IRQ entry.
+//
+// Completes the trap frame, calls the Rust dispatcher with it, and restores the context.
+.type irq_entry, %function
+irq_entry:
+    mrs x1, currentel
+    cmp x1, #0x4
+    bne fault_entry
+    save_regs
+    mrs x2, elr_el1
+    mrs x3, spsr_el1
+    stp x2, x3, [sp, #0xf8]
+    add fp, sp, #0xe8
+    mov x0, sp
+    bl irq
+    ldp x2, x3, [sp, #0xf8]
+    msr elr_el1, x2
+    msr spsr_el1, x3
+    restore_regs
     eret
-.balign 0x80
-    stp x0, fp, [sp, #-0x10]!
-    mov fp, sp
-    mrs x0, currentel
-    cmp x0, #0x4
-    mov x0, #0x\kind + 2
-    bne fault
-    mrs x0, spsr_el1
-    orr x0, x0, #0xc0
-    msr spsr_el1, x0
-    ldp x0, fp, [sp], #0x10
+
+// FIQ entry.
+//
+// Masks FIQs in the interrupted code and returns to it.
+.type fiq_entry, %function
+fiq_entry:
+    mrs x1, currentel
+    cmp x1, #0x4
+    bne fault_entry
+    mrs x1, spsr_el1
+    orr x1, x1, #0x40
+    msr spsr_el1, x1
+    ldp x0, x1, [sp]
+    add sp, sp, #0x320
     eret
-.balign 0x80
-    mov x0, #0x\kind + 3
-    mov fp, sp
-    b fault
-.balign 0x80
-.endr
 
 .section .text
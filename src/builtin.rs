@@ -79,6 +79,29 @@ pub unsafe extern "C" fn memset(buf: *mut c_void, val: c_int, len: c_size_t) ->
     ret
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn memcmp(lhs: *const c_void, rhs: *const c_void, len: c_size_t) -> c_int
+{
+    let mut lhs = lhs as usize;
+    let mut rhs = rhs as usize;
+    let end = lhs + len as usize;
+    while lhs != end {
+        let lval: c_int;
+        let rval: c_int;
+        asm!("ldrb {lval:w}, [{lhs}], #1", "ldrb {rval:w}, [{rhs}], #1", lval = out (reg) lval, rval = out (reg) rval, lhs = inout (reg) lhs, rhs = inout (reg) rhs, options (preserves_flags));
+        if lval != rval {
+            return lval - rval;
+        }
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bcmp(lhs: *const c_void, rhs: *const c_void, len: c_size_t) -> c_int
+{
+    memcmp(lhs, rhs, len)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn fmodf(x: c_float, y: c_float) -> c_float
 {
@@ -0,0 +1,248 @@
+//! Serial chainloader.
+//!
+//! Receives a kernel image from the host over the UART using the framing
+//! protocol and boots it in place of the running kernel.  Since the new image
+//! overwrites the running code, the final copy is performed by a small stub
+//! that is relocated to the heap and runs with the MMU disabled.
+
+use alloc::alloc::{alloc, handle_alloc_error};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::arch::{asm, global_asm};
+use core::ptr::{addr_of, copy_nonoverlapping, write_bytes};
+
+use crate::proto::{self, crc32_update, reply, Image, Kind, Link, RecvError, MAX_PAYLOAD, NAK_CHECKSUM, NAK_CORRUPT,
+                   NAK_TOO_LARGE, NAK_UNEXPECTED};
+use crate::uart::{RxError, Uart};
+use crate::{dma, fdt, hdmi, irq, smp};
+
+/// Address at which kernel images are loaded.
+const LOAD_ADDR: usize = 0x80000;
+/// Cache line size.
+const CACHE_LINE_SIZE: usize = 64;
+/// Mask of the MMU, data cache and instruction cache enable bits of the system
+/// control register.
+const SCTLR_MMU_CACHES: usize = 0x1005;
+
+extern "C" {
+    /// Start of the crash log region, which is also the end of the memory
+    /// reserved for the kernel, defined by the linker script.
+    static crash_log_start: u8;
+    /// Start of the cached heap region, defined by the linker script.
+    static cached_heap_start: u8;
+    /// End of the cached heap region, defined by the linker script.
+    static cached_heap_end: u8;
+    /// Start of the relocatable stub.
+    static chainload_stub: u8;
+    /// End of the relocatable stub.
+    static chainload_stub_end: u8;
+}
+
+// Relocatable stub that copies the image to its load address and jumps to it.
+//
+// x0: Image address, aligned to 8 bytes.
+// x1: Image size, a multiple of 8 bytes.
+// x2: Load address, aligned to a cache line.
+// x3: End of the memory reserved for the kernel.
+// x7: Device tree address, passed on to the new kernel in x0.
+//
+// Runs with the MMU and caches disabled, so any lines still cached for the
+// kernel's memory are discarded first, as they would otherwise be written back
+// on top of the new image.
+global_asm!(".section .text",
+            ".balign 4",
+            "chainload_stub:",
+            "    mov x4, x2",
+            "0:",
+            "    dc ivac, x4",
+            "    add x4, x4, #64",
+            "    cmp x4, x3",
+            "    b.lo 0b",
+            "    dsb sy",
+            "    mov x4, x2",
+            "    add x5, x0, x1",
+            "0:",
+            "    cmp x0, x5",
+            "    b.hs 0f",
+            "    ldr x6, [x0], #8",
+            "    str x6, [x4], #8",
+            "    b 0b",
+            "0:",
+            "    dsb sy",
+            "    ic iallu",
+            "    tlbi vmalle1",
+            "    dsb sy",
+            "    isb",
+            "    mov x0, x7",
+            "    mov x1, xzr",
+            "    mov x3, xzr",
+            "    br x2",
+            "chainload_stub_end:");
+
+/// UART transport.
+struct UartLink;
+
+impl Link for UartLink
+{
+    type Error = RxError;
+
+    fn read_byte(&mut self) -> Result<u8, RxError>
+    {
+        Uart.read_byte()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RxError>
+    {
+        Uart.write_bytes(bytes);
+        Ok(())
+    }
+}
+
+/// Returns the largest image that fits in the memory reserved for the kernel.
+pub fn max_image_size() -> usize
+{
+    addr_of!(crash_log_start) as usize - LOAD_ADDR
+}
+
+/// Waits for the host to send a kernel image, restarting the transfer as many
+/// times as necessary until an image arrives intact.
+///
+/// Returns the received image.
+pub fn receive() -> Vec<u8>
+{
+    let mut link = UartLink;
+    let mut buf = [0; MAX_PAYLOAD];
+    // Header and data of the image being received.
+    let mut image: Option<(Image, Vec<u8>)> = None;
+    let mut next = 0u8;
+    loop {
+        let frame = match proto::receive(&mut link, &mut buf) {
+            Ok(frame) => frame,
+            Err(RecvError::Link(_) | RecvError::Corrupt) => {
+                reply(&mut link, Kind::Nak, next, &[NAK_CORRUPT]);
+                continue;
+            }
+        };
+        match frame.kind {
+            Kind::Start => {
+                let Some(header) = Image::from_bytes(frame.payload) else {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_CORRUPT]);
+                    continue;
+                };
+                if header.size as usize > max_image_size() {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_TOO_LARGE]);
+                    continue;
+                }
+                image = Some((header, Vec::with_capacity(header.size as usize)));
+                next = frame.seq.wrapping_add(1);
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+            }
+            Kind::Data => {
+                let Some((header, data)) = image.as_mut() else {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_UNEXPECTED]);
+                    continue;
+                };
+                if frame.seq == next.wrapping_sub(1) {
+                    // The acknowledgement got lost and the host resent a frame that has
+                    // already been stored.
+                    reply(&mut link, Kind::Ack, frame.seq, &[]);
+                    continue;
+                }
+                if frame.seq != next || data.len() + frame.payload.len() > header.size as usize {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_UNEXPECTED]);
+                    continue;
+                }
+                data.extend_from_slice(frame.payload);
+                next = next.wrapping_add(1);
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+            }
+            Kind::End => {
+                let Some((header, data)) = image.take() else {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_UNEXPECTED]);
+                    continue;
+                };
+                if data.len() != header.size as usize || !crc32_update(!0, &data) != header.crc {
+                    // Start over, since there's no telling which part got corrupted.
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_CHECKSUM]);
+                    continue;
+                }
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+                return data;
+            }
+            Kind::Ack | Kind::Nak => (),
+        }
+    }
+}
+
+/// Shuts down the peripherals and boots a kernel image.
+///
+/// * `image`: Image to boot.
+///
+/// Panics if the image doesn't fit in the memory reserved for the kernel, or
+/// if any secondary core is still running or doesn't stop in time.
+#[track_caller]
+pub fn boot(image: &[u8]) -> !
+{
+    assert!(image.len() <= max_image_size(),
+            "Kernel image is too large: {} bytes",
+            image.len());
+    // Secondary cores would keep running code that is about to be overwritten,
+    // so they must have finished and be on their way out of the kernel image.
+    assert!((1 .. smp::CORES).all(|core| !smp::is_running(core)),
+            "Secondary cores are still running");
+    assert!((1 .. smp::CORES).all(|core| smp::wait_stopped(core).is_ok()),
+            "Secondary cores didn't stop in time");
+    let size = image.len().next_multiple_of(8);
+    let stub = addr_of!(chainload_stub);
+    let stub_size = addr_of!(chainload_stub_end) as usize - stub as usize;
+    // Keep both the image and the stub out of the memory being overwritten.
+    let layout = Layout::from_size_align(size + stub_size, CACHE_LINE_SIZE).unwrap();
+    let buf = unsafe { alloc(layout) };
+    if buf.is_null() {
+        handle_alloc_error(layout);
+    }
+    unsafe {
+        copy_nonoverlapping(image.as_ptr(), buf, image.len());
+        write_bytes(buf.add(image.len()), 0, size - image.len());
+        copy_nonoverlapping(stub, buf.add(size), stub_size);
+    }
+    hdmi::stop_audio();
+    // Whatever couldn't be transmitted in time is not worth holding the new
+    // kernel back for.
+    let _ = Uart.flush();
+    // Leave the distributor without interrupts enabled for handlers that the
+    // new kernel doesn't have.
+    irq::unregister(Uart::irq());
+    irq::unregister(dma::irq());
+    unsafe {
+        asm!("msr daifset, #0xf", options(nomem, nostack, preserves_flags));
+        // Write everything in the heap out to memory so that it's visible with the
+        // caches disabled, and so that nothing dirty is left behind for the new
+        // kernel.
+        let start = addr_of!(cached_heap_start) as usize;
+        let end = addr_of!(cached_heap_end) as usize;
+        for addr in (start .. end).step_by(CACHE_LINE_SIZE) {
+            asm!("dc civac, {addr}", addr = in (reg) addr, options (preserves_flags));
+        }
+        // The kernel is identity mapped, so execution carries on seamlessly once
+        // the MMU is disabled.
+        asm!(
+            "dsb sy",
+            "mrs x4, sctlr_el1",
+            "bic x4, x4, x5",
+            "msr sctlr_el1, x4",
+            "isb",
+            "ic iallu",
+            "dsb sy",
+            "isb",
+            "br x6",
+            in ("x0") buf,
+            in ("x1") size,
+            in ("x2") LOAD_ADDR,
+            in ("x3") start,
+            in ("x5") SCTLR_MMU_CACHES,
+            in ("x6") buf.add(size),
+            in ("x7") fdt::get().map_or(0, |fdt| fdt.addr()),
+            options (noreturn, nostack));
+    }
+}
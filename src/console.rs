@@ -0,0 +1,179 @@
+//! Framebuffer text console.
+//!
+//! Renders text on the framebuffer allocated by the firmware using the bitmap
+//! font, scrolling up when the bottom of the screen is reached.
+
+use core::fmt::{self, Write};
+use core::ptr::{copy, write_bytes};
+
+use crate::board::mbox::phys_address;
+use crate::font::{self, GLYPHS};
+use crate::mbox;
+use crate::sync::SpinLock;
+
+/// Allocate frame buffer property tag.
+const GET_FB_TAG: u32 = 0x40001;
+/// Get physical display size property tag.
+const GET_SIZE_TAG: u32 = 0x40003;
+/// Get frame buffer depth property tag.
+const GET_DEPTH_TAG: u32 = 0x40005;
+/// Get frame buffer pitch property tag.
+const GET_PITCH_TAG: u32 = 0x40008;
+/// Foreground color in 16 and 32 bit pixel formats.
+const FOREGROUND: (u16, u32) = (0xFFFF, 0xFFFFFFFF);
+/// Tab stop interval in columns.
+const TAB_WIDTH: usize = 8;
+
+/// Screen state, or `None` if the console hasn't been set up.
+static SCREEN: SpinLock<Option<Screen>> = SpinLock::new(None);
+
+/// Framebuffer text console.
+pub struct Console;
+
+/// Screen state.
+#[derive(Debug)]
+struct Screen
+{
+    /// Framebuffer address.
+    base: usize,
+    /// Distance between pixel rows in bytes.
+    pitch: usize,
+    /// Bytes per pixel.
+    bpp: usize,
+    /// Number of text columns.
+    cols: usize,
+    /// Number of text rows.
+    rows: usize,
+    /// Cursor column.
+    col: usize,
+    /// Cursor row.
+    row: usize,
+}
+
+impl Console
+{
+    /// Sets up the console on the framebuffer allocated by the firmware and
+    /// clears the screen.
+    ///
+    /// Returns whether the console is available, which fails if the firmware
+    /// doesn't respond or the framebuffer has an unsupported pixel depth.
+    pub fn init() -> bool
+    {
+        let get_fb_in: u32 = 4;
+        let mut get_fb_out = [0u32; 2];
+        let mut get_size_out = [0u32; 2];
+        let mut get_depth_out = 0u32;
+        let mut get_pitch_out = 0u32;
+        let res = mbox! {
+            GET_FB_TAG: get_fb_in => get_fb_out,
+            GET_SIZE_TAG: _ => get_size_out,
+            GET_DEPTH_TAG: _ => get_depth_out,
+            GET_PITCH_TAG: _ => get_pitch_out,
+        };
+        if res.is_err() || get_depth_out != 16 && get_depth_out != 32 {
+            return false;
+        }
+        let screen = Screen { base: phys_address(get_fb_out[0] as usize),
+                              pitch: get_pitch_out as usize,
+                              bpp: get_depth_out as usize / 8,
+                              cols: get_size_out[0] as usize / font::WIDTH,
+                              rows: get_size_out[1] as usize / font::HEIGHT,
+                              col: 0,
+                              row: 0 };
+        unsafe { write_bytes(screen.base as *mut u8, 0, screen.pitch * screen.rows * font::HEIGHT) };
+        *SCREEN.lock_irq() = Some(screen);
+        true
+    }
+
+    /// Returns whether the console has been set up.
+    pub fn is_available() -> bool
+    {
+        SCREEN.lock_irq().is_some()
+    }
+
+    /// Writes bytes to the screen, doing nothing if the console hasn't been set
+    /// up.
+    ///
+    /// * `bytes`: Bytes to write, with non printable characters other than line
+    ///   feeds, carriage returns and tabs shown as question marks.
+    pub fn write_bytes(&mut self, bytes: &[u8])
+    {
+        let mut screen = SCREEN.lock_irq();
+        let Some(screen) = screen.as_mut() else {
+            return;
+        };
+        for byte in bytes.iter().copied() {
+            match byte {
+                b'\n' => screen.new_line(),
+                b'\r' => screen.col = 0,
+                b'\t' => {
+                    for _ in 0 .. TAB_WIDTH - screen.col % TAB_WIDTH {
+                        screen.put(b' ');
+                    }
+                }
+                _ => screen.put(byte),
+            }
+        }
+    }
+}
+
+impl Screen
+{
+    /// Draws a character at the cursor and advances it, wrapping around to the
+    /// next line at the right edge.
+    ///
+    /// * `byte`: Character to draw.
+    fn put(&mut self, byte: u8)
+    {
+        if self.col == self.cols {
+            self.new_line();
+        }
+        let byte = if (font::FIRST ..= font::LAST).contains(&byte) {
+            byte
+        } else {
+            b'?'
+        };
+        let glyph = &GLYPHS[(byte - font::FIRST) as usize];
+        let origin = self.base + self.row * font::HEIGHT * self.pitch + self.col * font::WIDTH * self.bpp;
+        for (y, bits) in glyph.iter().enumerate() {
+            let line = origin + y * self.pitch;
+            for x in 0 .. font::WIDTH {
+                let set = bits >> x & 0x1 != 0;
+                unsafe {
+                    match self.bpp {
+                        2 => ((line + x * 2) as *mut u16).write(if set { FOREGROUND.0 } else { 0 }),
+                        _ => ((line + x * 4) as *mut u32).write(if set { FOREGROUND.1 } else { 0 }),
+                    }
+                }
+            }
+        }
+        self.col += 1;
+    }
+
+    /// Moves the cursor to the start of the next line, scrolling the screen up
+    /// if it's on the last line.
+    fn new_line(&mut self)
+    {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        let line_size = self.pitch * font::HEIGHT;
+        unsafe {
+            copy((self.base + line_size) as *const u8,
+                 self.base as *mut u8,
+                 line_size * (self.rows - 1));
+            write_bytes((self.base + line_size * (self.rows - 1)) as *mut u8, 0, line_size);
+        }
+    }
+}
+
+impl Write for Console
+{
+    fn write_str(&mut self, msg: &str) -> fmt::Result
+    {
+        self.write_bytes(msg.as_bytes());
+        Ok(())
+    }
+}
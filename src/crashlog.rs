@@ -0,0 +1,107 @@
+//! Crash log that survives warm reboots.
+//!
+//! Panic messages, including the registers reported by faults, are appended
+//! to a ring log kept in a memory region that is neither part of the image nor
+//! cleared at boot, so they can be reported on the next boot even if nobody
+//! was watching the UART when the crash happened.  The log is written out to
+//! main memory after every change, since a reset discards the caches.
+
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::ptr::addr_of_mut;
+
+use crate::cleanup_cache;
+use crate::uart::Uart;
+
+/// Tag identifying an initialized log.
+const MAGIC: u64 = 0x474F4C4853415243;
+/// Size of the region reserved for the log by the linker script.
+const REGION_SIZE: usize = 0x1000;
+/// Capacity of the log in bytes.
+const CAPACITY: usize = REGION_SIZE - 16;
+
+const _: () = assert!(size_of::<CrashLog>() <= REGION_SIZE);
+
+extern "C" {
+    /// Crash log region, defined by the linker script.
+    static mut crash_log_start: CrashLog;
+}
+
+/// Writer that appends to the crash log.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashLog
+{
+    /// Tag identifying an initialized log.
+    magic: u64,
+    /// Offset at which the next byte will be written.
+    head: u32,
+    /// Number of bytes in the log.
+    len: u32,
+    /// Contents.
+    data: [u8; CAPACITY],
+}
+
+impl CrashLog
+{
+    /// Returns the crash log, resetting it if the region holds anything other
+    /// than a valid log, which is the case after a cold boot.
+    pub fn get() -> &'static mut Self
+    {
+        let log = unsafe { &mut *addr_of_mut!(crash_log_start) };
+        if log.magic != MAGIC || log.head as usize >= CAPACITY || log.len as usize > CAPACITY {
+            log.magic = MAGIC;
+            log.clear();
+        }
+        log
+    }
+
+    /// Prints the log left by the previous boot, if any, and clears it.
+    pub fn report()
+    {
+        let log = Self::get();
+        if log.len == 0 {
+            return;
+        }
+        Uart.write_bytes(b"Crash log from the previous boot:\r\n");
+        let start = (log.head as usize + CAPACITY - log.len as usize) % CAPACITY;
+        let end = start + log.len as usize;
+        if end > CAPACITY {
+            Uart.write_bytes(&log.data[start ..]);
+            Uart.write_bytes(&log.data[.. end - CAPACITY]);
+        } else {
+            Uart.write_bytes(&log.data[start .. end]);
+        }
+        log.clear();
+    }
+
+    /// Appends bytes, overwriting the oldest ones if the log is full.
+    ///
+    /// * `bytes`: Bytes to append.
+    fn append(&mut self, bytes: &[u8])
+    {
+        for byte in bytes {
+            self.data[self.head as usize] = *byte;
+            self.head = (self.head + 1) % CAPACITY as u32;
+        }
+        self.len = (self.len as usize + bytes.len()).min(CAPACITY) as u32;
+        cleanup_cache(self);
+    }
+
+    /// Empties the log.
+    fn clear(&mut self)
+    {
+        self.head = 0;
+        self.len = 0;
+        cleanup_cache(self);
+    }
+}
+
+impl Write for CrashLog
+{
+    fn write_str(&mut self, msg: &str) -> fmt::Result
+    {
+        self.append(msg.as_bytes());
+        Ok(())
+    }
+}
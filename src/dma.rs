@@ -1,21 +1,53 @@
 //! Direct memory access controller driver.
 //!
 //! Implements a simple DMA driver that reads cyclically from a single buffer
-//! and sends the data to a peripheral.
+//! and sends the data to a peripheral.  The channel interrupts the CPU
+//! whenever it finishes sending a control block, which wakes the task waiting
+//! for it, if any.
 
+use core::future::poll_fn;
 use core::marker::PhantomPinned;
-use core::mem::size_of_val;
-use core::sync::atomic::{fence, Ordering};
+use core::ptr::NonNull;
+use core::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
+use core::task::Poll;
+use core::time::Duration;
 
-use crate::println;
-use crate::scalloc::alloc;
+use crate::board::dma as board;
+use crate::dmabuf::DeviceBuffer;
+use crate::executor::WakerSlot;
+use crate::fdt::{Fdt, RegisterBlock};
+use crate::scalloc::{alloc, free};
+use crate::sync::SpinLock;
+use crate::timer::poll_register;
+use crate::{debug, warn};
 
-/// Base address.
-const BASE: usize = 0x1000010000;
 /// Channel 0 control and status register.
-const CH0_CS: *mut u32 = BASE as _;
+const CH0_CS: usize = 0x0;
 /// Channel 0 control block register.
-const CH0_CB: *mut u32 = (BASE + 0x4) as _;
+const CH0_CB: usize = 0x4;
+/// Maximum time to wait for the channel to stop after a reset.
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+/// Transfer information bit requesting an interrupt when the control block is
+/// done.
+const INTEN: u32 = 0x1;
+
+/// Registers.
+static BASE: RegisterBlock = RegisterBlock::new(board::BASE);
+/// Channel 0 interrupt ID.
+static IRQ: AtomicU32 = AtomicU32::new(board::IRQ);
+/// Control blocks of the active transfer.
+static CHAIN: SpinLock<Option<Chain>> = SpinLock::new(None);
+/// Number of control blocks sent, wrapping around.
+static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+/// Task waiting for a control block to be sent.
+static WAKER: WakerSlot = WakerSlot::new();
+
+/// Pair of control blocks pointing at each other.
+#[derive(Clone, Copy, Debug)]
+struct Chain(NonNull<ControlBlock>, NonNull<ControlBlock>);
+
+// The chain owns its control blocks, so it can be handed to another core.
+unsafe impl Send for Chain {}
 
 /// Control block.
 #[repr(align(32), C)]
@@ -30,9 +62,10 @@ struct ControlBlock
     dst: u32,
     /// Length in bytes.
     len: u32,
-    /// High 8 bits of source and destination addresses.
+    /// High 8 bits of source and destination addresses on 40 bit controllers,
+    /// or the 2D stride, which is left at 0, on 32 bit controllers.
     hisrcdst: u32,
-    /// Next control block address shifted 5 bits to the right.
+    /// Next control block address, encoded by the board.
     next: u32,
     /// Padding.
     _pad: [u32; 2],
@@ -40,33 +73,144 @@ struct ControlBlock
     _pin: PhantomPinned,
 }
 
-// Sets up a DMA channel to repeatedly send data to a peripheral.
-pub unsafe fn setup_sender<T>(src: &[T], dst: *mut u32, dreq: u32)
+// Switches to the DMA controller described by the device tree, taking its
+// registers and the interrupt of channel 0 from it.
+//
+// * `fdt`: Device tree.
+//
+// Must be called before any transfer is set up.
+pub fn probe(fdt: &Fdt)
+{
+    let Some(node) = board::COMPATIBLES.iter()
+                                       .find_map(|compatible| fdt.find_compatible(compatible))
+    else {
+        warn!("No DMA controller in the device tree");
+        return;
+    };
+    if let Some((addr, len)) = node.reg(0) {
+        BASE.set(addr, len);
+    }
+    if let Some(irq) = node.interrupt(0) {
+        IRQ.store(irq, Ordering::Relaxed);
+    }
+    debug!("Found DMA controller at 0x{:X} with IRQ {}", BASE.phys(0), irq());
+}
+
+// Returns the interrupt ID of channel 0 at the GIC.
+pub fn irq() -> u32
 {
+    IRQ.load(Ordering::Relaxed)
+}
+
+// Sets up a DMA channel to repeatedly send data to a peripheral, stopping any
+// previous transfer.
+pub unsafe fn setup_sender<T>(src: &DeviceBuffer<[T]>, dst: *mut u32, dreq: u32)
+{
+    let mut chain = CHAIN.lock_irq();
+    stop(&mut chain);
     let dreq = dreq & 0x1F;
-    let cb0 = alloc::<ControlBlock>();
-    let cb1 = alloc::<ControlBlock>();
-    *cb0 = ControlBlock { ti: 0xF348 | (dreq << 16),
-                          src: src.as_ptr() as usize as u32,
-                          dst: (dst as usize & 0xFFFFFFFF) as u32,
-                          len: size_of_val(src) as u32 / 2,
-                          hisrcdst: ((dst as usize >> 24) as u32 & 0xFF00)
-                                    | (src.as_ptr() as usize >> 32) as u32 & 0xFF,
-                          next: (cb1 as usize >> 5) as u32,
+    let cb0 = alloc::<ControlBlock>().expect("Out of uncached memory for DMA control blocks");
+    let cb1 = alloc::<ControlBlock>().expect("Out of uncached memory for DMA control blocks");
+    *chain = Some(Chain(cb0, cb1));
+    let cb0 = cb0.as_ptr();
+    let cb1 = cb1.as_ptr();
+    let src_addr = board::bus_address(src.addr());
+    let dst_addr = board::bus_address(dst as usize);
+    *cb0 = ControlBlock { ti: 0xF348 | INTEN | (dreq << 16),
+                          src: src_addr as u32,
+                          dst: dst_addr as u32,
+                          len: src.size() as u32 / 2,
+                          hisrcdst: ((dst_addr >> 24) as u32 & 0xFF00) | (src_addr >> 32) as u32 & 0xFF,
+                          next: board::control_block_address(cb1 as usize),
                           _pad: [0; 2],
                           _pin: PhantomPinned };
-    *cb1 = ControlBlock { ti: 0xF348 | (dreq << 16),
-                          src: src.as_ptr() as usize as u32 + (*cb0).len,
-                          dst: (dst as usize & 0xFFFFFFFF) as u32,
-                          len: size_of_val(src) as u32 / 2,
-                          hisrcdst: ((dst as usize >> 24) as u32 & 0xFF00)
-                                    | (src.as_ptr() as usize >> 32) as u32 & 0xFF,
-                          next: (cb0 as usize >> 5) as u32,
+    *cb1 = ControlBlock { ti: 0xF348 | INTEN | (dreq << 16),
+                          src: src_addr as u32 + (*cb0).len,
+                          dst: dst_addr as u32,
+                          len: src.size() as u32 / 2,
+                          hisrcdst: ((dst_addr >> 24) as u32 & 0xFF00) | (src_addr >> 32) as u32 & 0xFF,
+                          next: board::control_block_address(cb0 as usize),
                           _pad: [0; 2],
                           _pin: PhantomPinned };
     fence(Ordering::Release);
-    CH0_CS.write_volatile(0x80000000);
-    CH0_CB.write_volatile(cb0 as usize as u32 >> 5);
-    CH0_CS.write_volatile(0x20A50007);
-    println!("Initialized DMA channel #0");
+    BASE.reg(CH0_CS).write_volatile(0x80000000);
+    BASE.reg(CH0_CB)
+        .write_volatile(board::control_block_address(cb0 as usize));
+    BASE.reg(CH0_CS).write_volatile(0x20A50007);
+    debug!("Initialized DMA channel #0 with control blocks at 0x{:X} and 0x{:X}",
+           cb0 as usize, cb1 as usize);
+}
+
+// Stops the DMA channel and frees the control blocks of the transfer it was
+// performing, if any.
+pub unsafe fn stop_sender()
+{
+    stop(&mut CHAIN.lock_irq());
+}
+
+// Returns the index of the control block being processed by the DMA channel,
+// which tells which half of the source buffer is being sent, or `None` if the
+// channel is stopped.
+pub fn current_block() -> Option<usize>
+{
+    let Chain(cb0, _) = (*CHAIN.lock_irq())?;
+    let cb = unsafe { BASE.reg(CH0_CB).read_volatile() };
+    Some(if cb == board::control_block_address(cb0.as_ptr() as usize) {
+             0
+         } else {
+             1
+         })
+}
+
+// Waits for the DMA channel to finish sending a control block without blocking
+// other tasks, which includes waiting for a transfer to be set up if the
+// channel is stopped.
+//
+// Returns the index of the control block being processed next, or `None` if
+// the channel was stopped in the meantime.
+pub async fn next_block() -> Option<usize>
+{
+    let completed = COMPLETED.load(Ordering::Relaxed);
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+        if COMPLETED.load(Ordering::Relaxed) == completed {
+            return Poll::Pending;
+        }
+        Poll::Ready(current_block())
+    }).await
+}
+
+// Acknowledges the interrupt raised by the DMA channel when it finishes sending
+// a control block and wakes the task waiting for it.
+pub fn handle_interrupt()
+{
+    // IRQs are already masked in interrupt handlers, and holding the lock keeps
+    // the channel from being reset while its status is written back.
+    let chain = CHAIN.lock();
+    unsafe {
+        // The status bits are cleared by writing them back, which leaves the
+        // channel active.
+        let cs = BASE.reg(CH0_CS).read_volatile();
+        BASE.reg(CH0_CS).write_volatile(cs);
+    }
+    drop(chain);
+    COMPLETED.fetch_add(1, Ordering::Relaxed);
+    WAKER.wake();
+}
+
+// Stops the DMA channel and frees the control blocks of a transfer, if any.
+unsafe fn stop(chain: &mut Option<Chain>)
+{
+    let Some(Chain(cb0, cb1)) = chain.take() else {
+        return;
+    };
+    // Resetting the channel aborts the transfer.
+    BASE.reg(CH0_CS).write_volatile(0x80000000);
+    // The control blocks can't be freed while the channel might still read them.
+    if let Err(err) = poll_register("CH0_CS", BASE.reg(CH0_CS), RESET_TIMEOUT, |cs| cs & 0x1 == 0) {
+        panic!("DMA channel #0 failed to stop: {err}");
+    }
+    free(cb0);
+    free(cb1);
+    debug!("Stopped DMA channel #0");
 }
@@ -0,0 +1,210 @@
+//! DMA coherent buffers.
+//!
+//! Buffers live in cached memory so that the CPU can access them at full
+//! speed, and ownership is explicitly transferred to and from peripherals,
+//! performing the cache maintenance required at each transition.  Buffers
+//! occupy whole cache lines so that maintenance never affects unrelated data.
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use core::alloc::Layout;
+use core::arch::asm;
+use core::mem::size_of_val;
+use core::ops::{Deref, DerefMut, Range};
+use core::ptr::{slice_from_raw_parts_mut, NonNull};
+use core::sync::atomic::{fence, Ordering};
+
+/// Cache line size.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Buffer owned by the CPU, which can be freely accessed.
+pub struct DmaBuffer<T: ?Sized>
+{
+    /// Pointer to the buffer's contents.
+    ptr: NonNull<T>,
+    /// Size of the contents in bytes.
+    size: usize,
+    /// Layout of the allocation, padded to whole cache lines.
+    layout: Layout,
+}
+
+// The buffer owns its contents, so it can be handed to another core.
+unsafe impl<T: ?Sized + Send> Send for DmaBuffer<T> {}
+
+/// Buffer owned by a peripheral, which the CPU must not access.
+pub struct DeviceBuffer<T: ?Sized>
+{
+    /// Buffer being lent to the peripheral.
+    buf: DmaBuffer<T>,
+}
+
+impl<T> DmaBuffer<T>
+{
+    /// Moves a value into a newly allocated buffer.
+    ///
+    /// * `val`: Value to move.
+    ///
+    /// Returns the newly created buffer.
+    pub fn new(val: T) -> Self
+    {
+        let (ptr, size, layout) = Self::alloc_raw(Layout::new::<T>());
+        let ptr = ptr.cast::<T>();
+        unsafe { ptr.as_ptr().write(val) };
+        Self { ptr, size, layout }
+    }
+}
+
+impl<T: Default> DmaBuffer<[T]>
+{
+    /// Allocates a buffer for a slice whose length is only known at runtime,
+    /// initializing every element with its default value.
+    ///
+    /// * `len`: Number of elements in the slice.
+    ///
+    /// Returns the newly created buffer.
+    ///
+    /// Panics if the size of the slice overflows.
+    #[track_caller]
+    pub fn new_slice(len: usize) -> Self
+    {
+        let layout = Layout::array::<T>(len).expect("DMA buffer is too large");
+        let (ptr, size, layout) = Self::alloc_raw(layout);
+        let ptr = ptr.cast::<T>();
+        for idx in 0 .. len {
+            unsafe { ptr.as_ptr().add(idx).write(T::default()) };
+        }
+        let ptr = unsafe { NonNull::new_unchecked(slice_from_raw_parts_mut(ptr.as_ptr(), len)) };
+        Self { ptr, size, layout }
+    }
+}
+
+impl<T: ?Sized> DmaBuffer<T>
+{
+    /// Hands this buffer over to a peripheral, writing its contents out to main
+    /// memory and purging them from cache so that neither the peripheral reads
+    /// stale data nor dirty cache lines are later written over data the
+    /// peripheral stores.
+    ///
+    /// Returns the buffer owned by the peripheral.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_device(self) -> DeviceBuffer<T>
+    {
+        let start = self.ptr.as_ptr() as *mut u8 as usize;
+        fence(Ordering::Release);
+        for addr in (start .. start + self.layout.size()).step_by(CACHE_LINE_SIZE) {
+            unsafe { asm!("dc civac, {addr}", addr = in (reg) addr, options (preserves_flags)) };
+        }
+        unsafe { asm!("dsb sy", options(nomem, nostack, preserves_flags)) };
+        DeviceBuffer { buf: self }
+    }
+
+    /// Allocates cache line aligned memory from the heap.
+    ///
+    /// * `layout`: Layout of the contents.
+    ///
+    /// Returns a pointer to the uninitialized memory, the size of the
+    /// contents, and the padded layout of the allocation.
+    fn alloc_raw(layout: Layout) -> (NonNull<u8>, usize, Layout)
+    {
+        let size = layout.size();
+        let layout =
+            Layout::from_size_align(size.max(1), layout.align().max(CACHE_LINE_SIZE)).expect("DMA buffer is too large")
+                                                                                     .pad_to_align();
+        let ptr = unsafe { alloc(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            handle_alloc_error(layout)
+        };
+        (ptr, size, layout)
+    }
+}
+
+impl<T: ?Sized> DeviceBuffer<T>
+{
+    /// Returns the address of this buffer as seen by peripherals.
+    pub fn addr(&self) -> usize
+    {
+        self.buf.ptr.as_ptr() as *mut u8 as usize
+    }
+
+    /// Returns the size of the contents of this buffer in bytes.
+    pub fn size(&self) -> usize
+    {
+        self.buf.size
+    }
+
+    /// Takes ownership of this buffer back from the peripheral, purging any
+    /// data that may have been speculatively loaded into cache in the meantime
+    /// so that the CPU observes everything the peripheral stored.
+    ///
+    /// Returns the buffer owned by the CPU.
+    ///
+    /// The peripheral must have finished accessing the buffer before calling
+    /// this function.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_device(self) -> DmaBuffer<T>
+    {
+        let start = self.addr();
+        for addr in (start .. start + self.buf.layout.size()).step_by(CACHE_LINE_SIZE) {
+            unsafe { asm!("dc ivac, {addr}", addr = in (reg) addr, options (preserves_flags)) };
+        }
+        unsafe { asm!("dsb sy", options(nomem, nostack, preserves_flags)) };
+        fence(Ordering::Acquire);
+        self.buf
+    }
+}
+
+impl<T> DeviceBuffer<[T]>
+{
+    /// Lets the CPU update part of this buffer while a peripheral owns it,
+    /// writing the changes out to main memory afterwards.
+    ///
+    /// * `range`: Range of elements to update.
+    /// * `update`: Function that performs the update.
+    ///
+    /// Panics if the range is out of bounds.
+    ///
+    /// # Safety
+    ///
+    /// The peripheral must not access the range while it is being updated.
+    #[track_caller]
+    pub unsafe fn update<F: FnOnce(&mut [T])>(&mut self, range: Range<usize>, update: F)
+    {
+        let slice = &mut self.buf.ptr.as_mut()[range];
+        update(&mut *slice);
+        let start = slice.as_ptr() as usize & !(CACHE_LINE_SIZE - 1);
+        let end = slice.as_ptr() as usize + size_of_val(slice);
+        fence(Ordering::Release);
+        for addr in (start .. end).step_by(CACHE_LINE_SIZE) {
+            asm!("dc cvac, {addr}", addr = in (reg) addr, options (preserves_flags));
+        }
+        asm!("dsb sy", options(nomem, nostack, preserves_flags));
+    }
+}
+
+impl<T: ?Sized> Deref for DmaBuffer<T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for DmaBuffer<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for DmaBuffer<T>
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            self.ptr.as_ptr().drop_in_place();
+            dealloc(self.ptr.as_ptr() as *mut u8, self.layout);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! CPU exception reporting.
+//!
+//! The vectors in `boot.s` save every register of the interrupted code in a
+//! trap frame.  Faults are reported by panicking with the exception decoded
+//! from the syndrome register and the saved registers, and panics are reported
+//! with a symbolized backtrace obtained by walking the frame pointer chain,
+//! starting at the faulting instruction for faults.
+//!
+//! Stack overflows are reported as such, whether they're detected by the
+//! vectors, which move the trap frame to a separate stack when it lands on the
+//! guard page of the exception stack, or by a data abort on one of the guard
+//! pages.
+
+use core::arch::asm;
+use core::fmt::{self, Display, Formatter, Write};
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{smp, symbols};
+
+/// Maximum number of return addresses in a backtrace.
+const MAX_FRAMES: usize = 32;
+/// Exception classes of instruction aborts.
+const INSTRUCTION_ABORTS: [usize; 2] = [0x20, 0x21];
+/// Exception classes of data aborts.
+const DATA_ABORTS: [usize; 2] = [0x24, 0x25];
+/// Exception classes for which the fault address register is set.
+const ADDRESS_CLASSES: [usize; 7] = [0x20, 0x21, 0x22, 0x24, 0x25, 0x34, 0x35];
+/// FAR not valid bit of the abort syndromes.
+const FNV: usize = 0x400;
+/// Instruction syndrome valid bit of the data abort syndrome.
+const ISV: usize = 0x1000000;
+/// Write not read bit of the data abort syndrome.
+const WNR: usize = 0x40;
+/// Flag added to the vector entry kind when the trap frame landed on the guard
+/// page of the exception stack.
+const STACK_OVERFLOW: usize = 0x10;
+
+/// Program counter and frame pointer of the code whose fault is being reported
+/// by each core, with a null program counter if none is.
+static FAULTS: [(AtomicUsize, AtomicUsize); smp::CORES] =
+    [const { (AtomicUsize::new(0), AtomicUsize::new(0)) }; smp::CORES];
+
+extern "C" {
+    /// Start of the BSS, which holds the stacks, defined by the linker script.
+    static bss_start: u8;
+    /// End of the BSS, defined by the linker script.
+    static bss_end: u8;
+}
+
+/// Registers of the interrupted code saved by the vectors, laid out as
+/// expected by `boot.s`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame
+{
+    /// General purpose registers X0 through X30.
+    pub regs: [usize; 31],
+    /// Exception link register, which holds the address to return to.
+    pub elr: usize,
+    /// Saved program status register.
+    pub spsr: usize,
+    /// Padding to keep the vector registers aligned.
+    pad: usize,
+    /// SIMD and floating point registers Q0 through Q31.
+    pub vregs: [u128; 32],
+    /// Floating point control register.
+    pub fpcr: usize,
+    /// Floating point status register.
+    pub fpsr: usize,
+}
+
+const _: () = assert!(size_of::<TrapFrame>() == 0x320);
+
+/// Iterator over the return addresses found by walking a chain of frame
+/// records.
+pub struct Backtrace
+{
+    /// Address of the next frame record, or zero at the end of the chain.
+    fp: usize,
+    /// Number of return addresses produced so far.
+    frames: usize,
+}
+
+/// Description of a fault.
+struct Report<'a>
+{
+    /// Vector entry kind.
+    kind: usize,
+    /// Exception level at which the exception was taken.
+    level: usize,
+    /// Exception syndrome register.
+    esr: usize,
+    /// Fault address register.
+    far: usize,
+    /// Name of the stack that overflowed and the core that it belongs to, if
+    /// any.
+    overflow: Option<(&'static str, usize)>,
+    /// Registers of the interrupted code.
+    frame: &'a TrapFrame,
+}
+
+impl Backtrace
+{
+    /// Creates an iterator that walks a frame record chain.
+    ///
+    /// * `fp`: Address of the innermost frame record.
+    ///
+    /// Returns the newly created iterator.
+    pub fn new(fp: usize) -> Self
+    {
+        Self { fp, frames: 0 }
+    }
+}
+
+impl Iterator for Backtrace
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize>
+    {
+        let start = addr_of!(bss_start) as usize;
+        let end = addr_of!(bss_end) as usize;
+        // Stop at anything that doesn't look like a frame record on one of the
+        // stacks, since the chain might be corrupt.
+        if self.frames == MAX_FRAMES || self.fp & 0x7 != 0 || self.fp < start || self.fp + 16 > end {
+            return None;
+        }
+        let record = self.fp as *const usize;
+        let (next, ret) = unsafe { (record.read(), record.add(1).read()) };
+        // Stacks grow downwards, so callers' records are always further up.
+        self.fp = if next > self.fp { next } else { 0 };
+        self.frames += 1;
+        (ret != 0).then_some(ret)
+    }
+}
+
+impl Display for Report<'_>
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        let origin = match self.kind & 0xC {
+            0x0 => "current level with SP0",
+            0x4 => "current level with SPx",
+            0x8 => "lower level in AArch64",
+            _ => "lower level in AArch32",
+        };
+        let kind = match self.kind & 0x3 {
+            0 => "Synchronous exception",
+            1 => "IRQ",
+            2 => "FIQ",
+            _ => "SError",
+        };
+        if let Some((stack, core)) = self.overflow {
+            write!(fmt, "Stack overflow on the {stack} stack of core {core}\r\n")?;
+        }
+        write!(fmt, "{kind} from {origin} taken at EL{}\r\n", self.level)?;
+        // Only synchronous exceptions and SErrors have a syndrome.
+        if matches!(self.kind & 0x3, 0 | 3) {
+            let class = self.esr >> 26 & 0x3F;
+            write!(fmt, "ESR: 0x{:08X} ({}", self.esr, class_name(class))?;
+            let iss = self.esr & 0x1FFFFFF;
+            if INSTRUCTION_ABORTS.contains(&class) || DATA_ABORTS.contains(&class) {
+                write!(fmt, ": {}", FaultStatus(iss & 0x3F))?;
+            }
+            if DATA_ABORTS.contains(&class) {
+                fmt.write_str(if iss & WNR != 0 { " on write" } else { " on read" })?;
+                if iss & ISV != 0 {
+                    write!(fmt, " of {} bytes with x{}", 1 << (iss >> 22 & 0x3), iss >> 16 & 0x1F)?;
+                }
+            }
+            fmt.write_str(")\r\n")?;
+            let far_valid = !(INSTRUCTION_ABORTS.contains(&class) || DATA_ABORTS.contains(&class)) || iss & FNV == 0;
+            if ADDRESS_CLASSES.contains(&class) && far_valid {
+                write!(fmt, "FAR: 0x{:016X}\r\n", self.far)?;
+            }
+        }
+        write!(fmt,
+               "ELR: 0x{:016X}  SPSR: 0x{:08X}\r\n",
+               self.frame.elr, self.frame.spsr)?;
+        for (idx, reg) in self.frame.regs.iter().enumerate() {
+            let sep = match idx {
+                30 => "",
+                _ if idx % 4 == 3 => "\r\n",
+                _ => "  ",
+            };
+            let pad = if idx < 10 { " " } else { "" };
+            write!(fmt, "{pad}x{idx}: 0x{reg:016X}{sep}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a symbolized backtrace of the code whose fault is being reported,
+/// or of the caller if no fault is being reported.
+///
+/// * `out`: Destination of the backtrace.
+pub fn write_backtrace(out: &mut impl Write) -> fmt::Result
+{
+    let (pc, fp) = &FAULTS[smp::core_id()];
+    let (pc, fp) = match pc.load(Ordering::Relaxed) {
+        0 => (None, current_frame()),
+        pc => (Some(pc), fp.load(Ordering::Relaxed)),
+    };
+    out.write_str("Backtrace:")?;
+    // Return addresses point past the calls, which might be the last
+    // instructions of their functions.
+    let frames = pc.map(|pc| (pc, pc))
+                   .into_iter()
+                   .chain(Backtrace::new(fp).map(|ret| (ret, ret.wrapping_sub(4))));
+    for (idx, (addr, site)) in frames.enumerate() {
+        write!(out, "\r\n  #{idx:<2} 0x{addr:016X}")?;
+        if let Some((name, start)) = symbols::lookup(site) {
+            write!(out, " {name}+0x{:X}", addr - start)?;
+        }
+    }
+    out.write_str("\r\n")
+}
+
+/// Returns the address of the caller's frame record.
+#[inline(always)]
+fn current_frame() -> usize
+{
+    let fp: usize;
+    unsafe {
+        asm!(
+            "mov {fp}, x29",
+            fp = out (reg) fp,
+            options (nomem, nostack, preserves_flags));
+    }
+    fp
+}
+
+/// Returns the stack whose overflow caused a fault, if any.
+///
+/// * `kind`: Vector entry kind.
+/// * `esr`: Exception syndrome register.
+/// * `far`: Fault address register.
+///
+/// Returns the name of the stack and the core that it belongs to.
+fn overflowed_stack(kind: usize, esr: usize, far: usize) -> Option<(&'static str, usize)>
+{
+    if kind & STACK_OVERFLOW != 0 {
+        return Some(("exception", smp::core_id()));
+    }
+    let class = esr >> 26 & 0x3F;
+    if !DATA_ABORTS.contains(&class) || esr & FNV != 0 {
+        return None;
+    }
+    (0 .. smp::CORES).find_map(|core| {
+                         let [eln_guard, el0_guard] = smp::guard_pages(core);
+                         if (eln_guard .. eln_guard + smp::GUARD_SIZE).contains(&far) {
+                             Some(("exception", core))
+                         } else if (el0_guard .. el0_guard + smp::GUARD_SIZE).contains(&far) {
+                             Some(("main", core))
+                         } else {
+                             None
+                         }
+                     })
+}
+
+/// Fault status code of an abort syndrome.
+struct FaultStatus(usize);
+
+impl Display for FaultStatus
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        let level = self.0 & 0x3;
+        match self.0 {
+            0x00 ..= 0x03 => write!(fmt, "Address size fault at level {level}"),
+            0x04 ..= 0x07 => write!(fmt, "Translation fault at level {level}"),
+            0x08 ..= 0x0B => write!(fmt, "Access flag fault at level {level}"),
+            0x0C ..= 0x0F => write!(fmt, "Permission fault at level {level}"),
+            0x10 => fmt.write_str("Synchronous external abort"),
+            0x14 ..= 0x17 => write!(fmt, "Synchronous external abort on table walk at level {level}"),
+            0x21 => fmt.write_str("Alignment fault"),
+            0x30 => fmt.write_str("TLB conflict abort"),
+            code => write!(fmt, "Fault status 0x{code:02X}"),
+        }
+    }
+}
+
+/// Returns a description of an exception class.
+///
+/// * `class`: Exception class field of the syndrome register.
+fn class_name(class: usize) -> &'static str
+{
+    match class {
+        0x00 => "Unknown reason",
+        0x01 => "Trapped WFI or WFE",
+        0x07 => "Trapped SIMD or floating point access",
+        0x0E => "Illegal execution state",
+        0x15 => "SVC instruction",
+        0x16 => "HVC instruction",
+        0x17 => "SMC instruction",
+        0x18 => "Trapped system register access",
+        0x20 => "Instruction abort from a lower level",
+        0x21 => "Instruction abort",
+        0x22 => "PC alignment fault",
+        0x24 => "Data abort from a lower level",
+        0x25 => "Data abort",
+        0x26 => "SP alignment fault",
+        0x2C => "Trapped floating point exception",
+        0x2F => "SError interrupt",
+        0x30 | 0x31 => "Breakpoint",
+        0x32 | 0x33 => "Software step",
+        0x34 | 0x35 => "Watchpoint",
+        0x3C => "BRK instruction",
+        _ => "Unrecognized exception class",
+    }
+}
+
+/// Panics with diagnostic information about a fault, called by the vectors.
+///
+/// * `kind`: Vector entry kind, with the origin in bits 2 and 3, the type of
+///   exception in bits 0 and 1, and bit 4 set if the exception stack
+///   overflowed.
+/// * `frame`: Registers of the interrupted code.
+#[no_mangle]
+extern "C" fn fault(kind: usize, frame: &TrapFrame) -> !
+{
+    let level: usize;
+    let esr: usize;
+    let far: usize;
+    unsafe {
+        asm!(
+            "mrs {el}, currentel",
+            "lsr {el}, {el}, #2",
+            el = out (reg) level,
+            options (nomem, nostack, preserves_flags));
+        match level {
+            2 => asm!(
+                    "mrs {esr}, esr_el2",
+                    "mrs {far}, far_el2",
+                    esr = out (reg) esr,
+                    far = out (reg) far,
+                    options (nomem, nostack, preserves_flags)),
+            1 => asm!(
+                    "mrs {esr}, esr_el1",
+                    "mrs {far}, far_el1",
+                    esr = out (reg) esr,
+                    far = out (reg) far,
+                    options (nomem, nostack, preserves_flags)),
+            _ => panic!("Exception caught at unsupported level {level}"),
+        }
+    };
+    let (pc, fp) = &FAULTS[smp::core_id()];
+    fp.store(frame.regs[29], Ordering::Relaxed);
+    pc.store(frame.elr, Ordering::Relaxed);
+    let overflow = overflowed_stack(kind, esr, far);
+    panic!("{}",
+           Report { kind,
+                    level,
+                    esr,
+                    far,
+                    overflow,
+                    frame });
+}
@@ -0,0 +1,301 @@
+//! Cooperative async executor.
+//!
+//! Every core runs its own tasks, which are polled by [`block_on`] whenever
+//! they're woken, either by interrupt handlers through a [`WakerSlot`], by
+//! other tasks, or by the timer through [`sleep`].  While no task is ready the
+//! core waits for an event, which is signaled by interrupts, by wakers running
+//! on any core, and by the generic timer's event stream, which also lets the
+//! executor expire timers without a timer interrupt.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::convert::Infallible;
+use core::future::{pending, poll_fn, Future};
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use crate::smp::{self, CORES};
+use crate::sync::SpinLock;
+use crate::timer::Instant;
+
+/// Bit of the system counter whose transitions generate timer events, which
+/// at the 54MHz of the Raspberry Pi 4 and 5 happen about every 1.2ms.
+const EVENT_STREAM_BIT: u64 = 15;
+/// Event stream enable bit of the timer kernel control register.
+const EVNTEN: u64 = 0x4;
+/// Event stream trigger bit selection field of the timer kernel control
+/// register, along with the transition direction bit.
+const EVNTI_MASK: u64 = 0xF8;
+
+/// Executors indexed by core.
+static EXECUTORS: [Executor; CORES] = [const { Executor::new() }; CORES];
+
+/// Future of a spawned task.
+type TaskFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Tasks and timers of a core.
+struct Executor
+{
+    /// Spawned tasks that haven't completed yet.
+    tasks: SpinLock<Vec<Arc<Task>>>,
+    /// Deadlines along with the wakers of the tasks waiting for them.
+    timers: SpinLock<Vec<(Instant, Waker)>>,
+    /// Whether [`block_on`] is running on the core.
+    running: AtomicBool,
+}
+
+/// Spawned task, which is also its own waker.
+struct Task
+{
+    /// Future driving the task, or `None` once it completes.
+    future: SpinLock<Option<TaskFuture>>,
+    /// Whether the task needs to be polled.
+    woken: AtomicBool,
+}
+
+/// Waker of a task waiting for an event signaled by an interrupt handler.
+pub struct WakerSlot
+{
+    /// Waker of the waiting task, if any.
+    waker: SpinLock<Option<Waker>>,
+}
+
+/// Spawns a task on the current core, which runs whenever the core runs the
+/// executor.
+///
+/// * `future`: Future driving the task.
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F)
+{
+    let task = Arc::new(Task::new(Some(Box::pin(future))));
+    EXECUTORS[smp::core_id()].tasks.lock_irq().push(task);
+}
+
+/// Runs the tasks of the current core until a future completes.
+///
+/// * `future`: Future to wait for.
+///
+/// Returns the output of the future.
+///
+/// Panics if called from a task, which would poll that task recursively.
+#[track_caller]
+pub fn block_on<F: Future>(future: F) -> F::Output
+{
+    let executor = &EXECUTORS[smp::core_id()];
+    // Only the executor's own core ever touches the flag.
+    assert!(!executor.running.swap(true, Ordering::Relaxed),
+            "Attempted to block on a future from a task");
+    enable_event_stream();
+    let root = Arc::new(Task::new(None));
+    let waker = Waker::from(root.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if root.woken.swap(false, Ordering::Acquire) {
+            if let Poll::Ready(res) = future.as_mut().poll(&mut cx) {
+                executor.running.store(false, Ordering::Relaxed);
+                return res;
+            }
+        }
+        executor.expire_timers();
+        if !executor.poll_tasks() && !root.woken.load(Ordering::Acquire) {
+            // Wakers signal an event after waking a task, and so do returns from
+            // interrupt handlers, so nothing is missed by waiting here.
+            unsafe { asm!("wfe", options(nomem, nostack, preserves_flags)) };
+        }
+    }
+}
+
+/// Runs the tasks of the current core forever.
+pub fn run() -> !
+{
+    match block_on(pending::<Infallible>()) {}
+}
+
+/// Waits for some time without blocking other tasks.
+///
+/// * `duration`: Time to wait, which is rounded up to the period of the timer
+///   event stream.
+pub async fn sleep(duration: Duration)
+{
+    sleep_until(Instant::now() + duration).await
+}
+
+/// Waits for a deadline without blocking other tasks.
+///
+/// * `deadline`: Time to wait for, which is rounded up to the period of the
+///   timer event stream.
+pub async fn sleep_until(deadline: Instant)
+{
+    let mut registered = false;
+    poll_fn(|cx| {
+        if Instant::now() >= deadline {
+            return Poll::Ready(());
+        }
+        if !registered {
+            EXECUTORS[smp::core_id()].timers
+                                     .lock_irq()
+                                     .push((deadline, cx.waker().clone()));
+            registered = true;
+        }
+        Poll::Pending
+    }).await
+}
+
+/// Lets the other tasks that are ready run before resuming.
+pub async fn yield_now()
+{
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }).await
+}
+
+/// Enables the timer event stream on the current core.
+fn enable_event_stream()
+{
+    unsafe {
+        asm!(
+            "mrs {ctl}, cntkctl_el1",
+            "and {ctl}, {ctl}, {mask}",
+            "orr {ctl}, {ctl}, {stream}",
+            "msr cntkctl_el1, {ctl}",
+            ctl = out (reg) _,
+            mask = in (reg) !EVNTI_MASK,
+            stream = in (reg) EVNTEN | EVENT_STREAM_BIT << 4,
+            options (nomem, nostack, preserves_flags));
+    }
+}
+
+impl Executor
+{
+    /// Creates and initializes a new executor with no tasks.
+    ///
+    /// Returns the newly created executor.
+    const fn new() -> Self
+    {
+        Self { tasks: SpinLock::new(Vec::new()),
+               timers: SpinLock::new(Vec::new()),
+               running: AtomicBool::new(false) }
+    }
+
+    /// Polls the tasks that were woken, dropping the ones that complete.
+    ///
+    /// Returns whether any task was polled.
+    fn poll_tasks(&self) -> bool
+    {
+        // Tasks can spawn other tasks, so the list can't be locked while polling.
+        let woken = self.tasks
+                        .lock_irq()
+                        .iter()
+                        .filter(|task| task.woken.swap(false, Ordering::Acquire))
+                        .cloned()
+                        .collect::<Vec<_>>();
+        for task in woken.iter() {
+            let waker = Waker::from(task.clone());
+            let mut cx = Context::from_waker(&waker);
+            let mut future = task.future.lock();
+            if future.as_mut()
+                     .is_some_and(|future| future.as_mut().poll(&mut cx).is_ready())
+            {
+                *future = None;
+                drop(future);
+                self.tasks.lock_irq().retain(|other| !Arc::ptr_eq(other, task));
+            }
+        }
+        !woken.is_empty()
+    }
+
+    /// Wakes the tasks whose deadlines have passed.
+    fn expire_timers(&self)
+    {
+        let now = Instant::now();
+        self.timers.lock_irq().retain(|(deadline, waker)| {
+                                  if *deadline > now {
+                                      return true;
+                                  }
+                                  waker.wake_by_ref();
+                                  false
+                              });
+    }
+}
+
+impl Task
+{
+    /// Creates and initializes a new task that starts out woken.
+    ///
+    /// * `future`: Future driving the task.
+    ///
+    /// Returns the newly created task.
+    fn new(future: Option<TaskFuture>) -> Self
+    {
+        Self { future: SpinLock::new(future),
+               woken: AtomicBool::new(true) }
+    }
+}
+
+impl Wake for Task
+{
+    fn wake(self: Arc<Self>)
+    {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>)
+    {
+        self.woken.store(true, Ordering::Release);
+        // Ends the wait of the core running the task.
+        unsafe { asm!("dsb ish", "sev", options(nostack, preserves_flags)) };
+    }
+}
+
+impl WakerSlot
+{
+    /// Creates and initializes a new slot with no waiting task.
+    ///
+    /// Returns the newly created slot.
+    pub const fn new() -> Self
+    {
+        Self { waker: SpinLock::new(None) }
+    }
+
+    /// Registers the task to wake on the next event, replacing any other task.
+    ///
+    /// * `waker`: Waker of the task.
+    ///
+    /// The condition that the task is waiting for must be checked after
+    /// registering, so that an event that happens in between isn't missed.
+    pub fn register(&self, waker: &Waker)
+    {
+        let mut slot = self.waker.lock_irq();
+        if !slot.as_ref().is_some_and(|other| other.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    /// Wakes the registered task, if any.
+    pub fn wake(&self)
+    {
+        let waker = self.waker.lock_irq().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerSlot
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
@@ -0,0 +1,687 @@
+//! Flattened device tree parser.
+//!
+//! The firmware passes the address of a device tree blob describing the board
+//! to the kernel in x0, which [`init`] copies to the heap so that the drivers
+//! can look up their devices by compatible string and find out where their
+//! registers are, which interrupts they raise, and how fast their clocks run.
+//!
+//! Drivers keep their registers in a [`RegisterBlock`], which starts out at the
+//! address known for the board, so everything keeps working with the built in
+//! addresses if the firmware doesn't pass a device tree.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::fmt::{self, Display, Formatter};
+use core::iter::from_fn;
+use core::ops::Range;
+use core::ptr::copy_nonoverlapping;
+use core::str::from_utf8;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::Once;
+use crate::{debug, mmu, warn};
+
+/// Magic number at the start of a device tree blob.
+const MAGIC: u32 = 0xD00DFEED;
+/// Size of the blob header.
+const HEADER_LEN: usize = 40;
+/// Oldest blob version with all the header fields used by the parser.
+const MIN_VERSION: u32 = 17;
+/// Blob version whose layout the parser understands.
+const VERSION: u32 = 17;
+/// Largest blob that the kernel is willing to copy.
+const MAX_LEN: usize = 1 << 20;
+/// Structure block token starting a node.
+const BEGIN_NODE: u32 = 0x1;
+/// Structure block token ending a node.
+const END_NODE: u32 = 0x2;
+/// Structure block token starting a property.
+const PROP: u32 = 0x3;
+/// Structure block token to ignore.
+const NOP: u32 = 0x4;
+/// Structure block token ending the tree.
+const END: u32 = 0x9;
+/// Maximum depth of a node in the tree.
+const MAX_DEPTH: usize = 16;
+/// Number of cells in an address when a bus doesn't specify it.
+const DEFAULT_ADDRESS_CELLS: usize = 2;
+/// Number of cells in a size when a bus doesn't specify it.
+const DEFAULT_SIZE_CELLS: usize = 1;
+/// Compatible string of the interrupt controller whose interrupts the kernel
+/// can handle.
+const GIC_COMPATIBLE: &str = "arm,gic-400";
+/// Interrupt specifier type of a shared peripheral interrupt.
+const GIC_SPI: u32 = 0;
+/// Interrupt specifier type of a private peripheral interrupt.
+const GIC_PPI: u32 = 1;
+/// Interrupt ID of the first shared peripheral interrupt.
+const SPI_BASE: u32 = 32;
+/// Interrupt ID of the first private peripheral interrupt.
+const PPI_BASE: u32 = 16;
+/// Compatible string of clocks running at a fixed rate.
+const FIXED_CLOCK_COMPATIBLE: &str = "fixed-clock";
+
+/// Device tree passed by the firmware, or `None` if it's missing or invalid.
+static FDT: Once<Option<Fdt>> = Once::new();
+
+/// Parsed device tree blob.
+pub struct Fdt
+{
+    /// Physical address of the original blob.
+    addr: usize,
+    /// Copy of the blob.
+    blob: Box<[u8]>,
+    /// Range of the structure block in the blob.
+    structs: Range<usize>,
+    /// Range of the strings block in the blob.
+    strings: Range<usize>,
+}
+
+/// Node of the device tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a>
+{
+    /// Device tree containing the node.
+    fdt: &'a Fdt,
+    /// Offset of the token starting the node in the blob.
+    offset: usize,
+    /// Offsets of the tokens starting the ancestors of the node, from the root.
+    ancestors: [usize; MAX_DEPTH],
+    /// Number of ancestors.
+    depth: usize,
+}
+
+/// Base address of a register block, which starts out at the address known for
+/// the board and can be moved to the address found in the device tree.
+pub struct RegisterBlock
+{
+    /// Physical address of the registers.
+    phys: AtomicUsize,
+    /// Virtual address of the registers.
+    virt: AtomicUsize,
+}
+
+/// Error found while loading a device tree blob.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FdtError
+{
+    /// The firmware didn't pass a blob.
+    Missing,
+    /// The blob is not aligned to 8 bytes.
+    Misaligned(usize),
+    /// The blob doesn't start with the magic number.
+    BadMagic(u32),
+    /// The size of the blob is out of bounds.
+    BadSize(usize),
+    /// The blob's version is not compatible with the parser.
+    Unsupported(u32),
+    /// The blob's blocks extend past its end.
+    Truncated,
+}
+
+/// Structure block token.
+enum Token<'a>
+{
+    /// Start of a node with its name.
+    BeginNode(&'a str),
+    /// End of a node.
+    EndNode,
+    /// Property name and value.
+    Property(&'a str, &'a [u8]),
+    /// End of the tree.
+    End,
+}
+
+/// Loads the device tree blob passed by the firmware, if that hasn't been done
+/// yet.
+///
+/// * `addr`: Physical address of the blob, or 0 if there's none.
+///
+/// Returns the device tree, or `None` if the blob is missing or invalid, in
+/// which case the reason is logged.
+pub fn init(addr: usize) -> Option<&'static Fdt>
+{
+    FDT.call_once(|| match Fdt::load(addr) {
+           Ok(fdt) => {
+               debug!("Loaded 0x{:X} bytes of device tree from 0x{addr:X}", fdt.blob.len());
+               Some(fdt)
+           }
+           Err(err) => {
+               warn!("Using the built in device addresses: {err}");
+               None
+           }
+       })?
+       .as_ref()
+}
+
+/// Returns the device tree, or `None` if it hasn't been loaded.
+pub fn get() -> Option<&'static Fdt>
+{
+    FDT.get()?.as_ref()
+}
+
+/// Reads a big endian cell.
+///
+/// * `bytes`: Bytes containing the cell.
+/// * `offset`: Offset of the cell in bytes.
+///
+/// Returns the cell, or `None` if it's out of bounds.
+fn cell(bytes: &[u8], offset: usize) -> Option<u32>
+{
+    let bytes = bytes.get(offset .. offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads a number made of big endian cells.
+///
+/// * `bytes`: Bytes containing the cells.
+///
+/// Returns the number, or `None` if it doesn't fit in 64 bits.
+fn cells(bytes: &[u8]) -> Option<u64>
+{
+    if bytes.len() > 8 || !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |num, byte| num << 8 | *byte as u64))
+}
+
+/// Reads a NUL terminated string.
+///
+/// * `bytes`: Bytes starting with the string.
+///
+/// Returns the string, or `None` if it's unterminated or not valid UTF-8.
+fn c_str(bytes: &[u8]) -> Option<&str>
+{
+    let len = bytes.iter().position(|byte| *byte == 0)?;
+    from_utf8(&bytes[.. len]).ok()
+}
+
+impl Fdt
+{
+    /// Copies and validates a device tree blob.
+    ///
+    /// * `addr`: Physical address of the blob, or 0 if there's none.
+    ///
+    /// Returns the device tree, or an error describing what's wrong with the
+    /// blob.
+    fn load(addr: usize) -> Result<Self, FdtError>
+    {
+        if addr == 0 {
+            return Err(FdtError::Missing);
+        }
+        if addr & 0x7 != 0 {
+            return Err(FdtError::Misaligned(addr));
+        }
+        // The blob can be anywhere in memory, so it's mapped read-only through
+        // the device window, uncached since it's only read once to copy it.
+        let header = mmu::map_memory(addr, HEADER_LEN);
+        let (magic, len) = unsafe {
+            (u32::from_be(header.reg::<u32>(0x0).read()), u32::from_be(header.reg::<u32>(0x4).read()) as usize)
+        };
+        mmu::unmap_device(header, HEADER_LEN);
+        if magic != MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+        if !(HEADER_LEN ..= MAX_LEN).contains(&len) {
+            return Err(FdtError::BadSize(len));
+        }
+        let mut blob = vec![0u8; len].into_boxed_slice();
+        let mapping = mmu::map_memory(addr, len);
+        unsafe { copy_nonoverlapping(mapping.reg::<u8>(0x0), blob.as_mut_ptr(), len) };
+        mmu::unmap_device(mapping, len);
+        let field = |idx: usize| cell(&blob, idx * 4).unwrap() as usize;
+        let (version, last_compatible) = (field(5) as u32, field(6) as u32);
+        if version < MIN_VERSION || last_compatible > VERSION {
+            return Err(FdtError::Unsupported(version));
+        }
+        let structs = field(2) .. field(2) + field(9);
+        let strings = field(3) .. field(3) + field(8);
+        if structs.end > len || strings.end > len {
+            return Err(FdtError::Truncated);
+        }
+        Ok(Self { addr,
+                  blob,
+                  structs,
+                  strings })
+    }
+
+    /// Returns the physical address of the blob passed by the firmware.
+    pub fn addr(&self) -> usize
+    {
+        self.addr
+    }
+
+    /// Returns an iterator over all the nodes in the tree, parents before
+    /// children.
+    pub fn nodes(&self) -> impl Iterator<Item = Node<'_>>
+    {
+        let mut pos = self.structs.start;
+        let mut ancestors = [0; MAX_DEPTH];
+        let mut depth = 0;
+        from_fn(move || {
+            loop {
+                let Some((token, next)) = self.token(pos) else {
+                    // Stop at malformed tokens.
+                    pos = self.structs.end;
+                    return None;
+                };
+                match token {
+                    Token::BeginNode(_) if depth < MAX_DEPTH => {
+                        let node = Node { fdt: self,
+                                          offset: pos,
+                                          ancestors,
+                                          depth };
+                        ancestors[depth] = pos;
+                        depth += 1;
+                        pos = next;
+                        return Some(node);
+                    }
+                    Token::EndNode if depth > 0 => depth -= 1,
+                    Token::Property(..) => (),
+                    Token::BeginNode(_) | Token::EndNode | Token::End => {
+                        pos = self.structs.end;
+                        return None;
+                    }
+                }
+                pos = next;
+            }
+        })
+    }
+
+    /// Finds the first enabled node compatible with a device.
+    ///
+    /// * `compatible`: Compatible string of the device.
+    ///
+    /// Returns the node, or `None` if there's no such node.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'_>>
+    {
+        self.nodes()
+            .find(|node| node.is_compatible(compatible) && node.is_enabled())
+    }
+
+    /// Finds a node by its path from the root, or by an alias defined in the
+    /// `/aliases` node for such a path.
+    ///
+    /// * `path`: Absolute path or alias, in which leaving out a unit address
+    ///   matches the first node with that name.
+    ///
+    /// Returns the node, or `None` if there's no such node.
+    pub fn find_path(&self, path: &str) -> Option<Node<'_>>
+    {
+        let root = self.nodes().next()?;
+        let path = match path.strip_prefix('/') {
+            Some(path) => path,
+            None => {
+                let aliases = root.children().find(|node| node.name() == "aliases")?;
+                c_str(aliases.property(path)?)?.strip_prefix('/')?
+            }
+        };
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(root, |node, name| {
+                node.children().find(|child| {
+                                   let full = child.name();
+                                   full == name || full.split('@').next() == Some(name)
+                               })
+            })
+    }
+
+    /// Finds the node of the device that the firmware chose for the console,
+    /// named by the `stdout-path` property of the `/chosen` node, or by the
+    /// `serial0` alias if there's no such property.
+    ///
+    /// Returns the node, or `None` if there's no such node.
+    pub fn find_stdout(&self) -> Option<Node<'_>>
+    {
+        let path = self.find_path("/chosen")
+                       .and_then(|chosen| chosen.strings("stdout-path").next())
+                       .unwrap_or("serial0");
+        // Anything after a colon configures the device rather than naming it.
+        self.find_path(path.split(':').next()?)
+    }
+
+    /// Finds the node that other nodes refer to by a handle.
+    ///
+    /// * `phandle`: Handle of the node.
+    ///
+    /// Returns the node, or `None` if there's no such node.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'_>>
+    {
+        self.nodes().find(|node| node.u32_property("phandle") == Some(phandle))
+    }
+
+    /// Decodes the token at a position of the structure block, skipping any
+    /// NOP tokens.
+    ///
+    /// * `pos`: Offset of the token in the blob.
+    ///
+    /// Returns the token along with the offset of the next one, or `None` if
+    /// the token is malformed.
+    fn token(&self, mut pos: usize) -> Option<(Token<'_>, usize)>
+    {
+        let structs = &self.blob[.. self.structs.end];
+        loop {
+            let tag = cell(structs, pos)?;
+            pos += 4;
+            match tag {
+                BEGIN_NODE => {
+                    let name = c_str(structs.get(pos ..)?)?;
+                    return Some((Token::BeginNode(name), pos + ((name.len() + 4) & !0x3)));
+                }
+                END_NODE => return Some((Token::EndNode, pos)),
+                PROP => {
+                    let len = cell(structs, pos)? as usize;
+                    let name = cell(structs, pos + 4)? as usize;
+                    let value = structs.get(pos + 8 .. pos + 8 + len)?;
+                    let name = c_str(self.blob[.. self.strings.end].get(self.strings.start + name ..)?)?;
+                    return Some((Token::Property(name, value), pos + 8 + ((len + 3) & !0x3)));
+                }
+                NOP => (),
+                END => return Some((Token::End, pos)),
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Node<'a>
+{
+    /// Returns the name of the node, including the unit address.
+    pub fn name(&self) -> &'a str
+    {
+        match self.fdt.token(self.offset) {
+            Some((Token::BeginNode(name), _)) => name,
+            _ => "",
+        }
+    }
+
+    /// Returns the parent of the node, or `None` if this is the root.
+    pub fn parent(&self) -> Option<Self>
+    {
+        let depth = self.depth.checked_sub(1)?;
+        Some(Self { offset: self.ancestors[depth],
+                    depth,
+                    ..*self })
+    }
+
+    /// Returns an iterator over the children of the node.
+    pub fn children(&self) -> impl Iterator<Item = Self>
+    {
+        let parent = *self;
+        self.fdt
+            .nodes()
+            .filter(move |node| node.depth == parent.depth + 1 && node.ancestors[parent.depth] == parent.offset)
+    }
+
+    /// Returns an iterator over the names and values of the node's properties.
+    pub fn properties(&self) -> impl Iterator<Item = (&'a str, &'a [u8])>
+    {
+        let fdt = self.fdt;
+        let mut pos = fdt.token(self.offset).map_or(fdt.structs.end, |(_, next)| next);
+        from_fn(move || {
+            let (Token::Property(name, value), next) = fdt.token(pos)? else {
+                return None;
+            };
+            pos = next;
+            Some((name, value))
+        })
+    }
+
+    /// Looks up a property.
+    ///
+    /// * `name`: Name of the property.
+    ///
+    /// Returns the value of the property, or `None` if the node doesn't have
+    /// it.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]>
+    {
+        self.properties()
+            .find_map(|(other, value)| (other == name).then_some(value))
+    }
+
+    /// Looks up a property made of a single cell.
+    ///
+    /// * `name`: Name of the property.
+    ///
+    /// Returns the value of the property, or `None` if the node doesn't have
+    /// it or its value is not a single cell.
+    pub fn u32_property(&self, name: &str) -> Option<u32>
+    {
+        let value = self.property(name)?;
+        (value.len() == 4).then(|| cell(value, 0))?
+    }
+
+    /// Looks up a property made of a list of strings.
+    ///
+    /// * `name`: Name of the property.
+    ///
+    /// Returns an iterator over the strings, which is empty if the node doesn't
+    /// have the property.
+    pub fn strings(&self, name: &str) -> impl Iterator<Item = &'a str>
+    {
+        self.property(name)
+            .unwrap_or_default()
+            .split(|byte| *byte == 0)
+            .filter(|string| !string.is_empty())
+            .filter_map(|string| from_utf8(string).ok())
+    }
+
+    /// Returns whether the node is compatible with a device.
+    ///
+    /// * `compatible`: Compatible string of the device.
+    pub fn is_compatible(&self, compatible: &str) -> bool
+    {
+        self.strings("compatible").any(|other| other == compatible)
+    }
+
+    /// Returns whether the device described by the node is enabled.
+    pub fn is_enabled(&self) -> bool
+    {
+        self.strings("status")
+            .next()
+            .is_none_or(|status| status == "okay" || status == "ok")
+    }
+
+    /// Looks up a register block of the device described by the node.
+    ///
+    /// * `idx`: Index of the register block.
+    ///
+    /// Returns the physical address and length of the register block, or
+    /// `None` if there's no such block or it's not mapped into the CPU's
+    /// physical address space.
+    pub fn reg(&self, idx: usize) -> Option<(usize, usize)>
+    {
+        let bus = self.parent()?;
+        let addr_len = bus.cells("#address-cells", DEFAULT_ADDRESS_CELLS) * 4;
+        let size_len = bus.cells("#size-cells", DEFAULT_SIZE_CELLS) * 4;
+        let stride = addr_len + size_len;
+        let entry = self.property("reg")?
+                        .get(idx.checked_mul(stride)? .. (idx + 1) * stride)?;
+        let addr = cells(&entry[.. addr_len])?;
+        let len = cells(&entry[addr_len ..])?;
+        Some((bus.translate(addr)?, len as usize))
+    }
+
+    /// Looks up a register block of the device described by the node by name.
+    ///
+    /// * `name`: Name of the register block in the `reg-names` property.
+    ///
+    /// Returns the physical address and length of the register block, or
+    /// `None` if there's no such block or it's not mapped into the CPU's
+    /// physical address space.
+    pub fn reg_by_name(&self, name: &str) -> Option<(usize, usize)>
+    {
+        self.reg(self.strings("reg-names").position(|other| other == name)?)
+    }
+
+    /// Looks up an interrupt raised by the device described by the node.
+    ///
+    /// * `idx`: Index of the interrupt.
+    ///
+    /// Returns the interrupt ID at the GIC, or `None` if there's no such
+    /// interrupt or it's not routed directly to the GIC.
+    pub fn interrupt(&self, idx: usize) -> Option<u32>
+    {
+        let phandle = self.inherited_u32_property("interrupt-parent")?;
+        let controller = self.fdt.find_phandle(phandle)?;
+        if !controller.is_compatible(GIC_COMPATIBLE) {
+            return None;
+        }
+        let len = controller.cells("#interrupt-cells", 3) * 4;
+        if len < 8 {
+            return None;
+        }
+        let spec = self.property("interrupts")?
+                       .get(idx.checked_mul(len)? .. (idx + 1) * len)?;
+        let num = cell(spec, 4)?;
+        match cell(spec, 0)? {
+            GIC_SPI => num.checked_add(SPI_BASE),
+            GIC_PPI => num.checked_add(PPI_BASE),
+            _ => None,
+        }
+    }
+
+    /// Looks up the rate of a clock consumed by the device described by the
+    /// node.
+    ///
+    /// * `idx`: Index of the clock.
+    ///
+    /// Returns the clock rate in Hz, or `None` if there's no such clock or it
+    /// doesn't run at a fixed rate, in which case only the firmware knows it.
+    pub fn clock_rate(&self, idx: usize) -> Option<u32>
+    {
+        let clocks = self.property("clocks")?;
+        let mut pos = 0;
+        for _ in 0 .. idx {
+            let provider = self.fdt.find_phandle(cell(clocks, pos)?)?;
+            pos += (provider.cells("#clock-cells", 0) + 1) * 4;
+        }
+        let provider = self.fdt.find_phandle(cell(clocks, pos)?)?;
+        if !provider.is_compatible(FIXED_CLOCK_COMPATIBLE) {
+            return None;
+        }
+        provider.u32_property("clock-frequency")
+    }
+
+    /// Looks up the number of cells in a field of the node's children.
+    ///
+    /// * `name`: Name of the property holding the number of cells.
+    /// * `default`: Number of cells if the node doesn't specify it.
+    ///
+    /// Returns the number of cells.
+    fn cells(&self, name: &str, default: usize) -> usize
+    {
+        self.u32_property(name).map_or(default, |cells| cells as usize)
+    }
+
+    /// Looks up a property made of a single cell in the node or the closest
+    /// ancestor that has it.
+    ///
+    /// * `name`: Name of the property.
+    ///
+    /// Returns the value of the property, or `None` if no node has it.
+    fn inherited_u32_property(&self, name: &str) -> Option<u32>
+    {
+        let mut node = *self;
+        loop {
+            if let Some(value) = node.u32_property(name) {
+                return Some(value);
+            }
+            node = node.parent()?;
+        }
+    }
+
+    /// Translates an address on the bus formed by the node's children to a
+    /// CPU physical address, through the `ranges` of the node and its
+    /// ancestors.
+    ///
+    /// * `addr`: Address on the bus.
+    ///
+    /// Returns the physical address, or `None` if the address is not mapped
+    /// into the CPU's physical address space.
+    fn translate(&self, mut addr: u64) -> Option<usize>
+    {
+        let mut bus = *self;
+        while let Some(parent) = bus.parent() {
+            // A missing property means that the bus is not memory mapped, and an
+            // empty one that its addresses are the same as its parent's.
+            let ranges = bus.property("ranges")?;
+            if !ranges.is_empty() {
+                let child_len = bus.cells("#address-cells", DEFAULT_ADDRESS_CELLS) * 4;
+                let parent_len = parent.cells("#address-cells", DEFAULT_ADDRESS_CELLS) * 4;
+                let size_len = bus.cells("#size-cells", DEFAULT_SIZE_CELLS) * 4;
+                let stride = child_len + parent_len + size_len;
+                if stride == 0 {
+                    return None;
+                }
+                addr = ranges.chunks_exact(stride).find_map(|range| {
+                                                       let child = cells(&range[.. child_len])?;
+                                                       let parent = cells(&range[child_len .. child_len + parent_len])?;
+                                                       let len = cells(&range[child_len + parent_len ..])?;
+                                                       let offset = addr.checked_sub(child)?;
+                                                       (offset < len).then(|| parent + offset)
+                                                   })?;
+            }
+            bus = parent;
+        }
+        addr.try_into().ok()
+    }
+}
+
+impl RegisterBlock
+{
+    /// Creates and initializes a new register block at a fixed address.
+    ///
+    /// * `addr`: Physical address of the registers, which must be identity
+    ///   mapped.
+    ///
+    /// Returns the newly created register block.
+    pub const fn new(addr: usize) -> Self
+    {
+        Self { phys: AtomicUsize::new(addr),
+               virt: AtomicUsize::new(addr) }
+    }
+
+    /// Moves the register block to another physical address, mapping it if
+    /// it's outside the peripheral windows mapped at boot.
+    ///
+    /// * `phys`: Physical address of the registers.
+    /// * `len`: Length of the registers in bytes.
+    pub fn set(&self, phys: usize, len: usize)
+    {
+        self.virt.store(mmu::device_address(phys, len), Ordering::Relaxed);
+        self.phys.store(phys, Ordering::Relaxed);
+    }
+
+    /// Returns a pointer to a register.
+    ///
+    /// * `offset`: Offset of the register in bytes.
+    pub fn reg(&self, offset: usize) -> *mut u32
+    {
+        (self.virt.load(Ordering::Relaxed) + offset) as _
+    }
+
+    /// Returns the physical address of a register, which is what other bus
+    /// masters use to access it.
+    ///
+    /// * `offset`: Offset of the register in bytes.
+    pub fn phys(&self, offset: usize) -> usize
+    {
+        self.phys.load(Ordering::Relaxed) + offset
+    }
+}
+
+impl Display for FdtError
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::Missing => write!(fmt, "No device tree was passed by the firmware"),
+            Self::Misaligned(addr) => write!(fmt, "Device tree at 0x{addr:X} is misaligned"),
+            Self::BadMagic(magic) => write!(fmt, "Device tree has a bad magic number: 0x{magic:08X}"),
+            Self::BadSize(len) => write!(fmt, "Device tree has an unsupported size: 0x{len:X} bytes"),
+            Self::Unsupported(version) => write!(fmt, "Device tree has an unsupported version: {version}"),
+            Self::Truncated => write!(fmt, "Device tree is truncated"),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Bitmap font for the framebuffer console.
+//!
+//! Glyphs were rasterized from DejaVu Sans Mono at 14 pixels, and cover the
+//! printable ASCII characters.  Each glyph has one byte per row, with the least
+//! significant bit being the leftmost pixel.
+
+/// Glyph width in pixels.
+pub const WIDTH: usize = 8;
+/// Glyph height in pixels.
+pub const HEIGHT: usize = 16;
+/// First character with a glyph.
+pub const FIRST: u8 = b' ';
+/// Last character with a glyph.
+pub const LAST: u8 = b'~';
+
+/// Glyphs, indexed by character minus [`FIRST`].
+pub static GLYPHS: [[u8; HEIGHT]; (LAST - FIRST + 1) as usize] =
+    [[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+     [0x00, 0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00], // '!'
+     [0x00, 0x00, 0x28, 0x28, 0x28, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+     [0x00, 0x00, 0x48, 0x48, 0x68, 0xFE, 0x24, 0x24, 0x7F, 0x14, 0x12, 0x12, 0x00, 0x00, 0x00, 0x00], // '#'
+     [0x00, 0x10, 0x10, 0x7C, 0x92, 0x12, 0x16, 0x7C, 0xD0, 0x90, 0x92, 0x7C, 0x10, 0x10, 0x00, 0x00], // '$'
+     [0x00, 0x00, 0x06, 0x09, 0x09, 0x46, 0x30, 0x0C, 0x62, 0x90, 0x90, 0x60, 0x00, 0x00, 0x00, 0x00], // '%'
+     [0x00, 0x00, 0x38, 0x04, 0x04, 0x0C, 0x0C, 0x92, 0xA2, 0xA2, 0x46, 0xBC, 0x00, 0x00, 0x00, 0x00], // '&'
+     [0x00, 0x00, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+     [0x00, 0x30, 0x10, 0x10, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x10, 0x10, 0x20, 0x00, 0x00, 0x00], // '('
+     [0x00, 0x0C, 0x08, 0x08, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x08, 0x08, 0x0C, 0x00, 0x00, 0x00], // ')'
+     [0x00, 0x00, 0x10, 0x92, 0x7C, 0x38, 0xD6, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '*'
+     [0x00, 0x00, 0x00, 0x00, 0x10, 0x10, 0x10, 0xFE, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00], // '+'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x08, 0x04, 0x00, 0x00], // ','
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '-'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '.'
+     [0x00, 0x00, 0x40, 0x20, 0x20, 0x20, 0x10, 0x10, 0x08, 0x08, 0x04, 0x04, 0x04, 0x02, 0x00, 0x00], // '/'
+     [0x00, 0x00, 0x38, 0x44, 0x82, 0x82, 0x92, 0x82, 0x82, 0x82, 0x44, 0x38, 0x00, 0x00, 0x00, 0x00], // '0'
+     [0x00, 0x00, 0x18, 0x14, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7C, 0x00, 0x00, 0x00, 0x00], // '1'
+     [0x00, 0x00, 0x7C, 0xC2, 0x80, 0x80, 0x40, 0x60, 0x30, 0x08, 0x04, 0xFE, 0x00, 0x00, 0x00, 0x00], // '2'
+     [0x00, 0x00, 0x7C, 0x82, 0x80, 0xC0, 0x38, 0xC0, 0x80, 0x80, 0xC2, 0x7C, 0x00, 0x00, 0x00, 0x00], // '3'
+     [0x00, 0x00, 0x60, 0x50, 0x58, 0x48, 0x44, 0x42, 0xFE, 0x40, 0x40, 0x40, 0x00, 0x00, 0x00, 0x00], // '4'
+     [0x00, 0x00, 0x7E, 0x02, 0x02, 0x3E, 0x42, 0x80, 0x80, 0x80, 0x42, 0x3C, 0x00, 0x00, 0x00, 0x00], // '5'
+     [0x00, 0x00, 0x78, 0x8C, 0x06, 0x02, 0x7A, 0xC6, 0x82, 0x82, 0xC4, 0x78, 0x00, 0x00, 0x00, 0x00], // '6'
+     [0x00, 0x00, 0xFE, 0xC0, 0x40, 0x20, 0x20, 0x10, 0x10, 0x08, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00], // '7'
+     [0x00, 0x00, 0x7C, 0x82, 0x82, 0x82, 0x7C, 0xC6, 0x82, 0x82, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '8'
+     [0x00, 0x00, 0x3C, 0x46, 0x82, 0x82, 0xC6, 0xBC, 0x80, 0xC0, 0x62, 0x3C, 0x00, 0x00, 0x00, 0x00], // '9'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // ':'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x08, 0x04, 0x00, 0x00], // ';'
+     [0x00, 0x00, 0x00, 0x00, 0x80, 0x70, 0x1C, 0x02, 0x1C, 0x70, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00], // '<'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0xFE, 0x00, 0x00, 0xFE, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '='
+     [0x00, 0x00, 0x00, 0x00, 0x02, 0x1C, 0x70, 0x80, 0x70, 0x1C, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00], // '>'
+     [0x00, 0x00, 0x1C, 0x22, 0x20, 0x30, 0x18, 0x08, 0x08, 0x00, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00], // '?'
+     [0x00, 0x00, 0x78, 0xCC, 0x84, 0xE2, 0x92, 0x92, 0x92, 0x92, 0xE2, 0x04, 0x0C, 0x70, 0x00, 0x00], // '@'
+     [0x00, 0x00, 0x10, 0x28, 0x28, 0x28, 0x28, 0x44, 0x7C, 0x44, 0x82, 0x82, 0x00, 0x00, 0x00, 0x00], // 'A'
+     [0x00, 0x00, 0x7E, 0x82, 0x82, 0x82, 0x7E, 0xC2, 0x82, 0x82, 0xC2, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'B'
+     [0x00, 0x00, 0x78, 0x84, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x84, 0x78, 0x00, 0x00, 0x00, 0x00], // 'C'
+     [0x00, 0x00, 0x3E, 0x42, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0x42, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'D'
+     [0x00, 0x00, 0xFE, 0x02, 0x02, 0x02, 0xFE, 0x02, 0x02, 0x02, 0x02, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'E'
+     [0x00, 0x00, 0xFE, 0x02, 0x02, 0x02, 0xFE, 0x02, 0x02, 0x02, 0x02, 0x02, 0x00, 0x00, 0x00, 0x00], // 'F'
+     [0x00, 0x00, 0x78, 0x84, 0x02, 0x02, 0x02, 0xC2, 0x82, 0x82, 0x84, 0x78, 0x00, 0x00, 0x00, 0x00], // 'G'
+     [0x00, 0x00, 0x82, 0x82, 0x82, 0x82, 0xFE, 0x82, 0x82, 0x82, 0x82, 0x82, 0x00, 0x00, 0x00, 0x00], // 'H'
+     [0x00, 0x00, 0x7C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'I'
+     [0x00, 0x00, 0x78, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x62, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'J'
+     [0x00, 0x00, 0x42, 0x22, 0x12, 0x0A, 0x0E, 0x12, 0x32, 0x22, 0x42, 0x82, 0x00, 0x00, 0x00, 0x00], // 'K'
+     [0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'L'
+     [0x00, 0x00, 0xC6, 0xC6, 0xAA, 0xAA, 0xAA, 0x92, 0x82, 0x82, 0x82, 0x82, 0x00, 0x00, 0x00, 0x00], // 'M'
+     [0x00, 0x00, 0x86, 0x86, 0x8A, 0x8A, 0x92, 0x92, 0xA2, 0xA2, 0xC2, 0xC2, 0x00, 0x00, 0x00, 0x00], // 'N'
+     [0x00, 0x00, 0x38, 0x44, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0x44, 0x38, 0x00, 0x00, 0x00, 0x00], // 'O'
+     [0x00, 0x00, 0x7E, 0xC2, 0x82, 0x82, 0xC2, 0x7E, 0x02, 0x02, 0x02, 0x02, 0x00, 0x00, 0x00, 0x00], // 'P'
+     [0x00, 0x00, 0x38, 0x44, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0x44, 0x78, 0x60, 0x40, 0x00, 0x00], // 'Q'
+     [0x00, 0x00, 0x7E, 0xC2, 0x82, 0x82, 0xC2, 0x3E, 0x42, 0x82, 0x82, 0x02, 0x00, 0x00, 0x00, 0x00], // 'R'
+     [0x00, 0x00, 0x78, 0x86, 0x02, 0x02, 0x0C, 0x70, 0x80, 0x80, 0xC2, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'S'
+     [0x00, 0x00, 0xFE, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00], // 'T'
+     [0x00, 0x00, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0x82, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'U'
+     [0x00, 0x00, 0x82, 0x82, 0x44, 0x44, 0x44, 0x28, 0x28, 0x28, 0x28, 0x10, 0x00, 0x00, 0x00, 0x00], // 'V'
+     [0x00, 0x00, 0x81, 0x81, 0x81, 0x99, 0x5A, 0x5A, 0x5A, 0x24, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00], // 'W'
+     [0x00, 0x00, 0x82, 0x44, 0x28, 0x28, 0x10, 0x28, 0x28, 0x44, 0x44, 0x82, 0x00, 0x00, 0x00, 0x00], // 'X'
+     [0x00, 0x00, 0x82, 0x44, 0x44, 0x28, 0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00], // 'Y'
+     [0x00, 0x00, 0xFE, 0xC0, 0x40, 0x20, 0x10, 0x10, 0x08, 0x04, 0x06, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'Z'
+     [0x00, 0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00, 0x00, 0x00], // '['
+     [0x00, 0x00, 0x02, 0x04, 0x04, 0x04, 0x08, 0x08, 0x10, 0x10, 0x20, 0x20, 0x20, 0x40, 0x00, 0x00], // '\\'
+     [0x00, 0x1C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1C, 0x00, 0x00, 0x00], // ']'
+     [0x00, 0x00, 0x10, 0x28, 0x44, 0xC6, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00], // '_'
+     [0x0C, 0x08, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+     [0x00, 0x00, 0x00, 0x00, 0x38, 0x44, 0x40, 0x7C, 0x42, 0x42, 0x62, 0x5C, 0x00, 0x00, 0x00, 0x00], // 'a'
+     [0x00, 0x02, 0x02, 0x02, 0x3E, 0x26, 0x42, 0x42, 0x42, 0x42, 0x26, 0x3A, 0x00, 0x00, 0x00, 0x00], // 'b'
+     [0x00, 0x00, 0x00, 0x00, 0x38, 0x44, 0x02, 0x02, 0x02, 0x02, 0x44, 0x38, 0x00, 0x00, 0x00, 0x00], // 'c'
+     [0x00, 0x40, 0x40, 0x40, 0x7C, 0x64, 0x42, 0x42, 0x42, 0x42, 0x64, 0x5C, 0x00, 0x00, 0x00, 0x00], // 'd'
+     [0x00, 0x00, 0x00, 0x00, 0x3C, 0x64, 0x42, 0x7E, 0x02, 0x02, 0x44, 0x38, 0x00, 0x00, 0x00, 0x00], // 'e'
+     [0x00, 0x70, 0x08, 0x08, 0x7E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00], // 'f'
+     [0x00, 0x00, 0x00, 0x00, 0x5C, 0x64, 0x42, 0x42, 0x42, 0x42, 0x64, 0x5C, 0x40, 0x44, 0x38, 0x00], // 'g'
+     [0x00, 0x02, 0x02, 0x02, 0x3A, 0x46, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00], // 'h'
+     [0x00, 0x10, 0x10, 0x00, 0x1C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'i'
+     [0x00, 0x10, 0x10, 0x00, 0x1C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x0E, 0x00], // 'j'
+     [0x00, 0x02, 0x02, 0x02, 0x22, 0x12, 0x0A, 0x0E, 0x12, 0x12, 0x22, 0x42, 0x00, 0x00, 0x00, 0x00], // 'k'
+     [0x00, 0x0F, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x70, 0x00, 0x00, 0x00, 0x00], // 'l'
+     [0x00, 0x00, 0x00, 0x00, 0x7E, 0x92, 0x92, 0x92, 0x92, 0x92, 0x92, 0x92, 0x00, 0x00, 0x00, 0x00], // 'm'
+     [0x00, 0x00, 0x00, 0x00, 0x3A, 0x46, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00], // 'n'
+     [0x00, 0x00, 0x00, 0x00, 0x3C, 0x66, 0x42, 0x42, 0x42, 0x42, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'o'
+     [0x00, 0x00, 0x00, 0x00, 0x3A, 0x26, 0x42, 0x42, 0x42, 0x42, 0x26, 0x3E, 0x02, 0x02, 0x02, 0x00], // 'p'
+     [0x00, 0x00, 0x00, 0x00, 0x5C, 0x64, 0x42, 0x42, 0x42, 0x42, 0x64, 0x5C, 0x40, 0x40, 0x40, 0x00], // 'q'
+     [0x00, 0x00, 0x00, 0x00, 0x3C, 0x4C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00], // 'r'
+     [0x00, 0x00, 0x00, 0x00, 0x3C, 0x42, 0x02, 0x0E, 0x70, 0x40, 0x42, 0x3C, 0x00, 0x00, 0x00, 0x00], // 's'
+     [0x00, 0x00, 0x08, 0x08, 0x7E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x70, 0x00, 0x00, 0x00, 0x00], // 't'
+     [0x00, 0x00, 0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x62, 0x5C, 0x00, 0x00, 0x00, 0x00], // 'u'
+     [0x00, 0x00, 0x00, 0x00, 0x42, 0x42, 0x24, 0x24, 0x24, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // 'v'
+     [0x00, 0x00, 0x00, 0x00, 0x81, 0x81, 0x5A, 0x5A, 0x5A, 0x5A, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00], // 'w'
+     [0x00, 0x00, 0x00, 0x00, 0x42, 0x24, 0x18, 0x18, 0x18, 0x24, 0x24, 0x42, 0x00, 0x00, 0x00, 0x00], // 'x'
+     [0x00, 0x00, 0x00, 0x00, 0x42, 0x44, 0x24, 0x24, 0x28, 0x18, 0x10, 0x10, 0x10, 0x08, 0x0C, 0x00], // 'y'
+     [0x00, 0x00, 0x00, 0x00, 0x7E, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'z'
+     [0x00, 0x60, 0x10, 0x10, 0x10, 0x10, 0x10, 0x0C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x60, 0x00, 0x00], // '{'
+     [0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00], // '|'
+     [0x00, 0x0C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x60, 0x10, 0x10, 0x10, 0x10, 0x10, 0x0C, 0x00, 0x00], // '}'
+     [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x9C, 0x62, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]  /* '~' */];
@@ -1,58 +1,103 @@
-use core::hint::spin_loop;
 use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
 
-use crate::dma::setup_sender;
-use crate::scalloc::alloc;
-use crate::{mbox, println};
+use crate::board::hdmi::{self as board, AU_CFG, AU_CHMAP, AU_PKTCFG, CRP_CFG, CTS0, CTS1, IF_CFG, IF_STATUS};
+use crate::board::mbox::phys_address;
+use crate::dma::{current_block, next_block, setup_sender, stop_sender};
+use crate::dmabuf::{DeviceBuffer, DmaBuffer};
+use crate::fdt::{Fdt, RegisterBlock};
+use crate::sync::{SpinLock, Spsc};
+use crate::timer::{poll_register, RegisterTimeout};
+use crate::{debug, info, mbox, println, timer, warn};
 
-/// Core register block base.
-const BASE: usize = 0x107C701400;
-/// Audio channel map.
-const AU_CHMAP: *mut u32 = (BASE + 0xA4) as _;
-/// Audio configuration register.
-const AU_CFG: *mut u32 = (BASE + 0xA8) as _;
-/// Packet configuration register.
-const AU_PKTCFG: *mut u32 = (BASE + 0xC0) as _;
-/// Info frame configuration register.
-const IF_CFG: *mut u32 = (BASE + 0xC4) as _;
-/// Info frame status register.
-const IF_STATUS: *mut u32 = (BASE + 0xCC) as _;
-/// Content type reporting packet configuration register.
-const CRP_CFG: *mut u32 = (BASE + 0xD0) as _;
-/// Clock to service register 0.
-const CTS0: *mut u32 = (BASE + 0xD4) as _;
-/// Clock to service register 1.
-const CTS1: *mut u32 = (BASE + 0xD8) as _;
-/// Info frame packet register block base.
-const IF_BASE: usize = 0x107C703800;
 /// First register of the info frame packet block.
-const IF_START: *mut u32 = IF_BASE as _;
-/// HD register block base.
-const HD_BASE: usize = 0x107C720000;
+const IF_START: usize = 0x0;
 /// HD audio control register.
-const HD_AU_CTL: *mut u32 = (HD_BASE + 0x10) as _;
+const HD_AU_CTL: usize = 0x10;
 /// HD audio DMA DREQ thresholds configuration register.
-const HD_AU_THR: *mut u32 = (HD_BASE + 0x14) as _;
+const HD_AU_THR: usize = 0x14;
 /// HD audio format register.
-const HD_AU_FMT: *mut u32 = (HD_BASE + 0x18) as _;
+const HD_AU_FMT: usize = 0x18;
 /// HD audio data FIFO register.
-const HD_AU_DATA: *mut u32 = (HD_BASE + 0x1C) as _;
+const HD_AU_DATA: usize = 0x1C;
 /// HD audio clock division register.
-const HD_AU_SMP: *mut u32 = (HD_BASE + 0x20) as _;
+const HD_AU_SMP: usize = 0x20;
 /// CPRMAN clock rate.
 const CLOCK_FREQ: u32 = 54000000;
 /// Pixel clock rate.
 const PIXCLOCK_FREQ: u32 = 148500000;
 /// Audio sample rate.
-const SAMPLE_RATE: u32 = 48000;
-/// Data request device ID.
-const DREQ: u32 = 10;
-/// Audio buffer length in words (must fit in a 128KB buffer).
-const AU_BUF_LEN: usize = (SAMPLE_RATE * 2 / 4) as _;
+pub const SAMPLE_RATE: u32 = 48000;
+/// Audio channel count.
+pub const CHANNELS: u32 = 2;
+/// Highest supported tone frequency.
+pub const MAX_TONE_FREQ: u32 = SAMPLE_RATE / 4;
+/// Highest supported volume in percent of full scale.
+pub const MAX_VOLUME: u32 = 100;
 /// Get frame buffer memory property tag.
 const GET_FB_TAG: u32 = 0x40001;
 /// Get frame buffer depth tag.
 const GET_FB_DEPTH_TAG: u32 = 0x40005;
+/// Duration of the audio held by the audio buffer.
+const BUFFER_TIME: Duration = Duration::from_millis(250);
+/// Number of words in the audio buffer, with one word per sample per channel,
+/// which must fit in a 128KB buffer.
+const BUFFER_LEN: usize = (SAMPLE_RATE * CHANNELS) as usize * BUFFER_TIME.as_millis() as usize / 1000;
+/// Milliseconds given to the video core to prepare the HDMI registers.
+const VIDEO_SETTLE_MS: u64 = 50;
+/// Maximum time to wait for a register to reflect a change.
+const REGISTER_TIMEOUT: Duration = Duration::from_millis(100);
+/// Capacity of the streamed sample queue, which holds a little over a third of
+/// a second of audio.
+const STREAM_QUEUE_LEN: usize = 32768;
+
+/// Core registers.
+static BASE: RegisterBlock = RegisterBlock::new(board::BASE);
+/// Info frame packet registers.
+static IF_BASE: RegisterBlock = RegisterBlock::new(board::IF_BASE);
+/// HD registers.
+static HD_BASE: RegisterBlock = RegisterBlock::new(board::HD_BASE);
+/// Audio playback state.
+static AUDIO: SpinLock<Audio> = SpinLock::new(Audio { buf: None,
+                                                      tone: Tone { freqs: [200, 300],
+                                                                   volume: 50 },
+                                                      stream: None });
+/// Streamed samples waiting to be copied to the audio buffer, consumed with
+/// the playback state locked.
+static STREAM_QUEUE: Spsc<u16, STREAM_QUEUE_LEN> = Spsc::new();
+
+/// Tone generator settings.
+#[derive(Clone, Copy, Debug)]
+pub struct Tone
+{
+    /// Frequency of the tone in each channel in Hz.
+    pub freqs: [u32; CHANNELS as usize],
+    /// Volume in percent of full scale.
+    pub volume: u32,
+}
+
+/// Audio playback state.
+struct Audio
+{
+    /// Audio buffer being played.
+    buf: Option<DeviceBuffer<[u32]>>,
+    /// Tone generator settings.
+    tone: Tone,
+    /// Streaming state, or `None` if the audio buffer is playing tones.
+    stream: Option<Stream>,
+}
+
+/// Streaming state.
+#[derive(Debug)]
+struct Stream
+{
+    /// Half of the audio buffer to fill next.
+    half: usize,
+    /// Index of the next subframe to encode.
+    subframe: usize,
+    /// Number of times the audio buffer has been refilled.
+    fills: usize,
+}
 
 // Generates a value with the specified bit fields.
 macro_rules! bits {
@@ -70,41 +115,62 @@ macro_rules! bits {
     }};
 }
 
+/// Switches to the HDMI controller described by the device tree, taking the
+/// core, info frame packet and HD register blocks from it.
+///
+/// * `fdt`: Device tree.
+///
+/// Must be called before [`init`].
+pub fn probe(fdt: &Fdt)
+{
+    let Some(node) = fdt.find_compatible(board::COMPATIBLE) else {
+        warn!("No HDMI controller in the device tree");
+        return;
+    };
+    for (name, block) in [("hdmi", &BASE), ("packet", &IF_BASE), ("hd", &HD_BASE)] {
+        match node.reg_by_name(name) {
+            Some((addr, len)) => block.set(addr, len),
+            None => warn!("No {name} registers for the HDMI controller in the device tree"),
+        }
+    }
+    debug!("Found HDMI controller at 0x{:X}", BASE.phys(0));
+}
+
 /// Sets up the HDMI controller to output video and audio.
+///
+/// Returns an error identifying the register that wedged if the firmware
+/// doesn't respond or the HDMI controller doesn't acknowledge the audio setup
+/// in time.
 #[track_caller]
-pub fn init()
+pub fn init() -> Result<(), RegisterTimeout>
 {
     let get_fb_in: u32 = 4;
-    let get_fb_out: [u32; 2];
-    let get_fb_depth_out: u32;
+    let mut get_fb_out = [0u32; 2];
+    let mut get_fb_depth_out = 0u32;
     mbox! {
         GET_FB_TAG: get_fb_in => get_fb_out,
         GET_FB_DEPTH_TAG: _ => get_fb_depth_out,
-    };
+    }?;
     if get_fb_depth_out == 16 {
-        let fb = get_fb_out[0] as usize as *mut u16;
+        let fb = phys_address(get_fb_out[0] as usize) as *mut u16;
         for idx in 0 .. get_fb_out[1] as usize / 2 {
             unsafe {
                 fb.add(idx).write(0x07E0);
             }
         }
     } else if get_fb_depth_out == 32 {
-        let fb = get_fb_out[0] as usize as *mut u32;
+        let fb = phys_address(get_fb_out[0] as usize) as *mut u32;
         for idx in 0 .. get_fb_out[1] as usize / 4 {
             unsafe {
                 fb.add(idx).write(0xFF00FF00);
             }
         }
     } else {
-        println!("Unsupported pixel depth: {get_fb_depth_out}");
+        warn!("Unsupported pixel depth: {get_fb_depth_out}");
     }
     // Wait for the video core to prepare the HDMI registers.
-    for _ in 0 .. 1000000 {
-        spin_loop()
-    }
-    println!("Video initialized");
-    let abuf = alloc::<[u32; AU_BUF_LEN]>();
-    synthesize(unsafe { &mut *abuf });
+    timer::delay_ms(VIDEO_SETTLE_MS);
+    info!("Video initialized");
     unsafe {
         let hd_au_ctl = bits! {
             // Clear starvation bit.
@@ -116,7 +182,7 @@ pub fn init()
             // Compute parity bits for IEC958 subframes.
             8 => 1,
             // Channel count.
-            4 ..= 7 => 2,
+            4 ..= 7 => CHANNELS,
             // Enable HDMI audio.
             3 => 1,
             // Clear underflow error bit.
@@ -124,10 +190,10 @@ pub fn init()
             // Clear overflow error bit.
             1 => 1,
         };
-        HD_AU_CTL.write_volatile(hd_au_ctl);
+        HD_BASE.reg(HD_AU_CTL).write_volatile(hd_au_ctl);
         // Info frames must only b updated when disabled with their register block
         // enabled.
-        let ifcfg = IF_CFG.read_volatile();
+        let ifcfg = BASE.reg(IF_CFG).read_volatile();
         let ifcfgset = bits! {
             // Enable info frame register block.
             16 => 1,
@@ -136,10 +202,10 @@ pub fn init()
             // Audio info frame.
             4 => 1,
         };
-        IF_CFG.write_volatile(ifcfg & !ifcfgclr | ifcfgset);
-        while IF_STATUS.read_volatile() & ifcfgclr != 0 {
-            spin_loop();
-        }
+        BASE.reg(IF_CFG).write_volatile(ifcfg & !ifcfgclr | ifcfgset);
+        poll_register("IF_STATUS", BASE.reg(IF_STATUS), REGISTER_TIMEOUT, |status| {
+            status & ifcfgclr == 0
+        })?;
         // Audio info frame offset (info frame 4, register stride 9).
         let offset = 4 * 9;
         let if40 = bits! {
@@ -150,7 +216,7 @@ pub fn init()
             // Info frame type (audio).
             0 ..= 7 => 0x84,
         };
-        IF_START.add(offset).write_volatile(if40);
+        IF_BASE.reg(IF_START).add(offset).write_volatile(if40);
         let if41 = bits! {
             // Allocate channel 1.
             25 => 1,
@@ -165,14 +231,14 @@ pub fn init()
             // Last channel index.
             0 ..= 2 => 1,
         };
-        IF_START.add(offset + 1).write_volatile(if41);
+        IF_BASE.reg(IF_START).add(offset + 1).write_volatile(if41);
         // The hardware computes the info frame checksums using their whole register
         // blocks, so all the remaining unused registers must be zeroed.
         for idx in 2 .. 9 {
-            IF_START.add(offset + idx).write_volatile(0);
+            IF_BASE.reg(IF_START).add(offset + idx).write_volatile(0);
         }
         let ifcfgset = ifcfgclr;
-        IF_CFG.write_volatile(ifcfg | ifcfgset);
+        BASE.reg(IF_CFG).write_volatile(ifcfg | ifcfgset);
         let au_cfg = bits! {
             // Not sure what this does, but Linux sets it.
             27 => 1,
@@ -183,7 +249,7 @@ pub fn init()
             // Enable channel 0.
             0 => 1,
         };
-        AU_CFG.write_volatile(au_cfg);
+        BASE.reg(AU_CFG).write_volatile(au_cfg);
         let au_pktcfg = bits! {
             // Zero data on flat sample.
             29 => 1,
@@ -196,21 +262,21 @@ pub fn init()
             // Channel 0.
             0 => 1,
         };
-        AU_PKTCFG.write_volatile(au_pktcfg);
+        BASE.reg(AU_PKTCFG).write_volatile(au_pktcfg);
         let au_chmap = bits! {
             // Map channel 1 to channel 1.
             4 ..= 6 => 1,
             // Map channel 0 to channel 0.
             0 ..= 2 => 0,
         };
-        AU_CHMAP.write_volatile(au_chmap);
+        BASE.reg(AU_CHMAP).write_volatile(au_chmap);
         let hd_au_fmt = bits! {
             // Coding type (PCM).
             16 ..= 23 => 2,
             // Sample rate (48000).
             8 ..= 15 => 9,
         };
-        HD_AU_FMT.write_volatile(hd_au_fmt);
+        HD_BASE.reg(HD_AU_FMT).write_volatile(hd_au_fmt);
         let hd_au_thr = bits! {
             // Set panic data request threshold.
             24 ..= 31 => 16,
@@ -221,72 +287,291 @@ pub fn init()
             // Clear normal data request threshold.
             0 ..= 7 => 28,
         };
-        HD_AU_THR.write_volatile(hd_au_thr);
+        HD_BASE.reg(HD_AU_THR).write_volatile(hd_au_thr);
         let hd_au_smp = bits! {
             // Numerator.
             8 ..= 31 => CLOCK_FREQ / SAMPLE_RATE * 2,
             // Denominator (1).
             0 ..= 7 => 0,
         };
-        HD_AU_SMP.write_volatile(hd_au_smp);
+        HD_BASE.reg(HD_AU_SMP).write_volatile(hd_au_smp);
         // I don't know how to operate the following registers, so I'm setting them to
         // the same values as Linux does for the same audio and video configuration.
-        CRP_CFG.write_volatile(0x1000000 | (SAMPLE_RATE * 128 / 1000));
-        CTS0.write_volatile(PIXCLOCK_FREQ / 1000);
-        CTS1.write_volatile(PIXCLOCK_FREQ / 1000);
-        println!("Audio initialized");
-        setup_sender(&*abuf, HD_AU_DATA, DREQ);
+        BASE.reg(CRP_CFG).write_volatile(0x1000000 | (SAMPLE_RATE * 128 / 1000));
+        BASE.reg(CTS0).write_volatile(PIXCLOCK_FREQ / 1000);
+        BASE.reg(CTS1).write_volatile(PIXCLOCK_FREQ / 1000);
+        info!("Audio initialized");
+    }
+    start_audio();
+    Ok(())
+}
+
+/// Starts playing the configured tones if audio is not already playing.
+pub fn start_audio()
+{
+    if is_playing() {
+        return;
+    }
+    let tone = tone();
+    let mut buf = DmaBuffer::<[u32]>::new_slice(BUFFER_LEN);
+    synthesize(&mut buf, tone);
+    // Another core might have started playing in the meantime.
+    let mut audio = AUDIO.lock_irq();
+    if audio.buf.is_some() {
+        return;
+    }
+    let buf = audio.buf.insert(buf.to_device());
+    unsafe { setup_sender(buf, HD_BASE.phys(HD_AU_DATA) as _, board::DREQ) };
+}
+
+/// Stops playing audio if it's playing.
+pub fn stop_audio()
+{
+    stop(&mut AUDIO.lock_irq());
+}
+
+/// Starts playing streamed samples instead of tones, stopping any audio that
+/// is playing.
+///
+/// The audio buffer starts out silent and must be kept fed by calling
+/// [`refill`] frequently, with silence played whenever no samples are queued.
+/// The task running [`feed_stream`] does so while the executor is running, but
+/// code that keeps its core busy must call it by itself.
+pub fn start_stream()
+{
+    let mut buf = DmaBuffer::<[u32]>::new_slice(BUFFER_LEN);
+    for (idx, output) in buf.iter_mut().enumerate() {
+        *output = encode(idx, 0);
+    }
+    let mut audio = AUDIO.lock_irq();
+    stop(&mut audio);
+    // Locking the playback state makes this the only consumer of the queue.
+    while unsafe { STREAM_QUEUE.pop() }.is_some() {}
+    // The DMA channel starts with the first half, so the second half is the
+    // first one to be replaced.
+    audio.stream = Some(Stream { half: 1,
+                                 subframe: BUFFER_LEN / 2,
+                                 fills: 0 });
+    let buf = audio.buf.insert(buf.to_device());
+    unsafe { setup_sender(buf, HD_BASE.phys(HD_AU_DATA) as _, board::DREQ) };
+}
+
+/// Queues a streamed sample to be played.
+///
+/// * `sample`: Signed 16 bit sample, interleaved with the samples of the other
+///   channels.
+///
+/// Returns whether the sample was queued, which fails if the queue is full.
+///
+/// # Safety
+///
+/// The queue is lock-free, so samples must not be queued by more than one
+/// caller at a time.
+pub unsafe fn push_sample(sample: u16) -> bool
+{
+    STREAM_QUEUE.push(sample)
+}
+
+/// Copies queued samples into the half of the audio buffer that was last
+/// played, if the DMA channel has moved on to the other half.
+pub fn refill()
+{
+    let mut audio = AUDIO.lock_irq();
+    let Audio { buf: Some(buf),
+                stream: Some(stream),
+                .. } = &mut *audio
+    else {
+        return;
+    };
+    if current_block() != Some(stream.half ^ 0x1) {
+        return;
+    }
+    let half = buf.size() / size_of::<u32>() / 2;
+    let range = stream.half * half .. (stream.half + 1) * half;
+    // Locking the playback state makes this the only consumer of the queue.
+    unsafe {
+        buf.update(range, |outputs| {
+               for output in outputs {
+                   // Play silence if the stream can't keep up.
+                   *output = encode(stream.subframe, STREAM_QUEUE.pop().unwrap_or(0) as u32);
+                   stream.subframe += 1;
+               }
+           })
+    };
+    stream.half ^= 0x1;
+    stream.fills += 1;
+}
+
+/// Keeps the audio buffer fed with queued samples forever without blocking
+/// other tasks, refilling it whenever the DMA channel moves on to the other
+/// half while streaming.
+pub async fn feed_stream()
+{
+    loop {
+        next_block().await;
+        refill();
     }
 }
 
-/// Synthesizes two audio tones in different frequencies to each of the stereo
-/// channels.
-fn synthesize(buf: &mut [u32; AU_BUF_LEN])
+/// Waits for all the queued samples to be played and stops playing audio.
+pub fn finish_stream()
 {
-    // 192 channel status bits.
-    let mut cs = [0; 24];
-    let csconf = [0x4,  // SPDIF, PCM, no copyright, no emphasis.
-                  0x44, // Software broadcast.
-                  0x0,  // Channel (to fill in later).
-                  0x2,  // 48000Hz, 1000ppm.
-                  0xD2  /* 16 bit sample size, 48000Hz original frequency. */];
-    cs[0 .. 5].copy_from_slice(&csconf);
+    let fills = || AUDIO.lock_irq().stream.as_ref().map(|stream| stream.fills);
+    if fills().is_none() {
+        return;
+    }
+    let queued = || STREAM_QUEUE.len();
+    // The queue drains at the sample rate, and the last samples end up in the
+    // half that was just filled, which is done playing once both halves have
+    // been refilled again.
+    let drain_time = Duration::from_micros(queued() as u64 * 1000000 / (SAMPLE_RATE * CHANNELS) as u64);
+    let res = timer::poll(drain_time + BUFFER_TIME, || {
+                  refill();
+                  queued() == 0
+              }).and_then(|_| {
+                    // The stream may be stopped elsewhere in the meantime, which
+                    // finishes it as well.
+                    let Some(last) = fills().map(|fills| fills + 2) else {
+                        return Ok(());
+                    };
+                    timer::poll(BUFFER_TIME * 2, || {
+                        refill();
+                        fills().is_none_or(|fills| fills >= last)
+                    })
+                });
+    if res.is_err() {
+        warn!("Timed out waiting for the audio stream to finish playing");
+    }
+    stop_audio();
+}
+
+/// Returns whether audio is playing.
+pub fn is_playing() -> bool
+{
+    AUDIO.lock_irq().buf.is_some()
+}
+
+/// Returns the current tone generator settings.
+pub fn tone() -> Tone
+{
+    AUDIO.lock_irq().tone
+}
+
+/// Changes the tone generator settings, restarting playback with the new tones
+/// if audio is playing.
+///
+/// * `tone`: New settings.
+///
+/// Panics if any of the frequencies is zero or above [`MAX_TONE_FREQ`], or the
+/// volume is above [`MAX_VOLUME`].
+#[track_caller]
+pub fn set_tone(tone: Tone)
+{
+    for freq in tone.freqs {
+        assert!(freq > 0 && freq <= MAX_TONE_FREQ,
+                "Tone frequency out of range: {freq}Hz");
+    }
+    assert!(tone.volume <= MAX_VOLUME, "Volume out of range: {}%", tone.volume);
+    AUDIO.lock_irq().tone = tone;
+    if is_playing() {
+        stop_audio();
+        start_audio();
+    }
+}
+
+/// Prints the contents of the HDMI audio registers.
+pub fn dump_registers()
+{
+    let regs = [("HD_AU_CTL", HD_BASE.reg(HD_AU_CTL)),
+                ("HD_AU_THR", HD_BASE.reg(HD_AU_THR)),
+                ("HD_AU_FMT", HD_BASE.reg(HD_AU_FMT)),
+                ("HD_AU_SMP", HD_BASE.reg(HD_AU_SMP)),
+                ("AU_CFG", BASE.reg(AU_CFG)),
+                ("AU_PKTCFG", BASE.reg(AU_PKTCFG)),
+                ("AU_CHMAP", BASE.reg(AU_CHMAP)),
+                ("IF_CFG", BASE.reg(IF_CFG)),
+                ("IF_STATUS", BASE.reg(IF_STATUS)),
+                ("CRP_CFG", BASE.reg(CRP_CFG)),
+                ("CTS0", BASE.reg(CTS0)),
+                ("CTS1", BASE.reg(CTS1))];
+    for (name, reg) in regs {
+        let val = unsafe { reg.read_volatile() };
+        println!("{name:>9} (0x{:X}): 0x{val:08X}", reg as usize);
+    }
+}
+
+/// Stops playing audio if it's playing.
+///
+/// * `audio`: Locked playback state.
+fn stop(audio: &mut Audio)
+{
+    let Some(buf) = audio.buf.take() else {
+        return;
+    };
+    unsafe { stop_sender() };
+    audio.stream = None;
+    drop(buf.from_device());
+}
+
+/// Synthesizes a square wave tone to each of the stereo channels.
+///
+/// * `buf`: Buffer to fill with interleaved IEC958 subframes.
+/// * `tone`: Tone generator settings.
+fn synthesize(buf: &mut [u32], tone: Tone)
+{
+    // Amplitude of the square waves as a signed 16 bit value, with the negative
+    // phase computed in two's complement.
+    let amplitude = 0x7FFF * tone.volume / MAX_VOLUME;
     for (idx, output) in buf.iter_mut().enumerate() {
         // We're dealing with twice as many frames here since we are synthesizing for
         // two channels one at a time, so the math must take that into account.
-        let halfperiod = if idx & 0x1 == 0 {
-            SAMPLE_RATE as usize / 200
-        } else {
-            SAMPLE_RATE as usize / 300
-        };
+        let halfperiod = (SAMPLE_RATE / tone.freqs[idx & 0x1]) as usize;
         let sample = if (idx / halfperiod) & 0x1 == 1 {
             // Positive phase.
-            0x3FFF
+            amplitude
         } else {
             // Negative phase.
-            0x0C000
-        };
-        let blockidx = idx % (192 * 2);
-        // Mark B subframes according to the configuration in the AU_PKTCFG register.
-        let preamble = ((blockidx == 0) as u32) << 3;
-        let byte = blockidx >> 4;
-        let bit = (blockidx >> 1) & 0x7;
-        let cs = if blockidx == 16 * 2 + 1 || blockidx == 20 * 2 + 1 {
-            // Nibbles 4 and 5 of channel status contain the source and destination channel
-            // indices. Channel 0 has index 0 so nothing needs to be done, but channel 1
-            // must have its index bits set appropriately.
-            0x1
-        } else {
-            (cs[byte] >> bit) & 0x1
-        };
-        *output = bits! {
-            // 8 * 24 channel status bits spread across 192 subframes per channel.
-            30 => cs,
-            // Signed 16 bit audio sample.
-            12 ..= 27 => sample,
-            // Preamble.
-            0 ..= 3 => preamble,
+            !amplitude & 0xFFFF
         };
+        *output = encode(idx, sample);
     }
     fence(Ordering::Release);
 }
+
+/// Encodes a sample as an IEC958 subframe.
+///
+/// * `idx`: Index of the subframe in the stream, with the subframes of both
+///   channels interleaved.
+/// * `sample`: Signed 16 bit audio sample.
+///
+/// Returns the encoded subframe.
+fn encode(idx: usize, sample: u32) -> u32
+{
+    // First bytes of the 192 channel status bits, with the rest being zero.
+    const CHANNEL_STATUS: [u32; 5] = [0x4,  // SPDIF, PCM, no copyright, no emphasis.
+                                      0x44, // Software broadcast.
+                                      0x0,  // Channel (to fill in later).
+                                      0x2,  // 48000Hz, 1000ppm.
+                                      0xD2  /* 16 bit sample size, 48000Hz original frequency. */];
+    let blockidx = idx % (192 * 2);
+    // Mark B subframes according to the configuration in the AU_PKTCFG register.
+    let preamble = ((blockidx == 0) as u32) << 3;
+    let byte = blockidx >> 4;
+    let bit = (blockidx >> 1) & 0x7;
+    let cs = if blockidx == 16 * 2 + 1 || blockidx == 20 * 2 + 1 {
+        // Nibbles 4 and 5 of channel status contain the source and destination channel
+        // indices. Channel 0 has index 0 so nothing needs to be done, but channel 1
+        // must have its index bits set appropriately.
+        0x1
+    } else {
+        CHANNEL_STATUS.get(byte).map_or(0, |cs| (cs >> bit) & 0x1)
+    };
+    bits! {
+        // 8 * 24 channel status bits spread across 192 subframes per channel.
+        30 => cs,
+        // Signed 16 bit audio sample.
+        12 ..= 27 => sample,
+        // Preamble.
+        0 ..= 3 => preamble,
+    }
+}
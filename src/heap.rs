@@ -0,0 +1,213 @@
+//! Heap allocators.
+//!
+//! Implements a first fit allocator that tracks free memory in a list of
+//! blocks sorted by address, whose headers are stored in the free memory
+//! itself, and coalesces adjacent blocks when memory is freed.  The global
+//! allocator uses it to manage a cached memory region reserved by the linker
+//! script, locking it so that it can be used from any core and from interrupt
+//! handlers.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{addr_of, addr_of_mut, null_mut, NonNull};
+
+use crate::sync::{Once, SpinLock};
+
+/// Allocation granularity, which must be able to hold a free block header.
+const GRANULE: usize = 16;
+
+extern "C" {
+    /// Start of the cached heap region, defined by the linker script.
+    static cached_heap_start: u8;
+    /// End of the cached heap region, defined by the linker script.
+    static cached_heap_end: u8;
+}
+
+/// Free list allocator.
+pub struct Heap
+{
+    /// First free block.
+    free: *mut FreeBlock,
+    /// Start address of the managed region.
+    start: usize,
+    /// End address of the managed region.
+    end: usize,
+}
+
+/// Header of a free memory block.
+#[repr(C)]
+struct FreeBlock
+{
+    /// Size of this block in bytes, including the header.
+    size: usize,
+    /// Next free block in ascending address order.
+    next: *mut FreeBlock,
+}
+
+/// Global allocator backed by the cached heap region.
+struct GlobalHeap;
+
+/// Cached heap.
+static HEAP: SpinLock<Heap> = SpinLock::new(Heap::empty());
+/// Initialization of the cached heap.
+static INIT: Once<()> = Once::new();
+
+#[global_allocator]
+static GLOBAL: GlobalHeap = GlobalHeap;
+
+// The heap owns the free memory it tracks, so it can be handed to another core.
+unsafe impl Send for Heap {}
+
+impl Heap
+{
+    /// Creates a new allocator with no memory to manage.
+    ///
+    /// Returns the newly created allocator.
+    pub const fn empty() -> Self
+    {
+        Self { free: null_mut(),
+               start: 0,
+               end: 0 }
+    }
+
+    /// Hands a memory region over to this allocator, discarding any previous
+    /// allocations.
+    ///
+    /// * `start`: Start address of the region.
+    /// * `end`: End address of the region.
+    ///
+    /// Panics if the region is empty or its bounds are not aligned to the
+    /// allocation granularity.
+    ///
+    /// # Safety
+    ///
+    /// The region must be mapped, writable, and not used for anything else.
+    #[track_caller]
+    pub unsafe fn init(&mut self, start: usize, end: usize)
+    {
+        assert!(start < end && start & (GRANULE - 1) == 0 && end & (GRANULE - 1) == 0,
+                "Invalid heap region: 0x{start:X} - 0x{end:X}");
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size: end - start,
+                                next: null_mut() });
+        self.free = block;
+        self.start = start;
+        self.end = end;
+    }
+
+    /// Allocates memory with the specified layout.
+    ///
+    /// * `layout`: Size and alignment requirements of the allocation.
+    ///
+    /// Returns a pointer to the uninitialized memory, or `None` if there isn't
+    /// a large enough free block.
+    pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>>
+    {
+        let align = layout.align().max(GRANULE);
+        let size = layout.size().max(1).checked_add(GRANULE - 1)? & !(GRANULE - 1);
+        unsafe {
+            let mut link = addr_of_mut!(self.free);
+            while !(*link).is_null() {
+                let block = *link;
+                let start = block as usize;
+                let end = start + (*block).size;
+                let base = (start + align - 1) & !(align - 1);
+                if base.checked_add(size).is_some_and(|top| top <= end) {
+                    // Return the unused memory after the allocation to the free list.
+                    let top = base + size;
+                    let mut next = (*block).next;
+                    if top != end {
+                        let tail = top as *mut FreeBlock;
+                        tail.write(FreeBlock { size: end - top, next });
+                        next = tail;
+                    }
+                    // Keep the unused memory before the allocation in the free list if the
+                    // alignment requirements left a gap.
+                    if base != start {
+                        (*block).size = base - start;
+                        (*block).next = next;
+                    } else {
+                        *link = next;
+                    }
+                    return NonNull::new(base as *mut u8);
+                }
+                link = addr_of_mut!((*block).next);
+            }
+        }
+        None
+    }
+
+    /// Frees memory previously allocated with the specified layout.
+    ///
+    /// * `ptr`: Pointer to the memory to free.
+    /// * `layout`: Layout used to allocate the memory.
+    ///
+    /// Panics if the memory is outside the managed region or overlaps memory
+    /// that is already free.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been allocated by this allocator with the same
+    /// layout and must no longer be in use.
+    #[track_caller]
+    pub unsafe fn free(&mut self, ptr: NonNull<u8>, layout: Layout)
+    {
+        let start = ptr.as_ptr() as usize;
+        let size = (layout.size().max(1) + GRANULE - 1) & !(GRANULE - 1);
+        assert!(start >= self.start && start <= self.end - size && start & (GRANULE - 1) == 0,
+                "Attempted to free memory outside the heap region: 0x{start:X}");
+        // Find the free blocks immediately before and after the memory being freed.
+        let mut prev = null_mut::<FreeBlock>();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+        assert!((prev.is_null() || prev as usize + (*prev).size <= start)
+                && (next.is_null() || start + size <= next as usize),
+                "Attempted to free memory that is already free: 0x{start:X}");
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.free = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+impl GlobalHeap
+{
+    /// Hands the cached heap region over to the allocator the first time this
+    /// function is called.
+    fn init_or_nop()
+    {
+        INIT.call_once(|| unsafe {
+                let start = addr_of!(cached_heap_start) as usize;
+                let end = addr_of!(cached_heap_end) as usize;
+                HEAP.lock_irq().init(start, end);
+            });
+    }
+}
+
+unsafe impl GlobalAlloc for GlobalHeap
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        Self::init_or_nop();
+        HEAP.lock_irq().alloc(layout).map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        if let Some(ptr) = NonNull::new(ptr) {
+            HEAP.lock_irq().free(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! GIC-400 interrupt controller driver and IRQ dispatch.
+//!
+//! Interrupts are routed to the boot core as level triggered group 1
+//! interrupts, acknowledged through the CPU interface by the IRQ vector in
+//! `boot.s`, and dispatched to the handlers registered for them with all the
+//! registers of the interrupted code saved in a trap frame.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::board::irq::{GICC_BASE, GICD_BASE};
+use crate::exception::TrapFrame;
+use crate::sync::SpinLock;
+use crate::{debug, warn};
+
+/// Distributor control register.
+const GICD_CTLR: *mut u32 = GICD_BASE as _;
+/// Interrupt controller type register.
+const GICD_TYPER: *const u32 = (GICD_BASE + 0x4) as _;
+/// Interrupt set enable registers.
+const GICD_ISENABLER: *mut u32 = (GICD_BASE + 0x100) as _;
+/// Interrupt clear enable registers.
+const GICD_ICENABLER: *mut u32 = (GICD_BASE + 0x180) as _;
+/// Interrupt clear pending registers.
+const GICD_ICPENDR: *mut u32 = (GICD_BASE + 0x280) as _;
+/// Interrupt clear active registers.
+const GICD_ICACTIVER: *mut u32 = (GICD_BASE + 0x380) as _;
+/// Interrupt priority registers, one byte per interrupt.
+const GICD_IPRIORITYR: *mut u8 = (GICD_BASE + 0x400) as _;
+/// Interrupt processor targets registers, one byte per interrupt.
+const GICD_ITARGETSR: *mut u8 = (GICD_BASE + 0x800) as _;
+/// Interrupt configuration registers, two bits per interrupt.
+const GICD_ICFGR: *mut u32 = (GICD_BASE + 0xC00) as _;
+/// CPU interface control register.
+const GICC_CTLR: *mut u32 = GICC_BASE as _;
+/// Interrupt priority mask register.
+const GICC_PMR: *mut u32 = (GICC_BASE + 0x4) as _;
+/// Binary point register.
+const GICC_BPR: *mut u32 = (GICC_BASE + 0x8) as _;
+/// Interrupt acknowledge register.
+const GICC_IAR: *const u32 = (GICC_BASE + 0xC) as _;
+/// End of interrupt register.
+const GICC_EOIR: *mut u32 = (GICC_BASE + 0x10) as _;
+/// First shared peripheral interrupt ID.
+const FIRST_SPI: u32 = 32;
+/// Number of interrupt IDs for which handlers can be registered.
+const MAX_IRQS: usize = 512;
+/// Interrupt IDs at or above this value are special, signaling spurious
+/// interrupts.
+const SPECIAL_IDS: u32 = 1020;
+/// Priority given to every interrupt.
+const PRIORITY: u8 = 0xA0;
+/// Priority mask that lets every interrupt through.
+const PRIORITY_MASK: u32 = 0xFF;
+/// Target mask selecting the boot core.
+const BOOT_CORE: u8 = 0x1;
+/// Enable bit of the distributor and CPU interface control registers.
+const ENABLE: u32 = 0x1;
+/// IRQ mask bit of the DAIF register.
+const DAIF_IRQ: usize = 0x80;
+
+/// Interrupt handlers indexed by interrupt ID.
+type Handlers = [Option<fn()>; MAX_IRQS];
+
+/// Number of interrupt lines implemented by the distributor, or zero if the
+/// driver is not initialized.
+static LINES: AtomicU32 = AtomicU32::new(0);
+/// Registered handlers indexed by interrupt ID.
+static HANDLERS: SpinLock<Handlers> = SpinLock::new([None; MAX_IRQS]);
+
+/// Initializes the distributor and the boot core's CPU interface with every
+/// interrupt disabled, discarding whatever a previous kernel left pending.
+///
+/// IRQs remain masked on the core until [`enable`] is called.
+pub fn init()
+{
+    let lines = unsafe {
+        GICD_CTLR.write_volatile(0);
+        let lines = (((GICD_TYPER.read_volatile() & 0x1F) + 1) * 32).min(MAX_IRQS as u32);
+        for reg in 0 .. lines as usize / 32 {
+            GICD_ICENABLER.add(reg).write_volatile(u32::MAX);
+            GICD_ICPENDR.add(reg).write_volatile(u32::MAX);
+            GICD_ICACTIVER.add(reg).write_volatile(u32::MAX);
+        }
+        for id in 0 .. lines as usize {
+            GICD_IPRIORITYR.add(id).write_volatile(PRIORITY);
+        }
+        // The target registers of the private interrupts are read only.
+        for id in FIRST_SPI as usize .. lines as usize {
+            GICD_ITARGETSR.add(id).write_volatile(BOOT_CORE);
+        }
+        // All shared peripheral interrupts are level triggered.
+        for reg in FIRST_SPI as usize / 16 .. lines as usize / 16 {
+            GICD_ICFGR.add(reg).write_volatile(0);
+        }
+        GICD_CTLR.write_volatile(ENABLE);
+        lines
+    };
+    LINES.store(lines, Ordering::Release);
+    init_core();
+    debug!("Initialized the interrupt controller with {lines} lines");
+}
+
+/// Initializes the CPU interface of the current core, which is banked per
+/// core, so that it can take the private interrupts enabled on it.
+///
+/// IRQs remain masked on the core until [`enable`] is called.
+pub fn init_core()
+{
+    unsafe {
+        GICC_PMR.write_volatile(PRIORITY_MASK);
+        GICC_BPR.write_volatile(0);
+        GICC_CTLR.write_volatile(ENABLE);
+    }
+}
+
+/// Installs the handler for an interrupt and enables it in the distributor.
+///
+/// * `id`: Interrupt ID.
+/// * `handler`: Function called with IRQs masked whenever the interrupt is
+///   raised, which must clear the condition that raised it.
+///
+/// Panics if the driver is not initialized, the interrupt ID is not
+/// implemented, or a handler is already installed for it.
+#[track_caller]
+pub fn register(id: u32, handler: fn())
+{
+    let lines = LINES.load(Ordering::Acquire);
+    assert!(id < lines, "Interrupt ID {id} is not implemented");
+    {
+        let mut handlers = HANDLERS.lock_irq();
+        let slot = &mut handlers[id as usize];
+        assert!(slot.is_none(), "Interrupt ID {id} already has a handler");
+        *slot = Some(handler);
+        unsafe { GICD_ISENABLER.add(id as usize / 32).write_volatile(1 << (id % 32)) };
+    }
+    debug!("Registered a handler for interrupt ID {id}");
+}
+
+/// Disables an interrupt in the distributor and removes its handler, if any.
+///
+/// * `id`: Interrupt ID.
+///
+/// Panics if the interrupt ID is not implemented.
+#[track_caller]
+pub fn unregister(id: u32)
+{
+    let lines = LINES.load(Ordering::Acquire);
+    assert!(id < lines, "Interrupt ID {id} is not implemented");
+    let mut handlers = HANDLERS.lock_irq();
+    unsafe { GICD_ICENABLER.add(id as usize / 32).write_volatile(1 << (id % 32)) };
+    handlers[id as usize] = None;
+}
+
+/// Unmasks IRQs on the current core.
+pub fn enable()
+{
+    unsafe { asm!("msr daifclr, #0x2", options(nostack, preserves_flags)) };
+}
+
+/// Masks IRQs on the current core.
+pub fn disable()
+{
+    unsafe { asm!("msr daifset, #0x2", options(nostack, preserves_flags)) };
+}
+
+/// Returns whether IRQs are unmasked on the current core.
+pub fn is_enabled() -> bool
+{
+    let daif: usize;
+    unsafe {
+        asm!(
+            "mrs {daif}, daif",
+            daif = out (reg) daif,
+            options (nomem, nostack, preserves_flags));
+    }
+    daif & DAIF_IRQ == 0
+}
+
+/// Acknowledges and dispatches pending interrupts, called by the IRQ vector.
+///
+/// * `frame`: Registers of the interrupted code.
+#[no_mangle]
+extern "C" fn irq(_frame: &mut TrapFrame)
+{
+    loop {
+        let iar = unsafe { GICC_IAR.read_volatile() };
+        let id = iar & 0x3FF;
+        if id >= SPECIAL_IDS {
+            break;
+        }
+        // IRQs are already masked, and the handler might register other handlers.
+        let handler = HANDLERS.lock().get(id as usize).copied().flatten();
+        match handler {
+            Some(handler) => handler(),
+            None => {
+                // Disable the interrupt so that it doesn't keep firing.
+                unsafe { GICD_ICENABLER.add(id as usize / 32).write_volatile(1 << (id % 32)) };
+                warn!("Disabled interrupt ID {id}, which has no handler");
+            }
+        }
+        unsafe { GICC_EOIR.write_volatile(iar) };
+    }
+}
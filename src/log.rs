@@ -0,0 +1,535 @@
+//! Leveled logging.
+//!
+//! Records carry a severity level, the path of the module that emitted them and
+//! a timestamp taken from the generic timer, and are delivered to every enabled
+//! sink.  Records more verbose than the level configured for their module are
+//! discarded before being formatted, so detailed driver tracing can remain
+//! compiled in and be switched on at runtime.
+//!
+//! Records are written with the sinks locked, so records logged concurrently
+//! by different cores don't get mixed up, and records logged by the sinks
+//! themselves while writing are discarded.
+
+use core::fmt::{self, Arguments, Display, Formatter, Write};
+use core::ptr::addr_of_mut;
+use core::str::{from_utf8, FromStr};
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+
+use crate::console::Console;
+use crate::ring::Ring;
+use crate::sync::{Once, SpinLock};
+use crate::uart::Uart;
+use crate::{println, timer};
+
+/// Maximum number of per module filters.
+const MAX_FILTERS: usize = 8;
+/// Maximum length of a filtered module path.
+const MAX_PATH_LEN: usize = 32;
+/// Maximum number of sinks.
+const MAX_SINKS: usize = 4;
+/// Capacity of the memory sink in bytes.
+const HISTORY_LEN: usize = 16384;
+/// Number of bytes of history copied at a time while printing it.
+const HISTORY_CHUNK_LEN: usize = 64;
+
+/// Registered sinks along with whether they are enabled.
+type Sinks = [Option<(&'static mut dyn Sink, bool)>; MAX_SINKS];
+
+/// Registration of the built-in sinks.
+static INIT: Once<()> = Once::new();
+/// Configured levels.
+static LEVELS: SpinLock<Levels> = SpinLock::new(Levels { default: Some(Level::Info),
+                                                         filters: [None; MAX_FILTERS] });
+/// Most verbose level enabled for any module, or zero if disabled, used to
+/// discard records without locking the filters.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// Registered sinks along with whether they are enabled.
+static SINKS: SpinLock<Sinks> = SpinLock::new([const { None }; MAX_SINKS]);
+/// Records kept by the memory sink.
+static HISTORY: SpinLock<Ring<u8, HISTORY_LEN>> = SpinLock::new(Ring::new(0));
+/// Sink that writes to the UART.
+static mut UART_SINK: UartSink = UartSink;
+/// Sink that writes to the framebuffer console.
+static mut CONSOLE_SINK: ConsoleSink = ConsoleSink;
+/// Sink that keeps the most recent records in memory.
+static mut MEMORY_SINK: MemorySink = MemorySink;
+
+/// Record severity, from most to least severe.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum Level
+{
+    /// Failures.
+    Error = 1,
+    /// Unexpected conditions that can be recovered from.
+    Warn = 2,
+    /// Major events.
+    Info = 3,
+    /// Details useful for debugging.
+    Debug = 4,
+    /// Very detailed tracing.
+    Trace = 5,
+}
+
+/// Log record.
+#[derive(Clone, Copy, Debug)]
+pub struct Record<'a>
+{
+    /// Severity.
+    pub level: Level,
+    /// Path of the emitting module, without the crate name.
+    pub module: &'a str,
+    /// Time since boot.
+    pub time: Duration,
+    /// Message.
+    pub args: Arguments<'a>,
+}
+
+/// Log record destination.
+pub trait Sink: Send
+{
+    /// Returns the name used to refer to this sink.
+    fn name(&self) -> &'static str;
+
+    /// Prepares this sink to receive records when it gets enabled.
+    ///
+    /// Returns whether the sink can be enabled.
+    fn enable(&mut self) -> bool
+    {
+        true
+    }
+
+    /// Outputs a record.
+    ///
+    /// * `record`: Record to output.
+    fn write(&mut self, record: &Record);
+}
+
+/// Sink that writes to the UART.
+struct UartSink;
+
+/// Sink that writes to the framebuffer console.
+struct ConsoleSink;
+
+/// Sink that keeps the most recent records in memory, discarding the oldest
+/// records as needed to make room for new ones.
+struct MemorySink;
+
+/// Configured levels.
+#[derive(Clone, Copy, Debug)]
+struct Levels
+{
+    /// Level of the modules not covered by any filter, or `None` if disabled.
+    default: Option<Level>,
+    /// Per module filters.
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+/// Displays an optional level, with `None` meaning disabled.
+struct LevelName(Option<Level>);
+
+/// Per module filter.
+#[derive(Clone, Copy, Debug)]
+struct Filter
+{
+    /// Module path.
+    path: [u8; MAX_PATH_LEN],
+    /// Length of the module path.
+    len: usize,
+    /// Level of the module and its descendants, or `None` if disabled.
+    level: Option<Level>,
+}
+
+/// Sends a formatted record to the enabled sinks if its level is enabled for
+/// the current module.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::log::enabled(level, module_path!()) {
+            $crate::log::write(level, module_path!(), format_args!($($arg)*));
+        }
+    }};
+}
+
+/// Logs a formatted record at the error level.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::Level::Error, $($arg)*)
+    };
+}
+
+/// Logs a formatted record at the warning level.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::Level::Warn, $($arg)*)
+    };
+}
+
+/// Logs a formatted record at the information level.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::Level::Info, $($arg)*)
+    };
+}
+
+/// Logs a formatted record at the debug level.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::Level::Debug, $($arg)*)
+    };
+}
+
+/// Logs a formatted record at the trace level.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::Level::Trace, $($arg)*)
+    };
+}
+
+/// Returns whether records of the specified level are enabled for a module.
+///
+/// * `level`: Record level.
+/// * `module`: Full path of the module.
+pub fn enabled(level: Level, module: &str) -> bool
+{
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+    && Some(level) <= LEVELS.lock_irq().module_level(strip_crate(module))
+}
+
+/// Sends a record to the enabled sinks regardless of the configured levels.
+///
+/// * `level`: Record level.
+/// * `module`: Full path of the emitting module.
+/// * `args`: Message.
+pub fn write(level: Level, module: &str, args: Arguments)
+{
+    init_or_nop();
+    // Records logged while writing to the sinks would deadlock.
+    if SINKS.is_held_here() {
+        return;
+    }
+    let record = Record { level,
+                          module: strip_crate(module),
+                          time: timer::uptime(),
+                          args };
+    for (sink, enabled) in SINKS.lock_irq().iter_mut().flatten() {
+        if *enabled {
+            sink.write(&record);
+        }
+    }
+}
+
+/// Changes the level of a module and its descendants, or the default level.
+///
+/// * `module`: Module path without the crate name, or `None` to change the
+///   default level.
+/// * `level`: New level, or `None` to disable logging.
+///
+/// Returns an error if the path is too long or there are no free filters.
+pub fn set_level(module: Option<&str>, level: Option<Level>) -> Result<(), &'static str>
+{
+    let mut levels = LEVELS.lock_irq();
+    match module {
+        None => levels.default = level,
+        Some(path) => {
+            if path.len() > MAX_PATH_LEN {
+                return Err("Module path is too long");
+            }
+            let filters = &mut levels.filters;
+            let slot = match filters.iter()
+                                    .position(|filter| filter.is_some_and(|filter| filter.path() == path))
+            {
+                Some(idx) => &mut filters[idx],
+                None => filters.iter_mut()
+                               .find(|filter| filter.is_none())
+                               .ok_or("Too many module filters")?,
+            };
+            let mut filter = Filter { path: [0; MAX_PATH_LEN],
+                                      len: path.len(),
+                                      level };
+            filter.path[.. path.len()].copy_from_slice(path.as_bytes());
+            *slot = Some(filter);
+        }
+    }
+    let max_level = levels.filters
+                          .iter()
+                          .flatten()
+                          .map(|filter| filter.level)
+                          .fold(levels.default, Option::max);
+    MAX_LEVEL.store(max_level.map_or(0, |level| level as u8), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Registers a sink.
+///
+/// * `sink`: Sink to register.
+/// * `enabled`: Whether the sink starts out enabled.
+///
+/// Panics if there is no room for more sinks.
+#[track_caller]
+pub fn add_sink(sink: &'static mut dyn Sink, enabled: bool)
+{
+    init_or_nop();
+    let enabled = enabled && sink.enable();
+    let mut sinks = SINKS.lock_irq();
+    let slot = sinks.iter_mut()
+                    .find(|slot| slot.is_none())
+                    .expect("Too many log sinks");
+    *slot = Some((sink, enabled));
+}
+
+/// Enables or disables a sink.
+///
+/// * `name`: Name of the sink.
+/// * `enabled`: Whether to enable the sink.
+///
+/// Returns an error if there is no sink with the specified name or it can't
+/// be enabled.
+pub fn set_sink_enabled(name: &str, enabled: bool) -> Result<(), &'static str>
+{
+    init_or_nop();
+    let mut sinks = SINKS.lock_irq();
+    let (sink, state) = sinks.iter_mut()
+                             .flatten()
+                             .find(|(sink, _)| sink.name() == name)
+                             .ok_or("Unknown log sink")?;
+    if enabled && !*state && !sink.enable() {
+        return Err("Log sink is not available");
+    }
+    *state = enabled;
+    Ok(())
+}
+
+/// Prints the configured levels and sinks.
+pub fn dump_config()
+{
+    init_or_nop();
+    // Printing might log, so nothing can be locked while doing it.
+    let levels = *LEVELS.lock_irq();
+    let mut sinks = [None; MAX_SINKS];
+    for (slot, (sink, enabled)) in sinks.iter_mut().zip(SINKS.lock_irq().iter().flatten()) {
+        *slot = Some((sink.name(), *enabled));
+    }
+    println!("Default level: {}", LevelName(levels.default));
+    for filter in levels.filters.iter().flatten() {
+        println!("Level of {}: {}", filter.path(), LevelName(filter.level));
+    }
+    for (name, enabled) in sinks.iter().flatten() {
+        println!("Sink {name}: {}", if *enabled { "on" } else { "off" });
+    }
+}
+
+/// Prints the records kept by the memory sink.
+pub fn dump_history()
+{
+    // Printing might log, so the history is copied out a chunk at a time, and
+    // any records added in the meantime are printed as well.
+    let mut pos = {
+        let history = HISTORY.lock_irq();
+        // Skip the remains of a record that was partially discarded.
+        if history.is_full() {
+            history.iter().position(|byte| byte == b'\n').map_or(0, |idx| idx + 1)
+        } else {
+            0
+        }
+    };
+    loop {
+        let mut chunk = [0; HISTORY_CHUNK_LEN];
+        let mut len = 0;
+        for (byte, slot) in HISTORY.lock_irq().iter().skip(pos).zip(chunk.iter_mut()) {
+            *slot = byte;
+            len += 1;
+        }
+        if len == 0 {
+            break;
+        }
+        pos += len;
+        for byte in chunk[.. len].iter().copied() {
+            if byte == b'\n' {
+                Uart.write_bytes(b"\r");
+            }
+            Uart.write_bytes(&[byte]);
+        }
+    }
+}
+
+/// Registers the built-in sinks if that hasn't been done yet.
+fn init_or_nop()
+{
+    // Registering the sinks calls this again, which does nothing while the
+    // registration is in progress on the same core.
+    INIT.call_once(|| unsafe {
+            add_sink(&mut *addr_of_mut!(UART_SINK), true);
+            add_sink(&mut *addr_of_mut!(MEMORY_SINK), true);
+            add_sink(&mut *addr_of_mut!(CONSOLE_SINK), false);
+        });
+}
+
+/// Removes the crate name from a module path.
+///
+/// * `module`: Full module path.
+///
+/// Returns the module path relative to the crate root, or the crate name for
+/// the crate root itself.
+fn strip_crate(module: &str) -> &str
+{
+    module.split_once("::").map_or(module, |(_, path)| path)
+}
+
+impl Levels
+{
+    /// Returns the level of a module.
+    ///
+    /// * `module`: Module path without the crate name.
+    fn module_level(&self, module: &str) -> Option<Level>
+    {
+        // The most specific filter wins.
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(module))
+            .max_by_key(|filter| filter.len)
+            .map_or(self.default, |filter| filter.level)
+    }
+}
+
+impl Filter
+{
+    /// Returns the module path.
+    fn path(&self) -> &str
+    {
+        from_utf8(&self.path[.. self.len]).unwrap()
+    }
+
+    /// Returns whether this filter covers a module.
+    ///
+    /// * `module`: Module path without the crate name.
+    fn matches(&self, module: &str) -> bool
+    {
+        module.strip_prefix(self.path())
+              .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+impl Display for Level
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        let name = match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        };
+        fmt.pad(name)
+    }
+}
+
+impl FromStr for Level
+{
+    type Err = &'static str;
+
+    fn from_str(name: &str) -> Result<Self, &'static str>
+    {
+        match name {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err("Unknown log level"),
+        }
+    }
+}
+
+impl Display for Record<'_>
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        write!(fmt,
+               "[{:>5}.{:06}] {:<5} {}: {}",
+               self.time.as_secs(),
+               self.time.subsec_micros(),
+               self.level,
+               self.module,
+               self.args)
+    }
+}
+
+impl Sink for UartSink
+{
+    fn name(&self) -> &'static str
+    {
+        "uart"
+    }
+
+    fn write(&mut self, record: &Record)
+    {
+        let _ = write!(Uart, "{record}\r\n");
+    }
+}
+
+impl Sink for ConsoleSink
+{
+    fn name(&self) -> &'static str
+    {
+        "console"
+    }
+
+    fn enable(&mut self) -> bool
+    {
+        Console::is_available() || Console::init()
+    }
+
+    fn write(&mut self, record: &Record)
+    {
+        let _ = writeln!(Console, "{record}");
+    }
+}
+
+impl Sink for MemorySink
+{
+    fn name(&self) -> &'static str
+    {
+        "memory"
+    }
+
+    fn write(&mut self, record: &Record)
+    {
+        let _ = writeln!(self, "{record}");
+    }
+}
+
+impl Write for MemorySink
+{
+    fn write_str(&mut self, msg: &str) -> fmt::Result
+    {
+        let mut history = HISTORY.lock_irq();
+        for byte in msg.bytes() {
+            if history.is_full() {
+                history.pop();
+            }
+            history.push(byte);
+        }
+        Ok(())
+    }
+}
+
+impl Display for LevelName
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        match self.0 {
+            Some(level) => level.fmt(fmt),
+            None => fmt.pad("off"),
+        }
+    }
+}
@@ -2,20 +2,50 @@
 #![no_main]
 #![feature(panic_info_message)]
 
+extern crate alloc;
+
+mod board;
+mod chainload;
+mod console;
+mod crashlog;
 mod dma;
+mod dmabuf;
+mod exception;
+mod executor;
+mod fdt;
+mod font;
 mod hdmi;
+mod heap;
+mod irq;
+mod log;
 mod mbox;
+mod mmu;
+mod monitor;
+mod pm;
+mod proto;
+mod ring;
 mod scalloc;
+mod shell;
+mod smp;
+mod stream;
+mod symbols;
+mod sync;
+mod timer;
 mod uart;
 
 use core::alloc::Layout;
 use core::arch::{asm, global_asm};
-use core::fmt::Write;
+use core::fmt::{self, Write};
 use core::mem::size_of_val;
 use core::panic::PanicInfo;
 use core::sync::atomic::{fence, Ordering};
 
-use self::uart::Uart;
+use self::crashlog::CrashLog;
+use self::mbox::Mailbox;
+use self::uart::{DirectUart, FifoLevel, Uart};
+
+/// Get VideoCore memory property tag.
+const GET_VC_MEMORY_TAG: u32 = 0x10006;
 
 /// Properly sized and aligned structure to temporarily store the contents of a
 /// cache line.
@@ -26,64 +56,48 @@ struct CacheLine
     data: [u8; 64],
 }
 
-global_asm!(include_str!("boot.s"));
+global_asm!(include_str!("boot.s"), core_shift = const board::smp::CORE_SHIFT);
 
 /// Entry point.
+///
+/// * `dtb`: Physical address of the device tree blob passed by the firmware, or
+///   0 if there's none.
 #[no_mangle]
-pub extern "C" fn start() -> !
-{
-    println!("Starting");
-    hdmi::init();
-    halt()
-}
-
-/// Panics with diagnostic information about a fault.
-#[no_mangle]
-pub extern "C" fn fault(kind: usize) -> !
+pub extern "C" fn start(dtb: usize) -> !
 {
-    let level: usize;
-    let syndrome: usize;
-    let addr: usize;
-    let ret: usize;
-    let state: usize;
-    unsafe {
-        asm!(
-            "mrs {el}, currentel",
-            "lsr {el}, {el}, #2",
-            el = out (reg) level,
-            options (nomem, nostack, preserves_flags));
-        match level {
-            2 => asm!(
-                    "mrs {synd}, esr_el2",
-                    "mrs {addr}, far_el2",
-                    "mrs {ret}, elr_el2",
-                    "mrs {state}, spsr_el2",
-                    synd = out (reg) syndrome,
-                    addr = out (reg) addr,
-                    ret = out (reg) ret,
-                    state = out (reg) state,
-                    options (nomem, nostack, preserves_flags)),
-            1 => asm!(
-                    "mrs {synd}, esr_el1",
-                    "mrs {addr}, far_el1",
-                    "mrs {ret}, elr_el1",
-                    "mrs {state}, spsr_el1",
-                    synd = out (reg) syndrome,
-                    addr = out (reg) addr,
-                    ret = out (reg) ret,
-                    state = out (reg) state,
-                    options (nomem, nostack, preserves_flags)),
-            _ => panic!("Exception caught at unsupported level {level}"),
-        }
-    };
-    panic!("Triggered an exception at level {level}: Kind: 0x{kind:x}, Syndrome: 0x{syndrome:x}, Address: 0x{addr:x}, Location: 0x{ret:x}, State: 0x{state:x}");
+    info!("Starting on the {}", board::NAME);
+    CrashLog::report();
+    if let Some(fdt) = fdt::init(dtb) {
+        Mailbox.probe(fdt);
+        Uart.probe(fdt);
+        dma::probe(fdt);
+        hdmi::probe(fdt);
+    }
+    let mut vc_memory = [0u32; 2];
+    match mbox! {GET_VC_MEMORY_TAG: _ => vc_memory} {
+        Ok(()) => mmu::map_vc_memory(board::mbox::phys_address(vc_memory[0] as usize), vc_memory[1] as usize),
+        Err(err) => error!("Failed to query the VideoCore memory: {err}"),
+    }
+    irq::init();
+    irq::register(Uart::irq(), Uart::handle_interrupt);
+    irq::register(dma::irq(), dma::handle_interrupt);
+    Uart.enable_interrupts(FifoLevel::OneQuarter, FifoLevel::Half);
+    irq::enable();
+    if let Err(err) = hdmi::init() {
+        error!("HDMI initialization failed: {err}");
+    }
+    executor::spawn(hdmi::feed_stream());
+    executor::spawn(monitor::run());
+    executor::spawn(shell::run());
+    executor::run()
 }
 
-/// Halts the system.
+/// Halts the current core.
 #[no_mangle]
 pub extern "C" fn halt() -> !
 {
-    println!("Halted");
+    // Halting can't rely on any lock being available.
+    let _ = DirectUart.write_str("Halted\r\n");
     unsafe {
         asm!("msr daifset, #0x3",
              "0:",
@@ -93,22 +107,35 @@ pub extern "C" fn halt() -> !
     }
 }
 
-/// Halts the system with a diagnostic error message.
+/// Halts the system with a diagnostic error message and backtrace, which are
+/// also kept in the crash log.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> !
+{
+    irq::disable();
+    let _ = describe_panic(CrashLog::get(), info);
+    let _ = describe_panic(&mut DirectUart::take_over(), info);
+    halt();
+}
+
+/// Writes a diagnostic message describing a panic, followed by a backtrace.
+///
+/// * `out`: Destination of the message.
+/// * `info`: Panic information.
+fn describe_panic(out: &mut impl Write, info: &PanicInfo) -> fmt::Result
 {
     if let Some(location) = info.location() {
-        print!("Panicked at {}:{}: ", location.file(), location.line());
+        write!(out, "Panicked at {}:{}: ", location.file(), location.line())?;
     } else {
-        print!("Panic: ");
+        out.write_str("Panic: ")?;
     }
     if let Some(args) = info.message() {
-        Uart.write_fmt(*args).unwrap()
+        out.write_fmt(*args)?;
     } else {
-        Uart.write_str("Unknown reason").unwrap()
+        out.write_str("Unknown reason")?;
     }
-    Uart.write_str("\r\n").unwrap();
-    halt();
+    out.write_str("\r\n")?;
+    exception::write_backtrace(out)
 }
 
 /// Invalidates the cache associated with the specified data to point of
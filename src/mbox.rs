@@ -1,20 +1,27 @@
 //! Video core mailbox interface.
 
-#![allow(dead_code)]
-
 use core::cmp::max;
-use core::hint::spin_loop;
-use core::mem::{align_of, size_of, size_of_val};
+use core::mem::{align_of, forget, replace, size_of, size_of_val};
 use core::slice::from_raw_parts as slice_from_raw_parts;
+use core::time::Duration;
 
-use crate::{cleanup_cache, invalidate_cache};
+use crate::board::mbox as board;
+use crate::dmabuf::{DeviceBuffer, DmaBuffer};
+use crate::executor::yield_now;
+use crate::fdt::{Fdt, RegisterBlock};
+use crate::sync::SpinLock;
+use crate::timer::{poll_register, Instant, RegisterTimeout};
+use crate::{cleanup_cache, debug, invalidate_cache, trace, warn};
 
 /// Assembles a buffer with the properties specified on input, sends it through
 /// the Mailbox interface, and populates the outputs with the returned
 /// properties.
 ///
-/// Panics if the video core fails to parse the buffer, does not know some of
-/// the properties, there isn't enough capacity to store a response property's
+/// Returns an error if the firmware doesn't respond in time, in which case the
+/// outputs are left untouched.
+///
+/// Panics if the firmware fails to parse the buffer, does not know some of the
+/// properties, there isn't enough capacity to store a response property's
 /// payload, or the alignment requirements of any of the payloads cannot be
 /// fulfilled.
 #[macro_export]
@@ -22,42 +29,50 @@ macro_rules! mbox {
     {msg = $msg:ident , $tag:tt : $input:expr => _ $(, $($tail:tt)*)?} => {{
         let prop = $crate::mbox::Property::new($tag, $input);
         $msg.add_property(&prop);
-        $crate::mbox! {msg = $msg $(,$($tail)*)?};
+        let res = $crate::mbox! {msg = $msg $(,$($tail)*)?};
         prop.nop(());
+        res
     }};
     {msg = $msg:ident , $tag:tt : _ => $output:expr $(, $($tail:tt)*)?} => {{
         let mut prop = $crate::mbox::Property::new($tag, ());
         $msg.add_property(&prop);
-        $crate::mbox! {msg = $msg $(,$($tail)*)?};
-        prop = $msg.find_property($tag);
-        $output = prop.payload();
+        let res = $crate::mbox! {msg = $msg $(,$($tail)*)?};
+        if res.is_ok() {
+            prop = $msg.find_property($tag);
+            $output = prop.payload();
+        }
+        res
     }};
     {msg = $msg:ident , $tag:tt : $input:expr => $output:expr $(, $($tail:tt)*)?} => {{
         let mut prop = $crate::mbox::Property::new($tag, $input);
         $msg.add_property(&prop);
-        $crate::mbox! {msg = $msg $(,$($tail)*)?};
-        prop = $msg.find_property($tag);
-        $output = prop.payload();
-    }};
-    {msg = $msg:ident} => {{
-        $crate::mbox::Mailbox.exchange(&mut $msg);
+        let res = $crate::mbox! {msg = $msg $(,$($tail)*)?};
+        if res.is_ok() {
+            prop = $msg.find_property($tag);
+            $output = prop.payload();
+        }
+        res
     }};
+    {msg = $msg:ident} => {
+        $crate::mbox::Mailbox.exchange(&mut $msg)
+    };
     {$($tag:tt : $input:tt => $output:tt),* $(,)?} => {{
         let mut msg = $crate::mbox::Message::new();
-        $crate::mbox! {msg = msg, $($tag: $input => $output),*};
+        $crate::mbox! {msg = msg, $($tag: $input => $output),*}
     }};
 }
 
-/// Base address of the video core mailbox registers.
-const BASE: usize = 0x107C013880;
 /// Inbox data register.
-const INBOX_DATA: *const u32 = BASE as _;
+const INBOX_DATA: usize = 0x0;
 /// Inbox status register.
-const INBOX_STATUS: *const u32 = (BASE + 0x18) as _;
+const INBOX_STATUS: usize = 0x18;
 /// Outbox data register.
-const OUTBOX_DATA: *mut u32 = (BASE + 0x20) as _;
+const OUTBOX_DATA: usize = 0x20;
 /// Outbox status register.
-const OUTBOX_STATUS: *const u32 = (BASE + 0x38) as _;
+const OUTBOX_STATUS: usize = 0x38;
+/// Maximum time to wait for the firmware to make room for or reply to a
+/// message.
+const TIMEOUT: Duration = Duration::from_secs(1);
 /// Mailbox full status value.
 const FULL_STATUS: u32 = 0x80000000;
 /// Mailbox empty status value.
@@ -71,9 +86,48 @@ const END_TAG: u32 = 0x0;
 /// Message buffer size.
 const BUF_SIZE: usize = 0x100;
 
+/// Registers.
+static BASE: RegisterBlock = RegisterBlock::new(board::BASE);
+/// Requests delivered to and responses received from the firmware, which
+/// replies through a single inbox, so only one request is ever in flight.
+static EXCHANGES: SpinLock<Exchanges> = SpinLock::new(Exchanges { sent: 0,
+                                                                  received: 0,
+                                                                  pending: None });
+
 /// Mailbox interface driver.
 pub struct Mailbox;
 
+/// Counts of the requests delivered to and responses received from the
+/// firmware.
+struct Exchanges
+{
+    /// Number of requests delivered.
+    sent: usize,
+    /// Number of responses received or given up on.
+    received: usize,
+    /// Buffer of the last asynchronous exchange, until its task collects the
+    /// response.
+    pending: Option<Pending>,
+}
+
+/// Buffer of an asynchronous exchange, which the mailbox owns so that the
+/// firmware never writes to memory that the task waiting for the response has
+/// stopped owning.
+struct Pending
+{
+    /// Number of the request among the delivered ones.
+    ticket: usize,
+    /// Copy of the message lent to the firmware.
+    buf: DeviceBuffer<Message>,
+    /// Whether the task stopped waiting for the response, in which case the
+    /// buffer is freed once the response arrives.
+    abandoned: bool,
+}
+
+/// Claim of a task on the response to an asynchronous exchange, which abandons
+/// it when dropped.
+struct Ticket(usize);
+
 /// Message buffer.
 #[repr(align(64), C)] // Align to a cache line.
 pub union Message
@@ -138,33 +192,243 @@ struct PropertyHeader
 
 impl Mailbox
 {
+    /// Switches to the mailbox described by the device tree, if any.
+    ///
+    /// * `fdt`: Device tree.
+    ///
+    /// Must be called before any other core starts exchanging messages.
+    pub fn probe(&mut self, fdt: &Fdt)
+    {
+        let Some((addr, len)) = fdt.find_compatible(board::COMPATIBLE).and_then(|node| node.reg(0)) else {
+            warn!("No mailbox registers in the device tree");
+            return;
+        };
+        BASE.set(addr, len);
+        debug!("Found mailbox at 0x{addr:X}");
+    }
+
     /// Delivers the request and waits for a response.
     ///
     /// * `msg`: Message with the request on input and response on output.
     ///
+    /// Returns an error identifying the status register that wedged if the
+    /// firmware doesn't make room for the request or reply to it in time.
+    ///
     /// Panics if the message is not a request on input or a success response on
     /// output.
+    ///
+    /// A response that a task is still waiting for is received on its behalf
+    /// first, since the task can't run until this returns if it's on the same
+    /// core.
     #[track_caller]
-    pub fn exchange(&mut self, msg: &mut Message)
+    pub fn exchange(&mut self, msg: &mut Message) -> Result<(), RegisterTimeout>
     {
         let code = unsafe { msg.header.code };
         assert!(code == REQUEST_CODE,
                 "Attempted to deliver a message to the firmware that is not a request");
         let buf = unsafe { &mut msg.byte_view };
-        while unsafe { OUTBOX_STATUS.read_volatile() } & FULL_STATUS != 0 {
-            spin_loop()
-        }
+        // Logging might deliver messages of its own, so it can't happen with the
+        // mailbox locked.
+        trace!("Delivering message at 0x{:X}", buf.as_ptr() as usize);
+        let mut exchanges = EXCHANGES.lock_irq();
+        exchanges.receive()?;
+        unsafe {
+            poll_register("OUTBOX_STATUS", BASE.reg(OUTBOX_STATUS), TIMEOUT, |status| {
+                status & FULL_STATUS == 0
+            })?
+        };
         let data = buf.as_ptr() as usize as u32 | 0xC0000008;
         cleanup_cache(buf);
-        unsafe { OUTBOX_DATA.write_volatile(data) };
-        while unsafe { INBOX_STATUS.read_volatile() } & EMPTY_STATUS != 0 {
-            spin_loop()
-        }
-        unsafe { INBOX_DATA.read_volatile() }; // Don't care about this value, just reading it to empty the inbox.
+        unsafe { BASE.reg(OUTBOX_DATA).write_volatile(data) };
+        exchanges.sent += 1;
+        exchanges.receive()?;
+        drop(exchanges);
         invalidate_cache(buf);
         let code = unsafe { msg.header.code };
         assert!(code == SUCCESS_CODE,
                 "Firmware reply contains an unexpected code: 0x{code:X}");
+        Ok(())
+    }
+
+    /// Delivers the request and waits for a response without blocking other
+    /// tasks, checking the mailbox whenever the task gets to run.
+    ///
+    /// * `msg`: Message with the request on input and response on output.
+    ///
+    /// Returns an error identifying the status register that wedged if the
+    /// firmware doesn't make room for the request or reply to it in time.
+    ///
+    /// Panics if the message is not a request on input or a success response on
+    /// output.
+    ///
+    /// The firmware is handed a copy of the message owned by the mailbox, so
+    /// the future can be dropped at any time, in which case the message is
+    /// left untouched.  The mailbox is only locked while checking it, so
+    /// [`Self::exchange`] can be called while the response is pending, in which
+    /// case it receives the response for this task.
+    pub async fn exchange_async(&mut self, msg: &mut Message) -> Result<(), RegisterTimeout>
+    {
+        let code = unsafe { msg.header.code };
+        assert!(code == REQUEST_CODE,
+                "Attempted to deliver a message to the firmware that is not a request");
+        let mut buf = Some(DmaBuffer::new(Message { byte_view: unsafe { msg.byte_view } }).to_device());
+        trace!("Delivering message at 0x{:X}",
+               buf.as_ref().map_or(0, DeviceBuffer::addr));
+        let mut ticket = Ticket(0);
+        let res =
+            poll_status("OUTBOX_STATUS", BASE.reg(OUTBOX_STATUS) as usize, |status| {
+                let mut exchanges = EXCHANGES.lock_irq();
+                exchanges.try_receive();
+                // Wait for any other request in flight to be answered and
+                // any other asynchronous response to be collected first.
+                if status & FULL_STATUS != 0 || exchanges.received != exchanges.sent || exchanges.pending.is_some() {
+                    return false;
+                }
+                let Some(buf) = buf.take() else {
+                    return true;
+                };
+                let data = buf.addr() as u32 | 0xC0000008;
+                unsafe { BASE.reg(OUTBOX_DATA).write_volatile(data) };
+                exchanges.sent += 1;
+                ticket.0 = exchanges.sent;
+                exchanges.pending = Some(Pending { ticket: ticket.0,
+                                                   buf,
+                                                   abandoned: false });
+                true
+            }).await;
+        if res.is_err() {
+            // Give up on whatever is keeping the mailbox busy so that it can be
+            // used again.
+            EXCHANGES.lock_irq().settle(false);
+        }
+        res?;
+        let res = poll_status("INBOX_STATUS", BASE.reg(INBOX_STATUS) as usize, |_| {
+                      let mut exchanges = EXCHANGES.lock_irq();
+                      // Blocking exchanges receive the response first if they
+                      // get to the mailbox while it's pending.
+                      exchanges.try_receive();
+                      exchanges.received >= ticket.0
+                  }).await;
+        let mut exchanges = EXCHANGES.lock_irq();
+        if res.is_err() {
+            exchanges.settle(false);
+        }
+        let pending = exchanges.pending.take_if(|pending| pending.ticket == ticket.0);
+        drop(exchanges);
+        res?;
+        // The response is gone if a blocking exchange gave up on it.
+        let Some(pending) = pending else {
+            let reg = BASE.reg(INBOX_STATUS);
+            return Err(RegisterTimeout { name: "INBOX_STATUS",
+                                         addr: reg as usize,
+                                         value: unsafe { reg.read_volatile() } });
+        };
+        let buf = pending.buf.from_device();
+        msg.byte_view = unsafe { buf.byte_view };
+        let code = unsafe { msg.header.code };
+        assert!(code == SUCCESS_CODE,
+                "Firmware reply contains an unexpected code: 0x{code:X}");
+        Ok(())
+    }
+}
+
+impl Exchanges
+{
+    /// Waits for the response to the request in flight, if any, giving up on
+    /// it if it doesn't arrive in time.
+    ///
+    /// Returns an error identifying the inbox status register if the response
+    /// doesn't arrive in time.
+    fn receive(&mut self) -> Result<(), RegisterTimeout>
+    {
+        if self.received == self.sent {
+            return Ok(());
+        }
+        let res = unsafe {
+            poll_register("INBOX_STATUS", BASE.reg(INBOX_STATUS), TIMEOUT, |status| {
+                status & EMPTY_STATUS == 0
+            })
+        };
+        if res.is_ok() {
+            // Don't care about this value, just reading it to empty the inbox.
+            unsafe { BASE.reg(INBOX_DATA).read_volatile() };
+        }
+        self.settle(res.is_ok());
+        res.map(|_| ())
+    }
+
+    /// Receives the response to the request in flight if it has already
+    /// arrived.
+    fn try_receive(&mut self)
+    {
+        if self.received == self.sent || unsafe { BASE.reg(INBOX_STATUS).read_volatile() } & EMPTY_STATUS != 0 {
+            return;
+        }
+        // Don't care about this value, just reading it to empty the inbox.
+        unsafe { BASE.reg(INBOX_DATA).read_volatile() };
+        self.settle(true);
+    }
+
+    /// Records that the request in flight was answered or given up on.
+    ///
+    /// * `answered`: Whether the firmware answered, in which case it's done
+    ///   with the buffer of the request.
+    fn settle(&mut self, answered: bool)
+    {
+        let received = replace(&mut self.received, self.sent);
+        let Some(pending) = self.pending.take_if(|pending| pending.ticket > received) else {
+            return;
+        };
+        if !answered {
+            // The firmware may still write the response at any time, so the
+            // buffer can never be reused.
+            forget(pending);
+        } else if !pending.abandoned {
+            self.pending = Some(pending);
+        }
+    }
+}
+
+impl Drop for Ticket
+{
+    fn drop(&mut self)
+    {
+        let mut exchanges = EXCHANGES.lock_irq();
+        let exchanges = &mut *exchanges;
+        let Some(pending) = exchanges.pending.as_mut().filter(|pending| pending.ticket == self.0) else {
+            return;
+        };
+        if exchanges.received >= self.0 {
+            exchanges.pending = None;
+        } else {
+            pending.abandoned = true;
+        }
+    }
+}
+
+/// Waits for a status register to satisfy a condition without blocking other
+/// tasks, since the mailbox doesn't interrupt the CPU.
+///
+/// * `name`: Register name to report on timeout.
+/// * `addr`: Register address, which unlike a pointer can be held across awaits
+///   by tasks that are sent between cores.
+/// * `cond`: Condition to wait for, given the register value.
+///
+/// Returns an error identifying the register along with the last value read
+/// from it if the condition isn't satisfied in time.
+async fn poll_status<F: FnMut(u32) -> bool>(name: &'static str, addr: usize, mut cond: F)
+                                            -> Result<(), RegisterTimeout>
+{
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let value = unsafe { (addr as *const u32).read_volatile() };
+        if cond(value) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(RegisterTimeout { name, addr, value });
+        }
+        yield_now().await;
     }
 }
 
@@ -303,6 +567,18 @@ impl<I: Copy, O: Copy> Property<I, O>
         unsafe { self.output.payload }
     }
 
+    /// Returns the size of this property's response payload in bytes, which
+    /// may exceed the payload's capacity if the response was truncated, or
+    /// `None` if the firmware did not respond to this property.
+    pub fn response_size(&self) -> Option<usize>
+    {
+        let resp_size = unsafe { self.header.resp_size };
+        if resp_size & 0x80000000 == 0 {
+            return None;
+        }
+        Some((resp_size & !0x80000000) as usize)
+    }
+
     /// Returns a byte representation of this property.
     fn bytes(&self) -> &[u8]
     {
@@ -311,6 +587,8 @@ impl<I: Copy, O: Copy> Property<I, O>
 
     /// Little hack to make type inference work in the macro when the user does
     /// not specify an output binding.
+    // Unused while every invocation binds all outputs.
+    #[allow(dead_code)]
     pub fn nop(&self, output: O) -> O
     {
         output
@@ -0,0 +1,929 @@
+//! MMU translation tables.
+//!
+//! The kernel identity maps everything it uses through TTBR0 with a 4KB
+//! granule and 38 bit virtual addresses, so translation starts at level 1,
+//! where each entry covers 1GB, followed by level 2 with 2MB entries and level
+//! 3 with 4KB entries.  The boot code calls [`map_kernel`] on the boot core
+//! with the MMU still off to build the tables from a statically allocated
+//! pool, and every core calls [`configure_mmu`] to point its MMU at them
+//! before enabling it.
+//!
+//! No mapping is ever both writable and executable, which the boot code also
+//! has the MMU enforce, and the guard pages below the stacks are left unmapped
+//! so that overflows fault instead of corrupting the BSS.
+//!
+//! Devices outside the peripheral windows mapped at boot can be mapped at run
+//! time with [`map_device`], which places them in a dedicated virtual window
+//! above the identity mapped physical addresses, where [`map_memory`] also
+//! maps memory left behind by the firmware outside the kernel's regions.
+
+#[cfg(not(test))]
+use core::arch::asm;
+use core::array::from_fn;
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::board::mmu::{PERIPHERALS, PERIPHERALS_LEN};
+use crate::sync::SpinLock;
+use crate::{debug, smp};
+
+/// Number of bits in a virtual address.
+const VA_BITS: usize = 38;
+/// Number of descriptors in a translation table.
+const ENTRIES: usize = 512;
+/// Number of translation tables in the pool, including the root.
+const MAX_TABLES: usize = 16;
+/// Number of adjacent entries that can share a TLB entry when marked as
+/// contiguous.
+const CONTIGUOUS_ENTRIES: usize = 16;
+/// Valid bit of all descriptors.
+const VALID: u64 = 0x1;
+/// Type bit distinguishing table descriptors from block descriptors at levels
+/// 1 and 2, which must also be set in page descriptors at level 3.
+const TABLE: u64 = 0x2;
+/// Access flag, which must be set to avoid access flag faults.
+const AF: u64 = 0x400;
+/// Contiguous hint bit.
+const CONTIGUOUS: u64 = 1 << 52;
+/// Privileged execute never bit.
+const PXN: u64 = 1 << 53;
+/// Unprivileged execute never bit.
+const UXN: u64 = 1 << 54;
+/// Output address bits of descriptors.
+const ADDR_MASK: u64 = 0xFFFF_FFFF_F000;
+/// Attributes of each memory type, indexed by the memory type, with zero
+/// encoding Device-nGnRnE memory.
+const MAIR: u64 = 0x04 << 24 // Device-nGnRE.
+                | 0x44 << 8 // Normal, inner and outer non-cacheable.
+                | 0xFF; // Normal, inner and outer write-back cacheable.
+/// Translation control register value, with a 4KB granule for TTBR0 encoded
+/// as zero.
+const TCR: u64 = 0x2 << 32 // IPS: 40 bit physical addresses.
+                | 0x2 << 30 // TG1: 4KB granule.
+                | 0x2 << 28 // SH1: Outer shareable.
+                | 0x1 << 26 // ORGN1: Write-back cacheable.
+                | 0x1 << 24 // IRGN1: Write-back cacheable.
+                | 0x1 << 23 // EPD1: TTBR1 walks disabled.
+                | (64 - 35) << 16 // T1SZ: 35 bit virtual addresses.
+                | 0x2 << 12 // SH0: Outer shareable.
+                | 0x1 << 10 // ORGN0: Write-back cacheable.
+                | 0x1 << 8 // IRGN0: Write-back cacheable.
+                | (64 - VA_BITS as u64); // T0SZ.
+/// Start of the virtual window for devices and firmware memory mapped at run
+/// time.
+const DEVICE_WINDOW: usize = 0x20_0000_0000;
+
+/// Translation table pool, with the root table at the start.
+static mut TABLES: [Table; MAX_TABLES] = [Table([Descriptor(0); ENTRIES]); MAX_TABLES];
+/// Number of tables allocated from the pool, including the root.
+static mut USED_TABLES: usize = 1;
+/// Start of the unused part of the device window, whose lock also serializes
+/// changes to the translation tables at run time.
+static NEXT_DEVICE: SpinLock<usize> = SpinLock::new(DEVICE_WINDOW);
+
+#[cfg(not(test))]
+extern "C" {
+    /// Start of the boot code, defined by the linker script.
+    static boot_start: u8;
+    /// End of the boot code, defined by the linker script.
+    static boot_end: u8;
+    /// Start of the code, defined by the linker script.
+    static text_start: u8;
+    /// End of the code, defined by the linker script.
+    static text_end: u8;
+    /// Start of the read-only data, defined by the linker script.
+    static rodata_start: u8;
+    /// End of the read-only data, defined by the linker script.
+    static rodata_end: u8;
+    /// Start of the symbol table, defined by the linker script.
+    static symbols_start: u8;
+    /// End of the symbol table, defined by the linker script.
+    static symbols_end: u8;
+    /// Start of the initialized data, defined by the linker script.
+    static data_start: u8;
+    /// End of the initialized data, defined by the linker script.
+    static data_end: u8;
+    /// Start of the BSS, defined by the linker script.
+    static bss_start: u8;
+    /// End of the BSS, defined by the linker script.
+    static bss_end: u8;
+    /// Start of the crash log, defined by the linker script.
+    static crash_log_start: u8;
+    /// End of the crash log, defined by the linker script.
+    static crash_log_end: u8;
+    /// Start of the cached heap region, defined by the linker script.
+    static cached_heap_start: u8;
+    /// End of the cached heap region, defined by the linker script.
+    static cached_heap_end: u8;
+    /// Start of the uncached heap region, defined by the linker script.
+    static heap_start: u8;
+    /// End of the uncached heap region, defined by the linker script.
+    static heap_end: u8;
+}
+
+/// Memory type, indexing the attributes configured in MAIR_EL1.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u64)]
+pub enum MemoryType
+{
+    /// Normal write-back cacheable memory.
+    Normal = 0,
+    /// Normal non-cacheable memory, for buffers shared with other bus masters.
+    NonCacheable = 1,
+    /// Device-nGnRnE memory, for peripheral registers.
+    Device = 2,
+    /// Device-nGnRE memory, for peripheral registers whose writes can be
+    /// acknowledged before reaching the peripheral.
+    PostedDevice = 3,
+}
+
+/// Data access permissions, for the kernel only.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u64)]
+pub enum Access
+{
+    /// Reads and writes are allowed.
+    ReadWrite = 0x0,
+    /// Only reads are allowed.
+    ReadOnly = 0x80,
+}
+
+/// Shareability domain of normal memory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u64)]
+pub enum Shareability
+{
+    /// Coherent with the current core only.
+    Non = 0x000,
+    /// Coherent with all the cores.
+    Inner = 0x300,
+}
+
+/// Size of the memory mapped by a single descriptor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlockSize
+{
+    /// 4KB page at level 3.
+    Size4K,
+    /// 2MB block at level 2.
+    Size2M,
+    /// 1GB block at level 1.
+    Size1G,
+}
+
+/// Attributes of a mapping.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Attributes
+{
+    /// Memory type.
+    pub memory: MemoryType,
+    /// Data access permissions.
+    pub access: Access,
+    /// Shareability domain.
+    pub shareability: Shareability,
+    /// Whether the kernel can execute code from the mapping.
+    pub executable: bool,
+}
+
+/// Translation table descriptor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Descriptor(u64);
+
+/// Virtual address of a mapping created at run time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VirtAddr(usize);
+
+/// Physical memory layout of the kernel, defined by the linker script.
+struct KernelLayout
+{
+    /// Boot code.
+    boot: Range<usize>,
+    /// Code.
+    text: Range<usize>,
+    /// Read-only data.
+    rodata: Range<usize>,
+    /// Symbol table.
+    symbols: Range<usize>,
+    /// Initialized data.
+    data: Range<usize>,
+    /// BSS, including the stacks of every core.
+    bss: Range<usize>,
+    /// Guard pages below the ELn and EL0 stacks of each core, which are left
+    /// unmapped.
+    guards: [[usize; 2]; smp::CORES],
+    /// Crash log.
+    crash_log: Range<usize>,
+    /// Cached heap region.
+    cached_heap: Range<usize>,
+    /// Uncached heap region.
+    heap: Range<usize>,
+}
+
+/// Translation table.
+#[derive(Clone, Copy)]
+#[repr(C, align(0x1000))]
+struct Table([Descriptor; ENTRIES]);
+
+impl BlockSize
+{
+    /// Returns the number of bytes mapped by a descriptor of this size.
+    pub fn len(self) -> usize
+    {
+        match self {
+            Self::Size4K => 0x1000,
+            Self::Size2M => 0x200000,
+            Self::Size1G => 0x40000000,
+        }
+    }
+
+    /// Returns the translation level at which descriptors of this size are
+    /// found.
+    fn level(self) -> usize
+    {
+        match self {
+            Self::Size4K => 3,
+            Self::Size2M => 2,
+            Self::Size1G => 1,
+        }
+    }
+}
+
+impl VirtAddr
+{
+    /// Returns the address as an integer.
+    pub fn addr(self) -> usize
+    {
+        self.0
+    }
+
+    /// Returns a pointer to a register at an offset from the address.
+    ///
+    /// * `offset`: Offset of the register in bytes.
+    pub fn reg<T>(self, offset: usize) -> *mut T
+    {
+        (self.0 + offset) as _
+    }
+}
+
+impl Descriptor
+{
+    /// Creates a descriptor pointing to a next level table.
+    ///
+    /// * `addr`: Physical address of the table.
+    ///
+    /// Returns the newly created descriptor.
+    fn table(addr: usize) -> Self
+    {
+        Self(addr as u64 & ADDR_MASK | TABLE | VALID)
+    }
+
+    /// Creates a descriptor mapping a block or page.
+    ///
+    /// * `addr`: Physical address of the block or page.
+    /// * `attrs`: Attributes of the mapping.
+    /// * `size`: Size of the block or page.
+    /// * `contiguous`: Whether the descriptor is part of an aligned run of
+    ///   contiguous descriptors with the same attributes.
+    ///
+    /// Returns the newly created descriptor.
+    fn block(addr: usize, attrs: Attributes, size: BlockSize, contiguous: bool) -> Self
+    {
+        let mut bits = addr as u64 & ADDR_MASK
+                       | UXN
+                       | AF
+                       | attrs.shareability as u64
+                       | attrs.access as u64
+                       | (attrs.memory as u64) << 2
+                       | VALID;
+        if size == BlockSize::Size4K {
+            bits |= TABLE;
+        }
+        if !attrs.executable {
+            bits |= PXN;
+        }
+        if contiguous {
+            bits |= CONTIGUOUS;
+        }
+        Self(bits)
+    }
+
+    /// Returns whether the descriptor is valid.
+    pub fn is_valid(self) -> bool
+    {
+        self.0 & VALID != 0
+    }
+
+    /// Returns whether the descriptor points to a next level table, which is
+    /// only meaningful at levels 1 and 2.
+    fn is_table(self) -> bool
+    {
+        self.0 & (TABLE | VALID) == TABLE | VALID
+    }
+
+    /// Returns the output address of the descriptor.
+    pub fn addr(self) -> usize
+    {
+        (self.0 & ADDR_MASK) as usize
+    }
+}
+
+/// Maps a range of physical memory with the given attributes, allocating the
+/// intermediate tables as needed.
+///
+/// Runs of descriptors that are aligned to and cover the span of
+/// [`CONTIGUOUS_ENTRIES`] descriptors are marked as contiguous so that they
+/// can share TLB entries.
+///
+/// * `virt`: Start of the virtual range.
+/// * `phys`: Start of the physical range.
+/// * `len`: Length of the range in bytes.
+/// * `attrs`: Attributes of the mapping.
+/// * `size`: Size of the blocks or pages to map the range with.
+///
+/// Panics if the mapping would be both writable and executable, the addresses
+/// or length are not aligned to the block size, the virtual range doesn't fit
+/// in the address space, part of the range is already mapped, or the table
+/// pool is exhausted.
+///
+/// This function only writes the translation tables, so it's up to the caller
+/// to make the changes visible to the MMU if it's enabled.
+/// The tables are not locked, so at run time this must only be called with
+/// [`NEXT_DEVICE`] locked.
+#[track_caller]
+pub unsafe fn map(virt: usize, phys: usize, len: usize, attrs: Attributes, size: BlockSize)
+{
+    assert!(!attrs.executable || attrs.access == Access::ReadOnly,
+            "Mapping 0x{len:X} bytes at 0x{virt:X} would be both writable and executable");
+    let block = size.len();
+    assert!((virt | phys | len) & (block - 1) == 0,
+            "Mapping 0x{len:X} bytes from 0x{virt:X} to 0x{phys:X} is not aligned to 0x{block:X} bytes");
+    assert!(virt < 1 << VA_BITS && len <= (1 << VA_BITS) - virt,
+            "Virtual range of 0x{len:X} bytes at 0x{virt:X} is out of bounds");
+    let group = block * CONTIGUOUS_ENTRIES;
+    let mut contiguous_end = 0;
+    for offset in (0 .. len).step_by(block) {
+        let (virt, phys) = (virt + offset, phys + offset);
+        if (virt | phys) & (group - 1) == 0 && len - offset >= group {
+            contiguous_end = offset + group;
+        }
+        let desc = entry(virt, size);
+        assert!(!(*desc).is_valid(), "Virtual address 0x{virt:X} is already mapped");
+        *desc = Descriptor::block(phys, attrs, size, offset < contiguous_end);
+    }
+}
+
+/// Unmaps a range of virtual addresses and invalidates their translations in
+/// the TLBs of all the cores.
+///
+/// * `virt`: Start of the virtual range.
+/// * `len`: Length of the range in bytes.
+///
+/// Panics if part of the range is not mapped, or the range only covers part of
+/// a block or of a run of contiguous descriptors.
+///
+/// The tables are not locked, so this must only be called with
+/// [`NEXT_DEVICE`] locked.
+#[track_caller]
+pub unsafe fn unmap(virt: usize, len: usize)
+{
+    let end = virt + len;
+    let mut addr = virt;
+    while addr < end {
+        let Some((desc, size)) = leaf(addr) else {
+            panic!("Virtual address 0x{addr:X} is not mapped");
+        };
+        let count = if (*desc).0 & CONTIGUOUS != 0 {
+            CONTIGUOUS_ENTRIES
+        } else {
+            1
+        };
+        let span = size.len() * count;
+        assert!(addr & (span - 1) == 0 && end - addr >= span,
+                "Unmapping 0x{len:X} bytes at 0x{virt:X} would split the mapping at 0x{addr:X}");
+        // Every descriptor of a contiguous run must be invalid before any of
+        // their translations is invalidated, since the run must never be seen
+        // with mismatched descriptors.
+        for idx in 0 .. count {
+            *desc.add(idx) = Descriptor(0);
+        }
+        // The descriptors must be observed as invalid by the table walkers
+        // before their translations are invalidated, or they could cache them
+        // again.
+        #[cfg(not(test))]
+        asm!("dsb ishst", options(nostack, preserves_flags));
+        for page in (addr .. addr + span).step_by(size.len()) {
+            invalidate(page);
+        }
+        addr += span;
+    }
+    #[cfg(not(test))]
+    asm!("dsb ish", "isb", options(nostack, preserves_flags));
+}
+
+/// Invalidates the translation of a virtual address in the TLBs of all the
+/// cores.
+///
+/// * `virt`: Virtual address to invalidate, which must no longer be mapped.
+unsafe fn invalidate(virt: usize)
+{
+    #[cfg(not(test))]
+    asm!(
+        "tlbi vae1is, {page}",
+        page = in (reg) virt >> 12,
+        options (nostack, preserves_flags));
+    #[cfg(test)]
+    tests::invalidate(virt);
+}
+
+/// Maps the registers of a device with the Device-nGnRE memory type into the
+/// device window and makes the mapping visible to the MMU.
+///
+/// * `phys`: Physical address of the registers.
+/// * `len`: Length of the registers in bytes.
+///
+/// Returns the virtual address corresponding to the physical address.
+///
+/// Panics if the device window or the table pool is exhausted.
+#[track_caller]
+pub fn map_device(phys: usize, len: usize) -> VirtAddr
+{
+    let attrs = Attributes { memory: MemoryType::PostedDevice,
+                             access: Access::ReadWrite,
+                             shareability: Shareability::Non,
+                             executable: false };
+    map_window(phys, len, attrs)
+}
+
+/// Maps memory outside the kernel's regions read-only with the normal
+/// non-cacheable memory type into the device window, for reading data left
+/// behind by the firmware, and makes the mapping visible to the MMU.
+///
+/// * `phys`: Physical address of the memory.
+/// * `len`: Length of the memory in bytes.
+///
+/// Returns the virtual address corresponding to the physical address.
+///
+/// Panics if the device window or the table pool is exhausted.
+#[track_caller]
+pub fn map_memory(phys: usize, len: usize) -> VirtAddr
+{
+    let attrs = Attributes { memory: MemoryType::NonCacheable,
+                             access: Access::ReadOnly,
+                             shareability: Shareability::Non,
+                             executable: false };
+    map_window(phys, len, attrs)
+}
+
+/// Maps a physical range into the device window and makes the mapping visible
+/// to the MMU.
+///
+/// * `phys`: Physical address of the range.
+/// * `len`: Length of the range in bytes.
+/// * `attrs`: Attributes of the mapping.
+///
+/// Returns the virtual address corresponding to the physical address.
+///
+/// Panics if the device window or the table pool is exhausted.
+#[track_caller]
+fn map_window(phys: usize, len: usize, attrs: Attributes) -> VirtAddr
+{
+    let start = phys & !0xFFF;
+    let end = (phys + len + 0xFFF) & !0xFFF;
+    // Use blocks whenever possible to save tables and TLB entries.
+    let size = if (start | end) & (BlockSize::Size2M.len() - 1) == 0 {
+        BlockSize::Size2M
+    } else {
+        BlockSize::Size4K
+    };
+    let mut next = NEXT_DEVICE.lock_irq();
+    let virt = (*next + size.len() - 1) & !(size.len() - 1);
+    assert!(end - start <= (1 << VA_BITS) - virt,
+            "Out of virtual address space to map 0x{len:X} bytes at 0x{phys:X}");
+    unsafe {
+        map(virt, start, end - start, attrs, size);
+        // The new descriptors replace invalid ones, which are never cached, so
+        // there are no stale translations to invalidate.
+        #[cfg(not(test))]
+        asm!("dsb ishst", "isb", options(nostack, preserves_flags));
+    }
+    *next = virt + end - start;
+    drop(next);
+    debug!("Mapped 0x{len:X} bytes of {:?} memory at 0x{phys:X} to 0x{:X}",
+           attrs.memory,
+           virt + phys - start);
+    VirtAddr(virt + phys - start)
+}
+
+/// Maps the memory reserved for the VideoCore, where the firmware allocates
+/// the framebuffer, to the same addresses with the normal non-cacheable memory
+/// type, and makes the mapping visible to the MMU.
+///
+/// * `phys`: Start of the memory reported by the firmware.
+/// * `len`: Length of the memory in bytes.
+///
+/// Panics if the memory, extended to whole 2MB blocks, overlaps anything
+/// already mapped, or the table pool is exhausted.
+#[track_caller]
+pub fn map_vc_memory(phys: usize, len: usize)
+{
+    let block = BlockSize::Size2M.len();
+    let start = phys & !(block - 1);
+    let end = (phys + len).next_multiple_of(block);
+    let attrs = Attributes { memory: MemoryType::NonCacheable,
+                             access: Access::ReadWrite,
+                             shareability: Shareability::Non,
+                             executable: false };
+    let next = NEXT_DEVICE.lock_irq();
+    unsafe {
+        map(start, start, end - start, attrs, BlockSize::Size2M);
+        #[cfg(not(test))]
+        asm!("dsb ishst", "isb", options(nostack, preserves_flags));
+    }
+    drop(next);
+    debug!("Mapped 0x{len:X} bytes of VideoCore memory at 0x{phys:X}");
+}
+
+/// Returns the virtual address at which device registers can be accessed,
+/// mapping them with [`map_device`] unless they're in one of the peripheral
+/// windows identity mapped at boot.
+///
+/// * `phys`: Physical address of the registers.
+/// * `len`: Length of the registers in bytes.
+///
+/// Panics if the registers need to be mapped and [`map_device`] fails.
+#[track_caller]
+pub fn device_address(phys: usize, len: usize) -> usize
+{
+    let mapped = PERIPHERALS.iter()
+                            .any(|start| phys >= *start && phys + len <= start + PERIPHERALS_LEN);
+    if mapped {
+        return phys;
+    }
+    map_device(phys, len).addr()
+}
+
+/// Unmaps the registers of a device mapped with [`map_device`], or memory
+/// mapped with [`map_memory`].
+///
+/// The virtual addresses of the mapping are not reused.
+///
+/// * `addr`: Address returned by [`map_device`] or [`map_memory`].
+/// * `len`: Length of the mapping in bytes.
+///
+/// Panics if the range was not mapped into the device window.
+#[track_caller]
+pub fn unmap_device(addr: VirtAddr, len: usize)
+{
+    let start = addr.0 & !0xFFF;
+    let end = (addr.0 + len + 0xFFF) & !0xFFF;
+    let next = NEXT_DEVICE.lock_irq();
+    assert!(start >= DEVICE_WINDOW && end <= *next,
+            "Virtual address 0x{:X} is not in the device window",
+            addr.0);
+    unsafe { unmap(start, end - start) };
+    drop(next);
+    debug!("Unmapped 0x{len:X} bytes at 0x{:X}", addr.0);
+}
+
+/// Maps a range of physical memory to the same virtual addresses.
+///
+/// * `range`: Range to map.
+/// * `attrs`: Attributes of the mapping.
+/// * `size`: Size of the blocks or pages to map the range with.
+///
+/// Panics under the same conditions as [`map`].
+#[track_caller]
+unsafe fn identity_map(range: &Range<usize>, attrs: Attributes, size: BlockSize)
+{
+    map(range.start, range.start, range.end - range.start, attrs, size);
+}
+
+/// Returns the descriptor translating a virtual address at the level of the
+/// given block size, allocating the intermediate tables as needed.
+///
+/// * `virt`: Virtual address to translate.
+/// * `size`: Size of the block or page containing the address.
+///
+/// Panics if the address is already mapped by a larger block, or the table
+/// pool is exhausted.
+#[track_caller]
+unsafe fn entry(virt: usize, size: BlockSize) -> *mut Descriptor
+{
+    let mut table = addr_of_mut!((*addr_of_mut!(TABLES))[0]);
+    for level in 1 .. size.level() {
+        let desc = addr_of_mut!((*table).0[index(virt, level)]);
+        if !(*desc).is_valid() {
+            *desc = Descriptor::table(alloc_table() as usize);
+        }
+        assert!((*desc).is_table(),
+                "Virtual address 0x{virt:X} is already mapped by a level {level} block");
+        table = (*desc).addr() as *mut Table;
+    }
+    addr_of_mut!((*table).0[index(virt, size.level())])
+}
+
+/// Returns the valid descriptor that maps a virtual address along with the
+/// size of the block or page that it maps.
+///
+/// * `virt`: Virtual address to look up.
+///
+/// Returns the descriptor and size, or `None` if the address is not mapped.
+unsafe fn leaf(virt: usize) -> Option<(*mut Descriptor, BlockSize)>
+{
+    if virt >= 1 << VA_BITS {
+        return None;
+    }
+    let mut table = addr_of_mut!((*addr_of_mut!(TABLES))[0]);
+    for size in [BlockSize::Size1G, BlockSize::Size2M, BlockSize::Size4K] {
+        let desc = addr_of_mut!((*table).0[index(virt, size.level())]);
+        if !(*desc).is_valid() {
+            return None;
+        }
+        if size == BlockSize::Size4K || !(*desc).is_table() {
+            return Some((desc, size));
+        }
+        table = (*desc).addr() as *mut Table;
+    }
+    None
+}
+
+/// Allocates an empty translation table from the pool.
+///
+/// Returns the allocated table.
+///
+/// Panics if the pool is exhausted.
+#[track_caller]
+unsafe fn alloc_table() -> *mut Table
+{
+    let used = *addr_of!(USED_TABLES);
+    assert!(used < MAX_TABLES, "Out of translation tables");
+    *addr_of_mut!(USED_TABLES) = used + 1;
+    addr_of_mut!((*addr_of_mut!(TABLES))[used])
+}
+
+/// Returns the index of the descriptor translating a virtual address in a
+/// table.
+///
+/// * `virt`: Virtual address.
+/// * `level`: Translation level of the table.
+fn index(virt: usize, level: usize) -> usize
+{
+    virt >> (39 - level * 9) & (ENTRIES - 1)
+}
+
+/// Builds the translation tables for the kernel, called by the boot code on the
+/// boot core before any other core is started.
+#[cfg(not(test))]
+#[no_mangle]
+extern "C" fn map_kernel()
+{
+    /// Returns the range between two linker symbols.
+    fn range(start: *const u8, end: *const u8) -> Range<usize>
+    {
+        start as usize .. end as usize
+    }
+
+    unsafe {
+        let layout = KernelLayout { boot: range(addr_of!(boot_start), addr_of!(boot_end)),
+                                    text: range(addr_of!(text_start), addr_of!(text_end)),
+                                    rodata: range(addr_of!(rodata_start), addr_of!(rodata_end)),
+                                    symbols: range(addr_of!(symbols_start), addr_of!(symbols_end)),
+                                    data: range(addr_of!(data_start), addr_of!(data_end)),
+                                    bss: range(addr_of!(bss_start), addr_of!(bss_end)),
+                                    guards: from_fn(smp::guard_pages),
+                                    crash_log: range(addr_of!(crash_log_start), addr_of!(crash_log_end)),
+                                    cached_heap: range(addr_of!(cached_heap_start), addr_of!(cached_heap_end)),
+                                    heap: range(addr_of!(heap_start), addr_of!(heap_end)) };
+        map_layout(&layout);
+    }
+}
+
+/// Maps the kernel and the memory regions that it uses.
+///
+/// * `layout`: Layout of the kernel.
+///
+/// Panics if any of the regions is misaligned or overlaps another.
+#[track_caller]
+unsafe fn map_layout(layout: &KernelLayout)
+{
+    let code = Attributes { memory: MemoryType::Normal,
+                            access: Access::ReadOnly,
+                            shareability: Shareability::Non,
+                            executable: true };
+    let rodata = Attributes { executable: false,
+                              ..code };
+    let data = Attributes { access: Access::ReadWrite,
+                            shareability: Shareability::Inner,
+                            ..rodata };
+    let uncached = Attributes { memory: MemoryType::NonCacheable,
+                                shareability: Shareability::Non,
+                                ..data };
+    let device = Attributes { memory: MemoryType::Device,
+                              ..uncached };
+    identity_map(&layout.boot, code, BlockSize::Size4K);
+    identity_map(&layout.text, code, BlockSize::Size4K);
+    identity_map(&layout.rodata, rodata, BlockSize::Size4K);
+    identity_map(&layout.symbols, rodata, BlockSize::Size4K);
+    identity_map(&layout.data, data, BlockSize::Size4K);
+    let mut start = layout.bss.start;
+    for guard in layout.guards.iter().flatten() {
+        map(start, start, guard - start, data, BlockSize::Size4K);
+        start = guard + smp::GUARD_SIZE;
+    }
+    map(start, start, layout.bss.end - start, data, BlockSize::Size4K);
+    identity_map(&layout.crash_log, data, BlockSize::Size4K);
+    identity_map(&layout.cached_heap, data, BlockSize::Size2M);
+    identity_map(&layout.heap, uncached, BlockSize::Size2M);
+    for start in PERIPHERALS {
+        map(start, start, PERIPHERALS_LEN, device, BlockSize::Size2M);
+    }
+}
+
+/// Points the MMU of the current core at the kernel's translation tables,
+/// called by the boot code on every core before it enables the MMU.
+#[cfg(not(test))]
+#[no_mangle]
+extern "C" fn configure_mmu()
+{
+    unsafe {
+        asm!(
+            "msr mair_el1, {mair}",
+            "msr tcr_el1, {tcr}",
+            "msr ttbr0_el1, {root}",
+            mair = in (reg) MAIR,
+            tcr = in (reg) TCR,
+            root = in (reg) addr_of!(TABLES),
+            options (nostack, preserves_flags));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::cell::RefCell;
+    use std::sync::{Mutex, MutexGuard};
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Serializes the tests, which share the translation tables.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    std::thread_local! {
+        /// Virtual addresses invalidated by the current test.
+        static INVALIDATED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Records the invalidation of a virtual address, checking that it's no
+    /// longer mapped.
+    ///
+    /// * `virt`: Invalidated virtual address.
+    pub(super) unsafe fn invalidate(virt: usize)
+    {
+        assert!(leaf(virt).is_none(), "Invalidated 0x{virt:X} while still mapped");
+        INVALIDATED.with(|invalidated| invalidated.borrow_mut().push(virt));
+    }
+
+    /// Clears the translation tables and the device window.
+    ///
+    /// Returns the guard that keeps other tests from using the tables.
+    fn reset() -> MutexGuard<'static, ()>
+    {
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            for table in (*addr_of_mut!(TABLES)).iter_mut() {
+                table.0 = [Descriptor(0); ENTRIES];
+            }
+            *addr_of_mut!(USED_TABLES) = 1;
+        }
+        *NEXT_DEVICE.lock_irq() = DEVICE_WINDOW;
+        INVALIDATED.with(|invalidated| invalidated.borrow_mut().clear());
+        guard
+    }
+
+    /// Checks that a range is identity mapped with the descriptors that the
+    /// boot code used to write before the tables were built here.
+    ///
+    /// * `range`: Range of addresses to check.
+    /// * `size`: Expected size of the blocks or pages.
+    /// * `old`: Block or page descriptor written by the boot code, without the
+    ///   output address.
+    ///
+    /// The boot code set the NS bit, which is ignored at non-secure EL1, left
+    /// UXN clear in executable mappings, which the kernel now always sets
+    /// since nothing runs at EL0, and set the contiguous hint on every 2MB
+    /// block, which is now only set on aligned runs, so these bits are adjusted
+    /// before comparing.
+    fn check_old(range: Range<usize>, size: BlockSize, old: u64)
+    {
+        const NS: u64 = 0x20;
+        let expected = old & !(NS | CONTIGUOUS) | UXN;
+        for virt in range.step_by(size.len()) {
+            let (desc, actual) = unsafe { leaf(virt) }.unwrap_or_else(|| panic!("0x{virt:X} is not mapped"));
+            let desc = unsafe { *desc };
+            assert_eq!(actual, size, "0x{virt:X} is mapped with the wrong size");
+            assert_eq!(desc.addr(), virt, "0x{virt:X} is not identity mapped");
+            assert_eq!(desc.0 & !ADDR_MASK & !CONTIGUOUS,
+                       expected,
+                       "0x{virt:X} is mapped with descriptor 0x{:X}",
+                       desc.0);
+        }
+    }
+
+    #[test]
+    fn kernel_layout_matches_boot_code()
+    {
+        let _guard = reset();
+        let bss = 0xB0000 .. 0x1F0000;
+        let layout = KernelLayout { boot: 0x80000 .. 0x81000,
+                                    text: 0x81000 .. 0x9B000,
+                                    rodata: 0x9B000 .. 0xA4000,
+                                    data: 0xA4000 .. 0xA6000,
+                                    symbols: 0xA6000 .. 0xAA000,
+                                    bss: bss.clone(),
+                                    guards: from_fn(|core| {
+                                        let base = bss.start + core * 0x10000;
+                                        [base, base + 0x8000]
+                                    }),
+                                    crash_log: 0x1FF000 .. 0x200000,
+                                    cached_heap: 0x200000 .. 0x4000000,
+                                    heap: 0x4000000 .. 0x8000000 };
+        unsafe { map_layout(&layout) };
+        // The boot code pointed root[0], root[64] and root[65] and the first
+        // entry of the level 2 table below root[0] at next level tables, with
+        // the NSTable and AF bits set, both of which are ignored.
+        let root = unsafe { &(*addr_of!(TABLES))[0] };
+        for idx in [0, 64, 65] {
+            let old = 0x8000 << 48 | 0x403;
+            assert_eq!(root.0[idx].0 & !ADDR_MASK,
+                       old & !(1 << 63 | AF),
+                       "root[{idx}] is not a table");
+        }
+        let static_tt = unsafe { &*(root.0[0].addr() as *const Table) };
+        assert!(static_tt.0[0].is_table(), "The first 2MB are not mapped by a table");
+        check_old(layout.boot.clone(), BlockSize::Size4K, 0x4A3);
+        check_old(layout.text.clone(), BlockSize::Size4K, 0x4A3);
+        check_old(layout.rodata.clone(), BlockSize::Size4K, 0x20 << 48 | 0x4A3);
+        check_old(layout.symbols.clone(), BlockSize::Size4K, 0x20 << 48 | 0x4A3);
+        check_old(layout.data.clone(), BlockSize::Size4K, 0x20 << 48 | 0x723);
+        check_old(layout.crash_log.clone(), BlockSize::Size4K, 0x20 << 48 | 0x723);
+        let mut start = layout.bss.start;
+        for guard in layout.guards.iter().flatten().copied() {
+            check_old(start .. guard, BlockSize::Size4K, 0x20 << 48 | 0x723);
+            assert!(unsafe { leaf(guard) }.is_none(), "Guard page at 0x{guard:X} is mapped");
+            start = guard + smp::GUARD_SIZE;
+        }
+        check_old(start .. layout.bss.end, BlockSize::Size4K, 0x20 << 48 | 0x723);
+        check_old(layout.cached_heap.clone(), BlockSize::Size2M, 0x30 << 48 | 0x721);
+        check_old(layout.heap.clone(), BlockSize::Size2M, 0x30 << 48 | 0x425);
+        for start in PERIPHERALS {
+            check_old(start .. start + PERIPHERALS_LEN, BlockSize::Size2M, 0x30 << 48 | 0x429);
+        }
+    }
+
+    #[test]
+    fn vc_memory_matches_boot_code()
+    {
+        let _guard = reset();
+        map_vc_memory(0x3C10_0000, 0x3E0_0000);
+        check_old(0x3C00_0000 .. 0x4000_0000, BlockSize::Size2M, 0x30 << 48 | 0x425);
+        assert!(unsafe { leaf(0x3BFF_F000) }.is_none());
+        assert!(unsafe { leaf(0x4000_0000) }.is_none());
+    }
+
+    #[test]
+    fn unmap_contiguous_device_range()
+    {
+        let _guard = reset();
+        let phys = 0x1F_0001_0000;
+        let len = 0x10000;
+        let addr = map_device(phys, len);
+        assert_eq!(addr.addr(), DEVICE_WINDOW);
+        for offset in (0 .. len).step_by(0x1000) {
+            let (desc, size) = unsafe { leaf(addr.addr() + offset) }.expect("Page not mapped");
+            let desc = unsafe { *desc };
+            assert_eq!(size, BlockSize::Size4K);
+            assert_eq!(desc.addr(), phys + offset);
+            assert_ne!(desc.0 & CONTIGUOUS, 0, "Page at offset 0x{offset:X} is not contiguous");
+        }
+        unmap_device(addr, len);
+        for offset in (0 .. len).step_by(0x1000) {
+            assert!(unsafe { leaf(addr.addr() + offset) }.is_none());
+        }
+        let invalidated = INVALIDATED.with(|invalidated| invalidated.borrow().clone());
+        let expected = (0 .. len).step_by(0x1000)
+                                 .map(|offset| addr.addr() + offset)
+                                 .collect::<Vec<_>>();
+        assert_eq!(invalidated, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "would split the mapping")]
+    fn unmap_part_of_contiguous_range()
+    {
+        let _guard = reset();
+        let addr = map_device(0x1F_0001_0000, 0x10000);
+        unmap_device(VirtAddr(addr.addr() + 0x1000), 0x1000);
+    }
+}
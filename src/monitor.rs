@@ -0,0 +1,58 @@
+//! Firmware health monitor.
+//!
+//! Periodically asks the firmware whether it's throttling the board because
+//! of an insufficient power supply or overheating, which can starve audio
+//! playback, and logs whenever that changes.
+
+use core::time::Duration;
+
+use crate::executor::sleep;
+use crate::mbox::{Mailbox, Message, Property};
+use crate::{info, warn};
+
+/// Get throttled state property tag.
+const GET_THROTTLED_TAG: u32 = 0x30046;
+/// Time between checks.
+const PERIOD: Duration = Duration::from_secs(5);
+/// Conditions currently reported by the throttled state, indexed by bit.
+const CONDITIONS: [&str; 4] = ["Under-voltage detected",
+                               "ARM frequency capped",
+                               "Throttled",
+                               "Soft temperature limit active"];
+
+/// Checks the throttled state forever without blocking other tasks.
+pub async fn run()
+{
+    let mut last = 0;
+    loop {
+        let mut msg = Message::new();
+        msg.add_property(&Property::<u32, u32>::new(GET_THROTTLED_TAG, 0));
+        match Mailbox.exchange_async(&mut msg).await {
+            Ok(()) => {
+                let state = msg.find_property::<u32, u32>(GET_THROTTLED_TAG).payload() & 0xF;
+                if state != last {
+                    report(state);
+                    last = state;
+                }
+            }
+            Err(err) => warn!("Failed to query the throttled state: {err}"),
+        }
+        sleep(PERIOD).await;
+    }
+}
+
+/// Logs the conditions in a throttled state.
+///
+/// * `state`: Throttled state reported by the firmware.
+fn report(state: u32)
+{
+    if state == 0 {
+        info!("No longer throttled");
+        return;
+    }
+    for (bit, condition) in CONDITIONS.iter().enumerate() {
+        if state & 1 << bit != 0 {
+            warn!("{condition}");
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Power management watchdog driver.
+
+use crate::board::pm::BASE;
+use crate::{halt, println};
+
+/// Reset control register.
+const RSTC: *mut u32 = (BASE + 0x1C) as _;
+/// Watchdog timer register.
+const WDOG: *mut u32 = (BASE + 0x24) as _;
+/// Password that must be present in every write to the registers.
+const PASSWORD: u32 = 0x5A000000;
+/// Reset configuration field of the reset control register.
+const RSTC_WRCFG_MASK: u32 = 0x30;
+/// Full reset configuration.
+const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+/// Watchdog timeout in ticks of roughly 16us.
+const TIMEOUT: u32 = 10;
+
+/// Reboots the system by letting the watchdog expire almost immediately.
+pub fn reboot() -> !
+{
+    println!("Rebooting");
+    unsafe {
+        WDOG.write_volatile(PASSWORD | TIMEOUT);
+        let rstc = RSTC.read_volatile() & !RSTC_WRCFG_MASK;
+        RSTC.write_volatile(PASSWORD | rstc | RSTC_WRCFG_FULL_RESET);
+    }
+    halt()
+}
@@ -0,0 +1,384 @@
+//! Serial link framing protocol.
+//!
+//! Frames start with a synchronization pattern followed by a header with the
+//! frame kind, a sequence number and the payload length, then the payload and
+//! a CRC-32 computed over the header and payload.  Receivers discard anything
+//! between frames, so frames can be interleaved with console text.
+//!
+//! The sender transmits one frame at a time and waits for the receiver to
+//! acknowledge it before transmitting the next, resending it if the receiver
+//! rejects it or doesn't respond, so the receiver controls the data flow by
+//! delaying its acknowledgements.
+//!
+//! This module only depends on `core` so that it can be shared with the host
+//! tools.
+
+/// Synchronization pattern that starts every frame.
+pub const SYNC: [u8; 2] = [0x5A, 0xA5];
+/// Maximum payload length.
+pub const MAX_PAYLOAD: usize = 1024;
+/// Length of a stream format payload.
+pub const FORMAT_LEN: usize = 8;
+/// Length of an image header payload.
+pub const IMAGE_LEN: usize = 8;
+/// Signed 16 bit little endian PCM encoding.
+pub const PCM_S16LE: u8 = 1;
+/// Rejection reason: the frame was corrupted.
+pub const NAK_CORRUPT: u8 = 1;
+/// Rejection reason: the stream format is not supported.
+pub const NAK_FORMAT: u8 = 2;
+/// Rejection reason: the frame was not expected at this point.
+pub const NAK_UNEXPECTED: u8 = 3;
+/// Rejection reason: the image is too large.
+pub const NAK_TOO_LARGE: u8 = 4;
+/// Rejection reason: the received image doesn't match its checksum.
+pub const NAK_CHECKSUM: u8 = 5;
+
+/// Frame kind.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Kind
+{
+    /// Starts a transfer, with the stream format or image header as payload.
+    Start = 1,
+    /// Transfer data.
+    Data = 2,
+    /// Ends a transfer.
+    End = 3,
+    /// Acknowledges the frame with the same sequence number.
+    Ack = 4,
+    /// Rejects the frame with the same sequence number, with the reason as
+    /// payload.
+    Nak = 5,
+}
+
+/// Frame.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a>
+{
+    /// Frame kind.
+    pub kind: Kind,
+    /// Sequence number.
+    pub seq: u8,
+    /// Payload.
+    pub payload: &'a [u8],
+}
+
+/// Audio stream format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Format
+{
+    /// Sample encoding.
+    pub encoding: u8,
+    /// Number of interleaved channels.
+    pub channels: u8,
+    /// Sample rate in Hz.
+    pub rate: u32,
+}
+
+/// Kernel image header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Image
+{
+    /// Size in bytes.
+    pub size: u32,
+    /// CRC-32 of the whole image.
+    pub crc: u32,
+}
+
+/// Receive error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecvError<E>
+{
+    /// The underlying link failed.
+    Link(E),
+    /// The header contained an unknown kind or excessive length, or the CRC
+    /// didn't match.
+    Corrupt,
+}
+
+/// Byte oriented transport.
+pub trait Link
+{
+    /// Transport error.
+    type Error;
+
+    /// Waits for a byte to arrive.
+    ///
+    /// Returns the received byte.
+    fn read_byte(&mut self) -> Result<u8, Self::Error>;
+
+    /// Sends bytes.
+    ///
+    /// * `bytes`: Bytes to send.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Sends a frame.
+///
+/// * `link`: Transport.
+/// * `frame`: Frame to send.
+///
+/// Panics if the payload is larger than [`MAX_PAYLOAD`].
+#[track_caller]
+pub fn send<L: Link>(link: &mut L, frame: &Frame) -> Result<(), L::Error>
+{
+    assert!(frame.payload.len() <= MAX_PAYLOAD,
+            "Frame payload is too large: {} bytes",
+            frame.payload.len());
+    let len = (frame.payload.len() as u16).to_le_bytes();
+    let header = [frame.kind as u8, frame.seq, len[0], len[1]];
+    let crc = crc32_update(crc32_update(!0, &header), frame.payload);
+    link.write(&SYNC)?;
+    link.write(&header)?;
+    link.write(frame.payload)?;
+    link.write(&(!crc).to_le_bytes())
+}
+
+/// Sends a reply frame, ignoring transport errors since the sender of the frame
+/// being replied to sends it again if the reply doesn't arrive.
+///
+/// * `link`: Transport.
+/// * `kind`: Acknowledgement or rejection.
+/// * `seq`: Sequence number of the frame being replied to.
+/// * `payload`: Rejection reason, if any.
+///
+/// Panics if the payload is larger than [`MAX_PAYLOAD`].
+#[track_caller]
+pub fn reply<L: Link>(link: &mut L, kind: Kind, seq: u8, payload: &[u8])
+{
+    let _ = send(link, &Frame { kind, seq, payload });
+}
+
+/// Waits for a frame to arrive, discarding anything that precedes it.
+///
+/// * `link`: Transport.
+/// * `buf`: Buffer to store the payload.
+///
+/// Returns the received frame.
+pub fn receive<'a, L: Link>(link: &mut L, buf: &'a mut [u8; MAX_PAYLOAD]) -> Result<Frame<'a>, RecvError<L::Error>>
+{
+    // Hunt for the synchronization pattern.
+    let mut prev = link.read_byte().map_err(RecvError::Link)?;
+    loop {
+        let byte = link.read_byte().map_err(RecvError::Link)?;
+        if [prev, byte] == SYNC {
+            break;
+        }
+        prev = byte;
+    }
+    let mut header = [0; 4];
+    for byte in header.iter_mut() {
+        *byte = link.read_byte().map_err(RecvError::Link)?;
+    }
+    let kind = match header[0] {
+        1 => Kind::Start,
+        2 => Kind::Data,
+        3 => Kind::End,
+        4 => Kind::Ack,
+        5 => Kind::Nak,
+        _ => return Err(RecvError::Corrupt),
+    };
+    let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+    if len > MAX_PAYLOAD {
+        return Err(RecvError::Corrupt);
+    }
+    for byte in buf[.. len].iter_mut() {
+        *byte = link.read_byte().map_err(RecvError::Link)?;
+    }
+    let mut crc = [0; 4];
+    for byte in crc.iter_mut() {
+        *byte = link.read_byte().map_err(RecvError::Link)?;
+    }
+    if !crc32_update(crc32_update(!0, &header), &buf[.. len]) != u32::from_le_bytes(crc) {
+        return Err(RecvError::Corrupt);
+    }
+    Ok(Frame { kind,
+               seq: header[1],
+               payload: &buf[.. len] })
+}
+
+/// Feeds data into a running CRC-32 (IEEE 802.3) computation.
+///
+/// * `crc`: Running value, starting at all ones and inverted at the end.
+/// * `data`: Data to feed.
+///
+/// Returns the updated running value.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32
+{
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0 .. 8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (crc & 0x1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+impl Format
+{
+    /// Parses a stream format payload.
+    ///
+    /// * `bytes`: Payload of a start frame.
+    ///
+    /// Returns the parsed format, or `None` if the payload has the wrong size.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self>
+    {
+        let bytes: &[u8; FORMAT_LEN] = bytes.try_into().ok()?;
+        Some(Self { encoding: bytes[0],
+                    channels: bytes[1],
+                    rate: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) })
+    }
+
+    /// Returns the payload representation of this format.
+    // Only the host tools send this payload.
+    #[allow(dead_code)]
+    pub fn to_bytes(self) -> [u8; FORMAT_LEN]
+    {
+        let rate = self.rate.to_le_bytes();
+        [self.encoding, self.channels, 0, 0, rate[0], rate[1], rate[2], rate[3]]
+    }
+}
+
+impl Image
+{
+    /// Parses an image header payload.
+    ///
+    /// * `bytes`: Payload of a start frame.
+    ///
+    /// Returns the parsed header, or `None` if the payload has the wrong size.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self>
+    {
+        let bytes: &[u8; IMAGE_LEN] = bytes.try_into().ok()?;
+        Some(Self { size: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    crc: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) })
+    }
+
+    /// Returns the payload representation of this header.
+    // Only the host tools send this payload.
+    #[allow(dead_code)]
+    pub fn to_bytes(self) -> [u8; IMAGE_LEN]
+    {
+        let size = self.size.to_le_bytes();
+        let crc = self.crc.to_le_bytes();
+        [size[0], size[1], size[2], size[3], crc[0], crc[1], crc[2], crc[3]]
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// In-memory pipe that returns whatever was written to it.
+    #[derive(Default)]
+    struct Pipe(VecDeque<u8>);
+
+    /// Error returned when reading from an empty pipe.
+    #[derive(Debug, Eq, PartialEq)]
+    struct Empty;
+
+    impl Link for Pipe
+    {
+        type Error = Empty;
+
+        fn read_byte(&mut self) -> Result<u8, Empty>
+        {
+            self.0.pop_front().ok_or(Empty)
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> Result<(), Empty>
+        {
+            self.0.extend(bytes);
+            Ok(())
+        }
+    }
+
+    /// Returns the bytes of a frame as sent over the link.
+    ///
+    /// * `frame`: Frame to encode.
+    fn encode(frame: &Frame) -> Vec<u8>
+    {
+        let mut pipe = Pipe::default();
+        send(&mut pipe, frame).unwrap();
+        pipe.0.into()
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let payload = (0 .. MAX_PAYLOAD).map(|idx| idx as u8).collect::<Vec<_>>();
+        let mut pipe = Pipe::default();
+        send(&mut pipe,
+             &Frame { kind: Kind::Data,
+                      seq: 42,
+                      payload: &payload }).unwrap();
+        let mut buf = [0; MAX_PAYLOAD];
+        let frame = receive(&mut pipe, &mut buf).unwrap();
+        assert_eq!(frame.kind, Kind::Data);
+        assert_eq!(frame.seq, 42);
+        assert_eq!(frame.payload, &payload[..]);
+        assert!(pipe.0.is_empty());
+    }
+
+    #[test]
+    fn corrupted_crc()
+    {
+        let mut bytes = encode(&Frame { kind: Kind::Start,
+                                        seq: 1,
+                                        payload: &[1, 2, 3] });
+        *bytes.last_mut().unwrap() ^= 0x1;
+        let mut pipe = Pipe(bytes.into());
+        let mut buf = [0; MAX_PAYLOAD];
+        assert_eq!(receive(&mut pipe, &mut buf).unwrap_err(), RecvError::Corrupt);
+    }
+
+    #[test]
+    fn resync_after_junk()
+    {
+        let mut bytes = b"console text\r\n".to_vec();
+        // A lone first half of the synchronization pattern followed by a
+        // corrupted frame.
+        bytes.push(SYNC[0]);
+        let mut corrupt = encode(&Frame { kind: Kind::Data,
+                                          seq: 2,
+                                          payload: &[4, 5, 6] });
+        corrupt[6] ^= 0x80;
+        bytes.extend(&corrupt);
+        bytes.extend(encode(&Frame { kind: Kind::End,
+                                     seq: 3,
+                                     payload: &[] }));
+        let mut pipe = Pipe(bytes.into());
+        let mut buf = [0; MAX_PAYLOAD];
+        assert_eq!(receive(&mut pipe, &mut buf).unwrap_err(), RecvError::Corrupt);
+        let frame = receive(&mut pipe, &mut buf).unwrap();
+        assert_eq!(frame.kind, Kind::End);
+        assert_eq!(frame.seq, 3);
+        assert!(frame.payload.is_empty());
+    }
+
+    #[test]
+    fn oversize_frame()
+    {
+        let len = (MAX_PAYLOAD as u16 + 1).to_le_bytes();
+        let mut bytes = SYNC.to_vec();
+        bytes.extend([Kind::Data as u8, 4, len[0], len[1]]);
+        bytes.extend(vec![0; MAX_PAYLOAD + 5]);
+        let mut pipe = Pipe(bytes.into());
+        let mut buf = [0; MAX_PAYLOAD];
+        assert_eq!(receive(&mut pipe, &mut buf).unwrap_err(), RecvError::Corrupt);
+    }
+
+    #[test]
+    #[should_panic(expected = "Frame payload is too large")]
+    fn send_oversize_frame()
+    {
+        let payload = [0; MAX_PAYLOAD + 1];
+        let _ = send(&mut Pipe::default(),
+                     &Frame { kind: Kind::Data,
+                              seq: 5,
+                              payload: &payload });
+    }
+}
@@ -0,0 +1,82 @@
+//! Fixed capacity ring buffer.
+
+/// First in first out queue backed by a fixed size array.
+pub struct Ring<T: Copy, const N: usize>
+{
+    /// Storage.
+    buf: [T; N],
+    /// Total number of elements ever popped, wrapping around.
+    head: usize,
+    /// Total number of elements ever pushed, wrapping around.
+    tail: usize,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N>
+{
+    /// Creates and initializes a new empty ring buffer.
+    ///
+    /// * `fill`: Value to fill the unused storage with.
+    ///
+    /// Returns the newly created ring buffer.
+    pub const fn new(fill: T) -> Self
+    {
+        assert!(N.is_power_of_two(), "Ring buffer capacity must be a power of two");
+        Self { buf: [fill; N],
+               head: 0,
+               tail: 0 }
+    }
+
+    /// Appends an element to the end of the queue.
+    ///
+    /// * `val`: Element to append.
+    ///
+    /// Returns whether the element was appended, which fails if the queue is
+    /// full.
+    pub fn push(&mut self, val: T) -> bool
+    {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[self.tail & (N - 1)] = val;
+        self.tail = self.tail.wrapping_add(1);
+        true
+    }
+
+    /// Removes an element from the front of the queue.
+    ///
+    /// Returns the removed element, or `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<T>
+    {
+        if self.is_empty() {
+            return None;
+        }
+        let val = self.buf[self.head & (N - 1)];
+        self.head = self.head.wrapping_add(1);
+        Some(val)
+    }
+
+    /// Returns an iterator over the elements in the queue from front to back,
+    /// without removing them.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_
+    {
+        (0 .. self.len()).map(|idx| self.buf[self.head.wrapping_add(idx) & (N - 1)])
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize
+    {
+        self.tail.wrapping_sub(self.head)
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    /// Returns whether the queue is full.
+    pub fn is_full(&self) -> bool
+    {
+        self.len() == N
+    }
+}
@@ -3,21 +3,52 @@
 //! Allocates memory in an uncached region to communicate with peripherals.
 
 use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::heap::Heap;
+use crate::sync::{Once, SpinLock};
 
 /// Base address to the uncached memory region.
 const UNCACHED_BASE: usize = 0x4000000;
+/// End address of the uncached memory region.
+const UNCACHED_END: usize = 0x8000000;
 
-/// Amount of memory allocated.
-static mut SCRATCHED: usize = 0;
+/// Uncached heap.
+static HEAP: SpinLock<Heap> = SpinLock::new(Heap::empty());
+/// Initialization of the uncached heap.
+static INIT: Once<()> = Once::new();
 
 /// Allocates uncached memory for data of the specified type.
-pub fn alloc<T>() -> *mut T
+///
+/// Returns a pointer to the uninitialized memory, or `None` if there isn't a
+/// large enough free block in the uncached region.
+pub fn alloc<T>() -> Option<NonNull<T>>
+{
+    init_or_nop();
+    HEAP.lock_irq().alloc(Layout::new::<T>()).map(NonNull::cast)
+}
+
+/// Frees uncached memory previously allocated for data of the specified type
+/// without dropping its contents.
+///
+/// * `ptr`: Pointer returned by a previous call to [`alloc`].
+///
+/// Panics if the memory is outside the uncached region or overlaps memory that
+/// is already free.
+///
+/// # Safety
+///
+/// The memory must have been allocated by [`alloc`] with the same type and must
+/// no longer be in use by either the CPU or peripherals.
+#[track_caller]
+pub unsafe fn free<T>(ptr: NonNull<T>)
+{
+    HEAP.lock_irq().free(ptr.cast(), Layout::new::<T>())
+}
+
+/// Makes the whole uncached region available for allocation the first time
+/// this function is called.
+fn init_or_nop()
 {
-    unsafe {
-        let layout = Layout::new::<T>();
-        SCRATCHED = (SCRATCHED + layout.align() - 1) & !(layout.align() - 1);
-        let base = (UNCACHED_BASE + SCRATCHED) as *mut T;
-        SCRATCHED += layout.size();
-        base
-    }
+    INIT.call_once(|| unsafe { HEAP.lock_irq().init(UNCACHED_BASE, UNCACHED_END) });
 }
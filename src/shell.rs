@@ -0,0 +1,500 @@
+//! Interactive command shell.
+//!
+//! Reads commands from the UART console and runs them, so that the system can
+//! be inspected and controlled without rebuilding it.
+
+use core::str::SplitWhitespace;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::board::mmu::{PERIPHERALS, PERIPHERALS_LEN};
+use crate::hdmi::{self, MAX_TONE_FREQ, MAX_VOLUME};
+use crate::log::{self, Level};
+use crate::mbox::{Mailbox, Message, Property};
+use crate::smp::{self, CORES};
+use crate::timer::{poll, Instant};
+use crate::uart::{DataBits, Parity, StopBits, TxPolicy, Uart, UartConfig};
+use crate::{chainload, pm, println, stream};
+
+/// Maximum length of a command line.
+const LINE_LEN: usize = 128;
+/// Maximum number of words in a mailbox property payload.
+const MBOX_WORDS: usize = 16;
+/// Maximum time a secondary core may take to run the test closure.
+const CORE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Microseconds each secondary core took to start running the test closure,
+/// or zero if it didn't run it.
+static CORE_LATENCIES: [AtomicU64; CORES] = [const { AtomicU64::new(0) }; CORES];
+
+/// Shell command.
+struct Command
+{
+    /// Name typed to run the command.
+    name: &'static str,
+    /// Argument synopsis.
+    args: &'static str,
+    /// Short description.
+    help: &'static str,
+    /// Implementation.
+    run: fn(&mut SplitWhitespace) -> Result<(), &'static str>,
+}
+
+/// Available commands.
+static COMMANDS: [Command; 18] = [Command { name: "help",
+                                            args: "",
+                                            help: "Lists the available commands",
+                                            run: help },
+                                  Command { name: "mbox",
+                                            args: "<tag> [word ...]",
+                                            help: "Queries a mailbox property",
+                                            run: mbox },
+                                  Command { name: "regs",
+                                            args: "",
+                                            help: "Dumps the HDMI audio registers",
+                                            run: regs },
+                                  Command { name: "tone",
+                                            args: "<left Hz> <right Hz>",
+                                            help: "Changes the tone frequencies",
+                                            run: tone },
+                                  Command { name: "volume",
+                                            args: "<percent>",
+                                            help: "Changes the tone volume",
+                                            run: volume },
+                                  Command { name: "start",
+                                            args: "",
+                                            help: "Starts playing audio",
+                                            run: start },
+                                  Command { name: "stop",
+                                            args: "",
+                                            help: "Stops playing audio",
+                                            run: stop },
+                                  Command { name: "stream",
+                                            args: "",
+                                            help: "Plays audio streamed by the host",
+                                            run: stream },
+                                  Command { name: "peek",
+                                            args: "<address>",
+                                            help: "Reads a 32 bit MMIO register",
+                                            run: peek },
+                                  Command { name: "poke",
+                                            args: "<address> <value>",
+                                            help: "Writes a 32 bit MMIO register",
+                                            run: poke },
+                                  Command { name: "cores",
+                                            args: "",
+                                            help: "Runs a test closure on each secondary core",
+                                            run: cores },
+                                  Command { name: "reboot",
+                                            args: "",
+                                            help: "Reboots the system",
+                                            run: reboot },
+                                  Command { name: "chainload",
+                                            args: "",
+                                            help: "Boots a kernel image sent by the host",
+                                            run: chainload },
+                                  Command { name: "log",
+                                            args: "[<level|off> [module]]",
+                                            help: "Shows or changes the log levels",
+                                            run: log },
+                                  Command { name: "logsink",
+                                            args: "<name> <on|off>",
+                                            help: "Enables or disables a log sink",
+                                            run: logsink },
+                                  Command { name: "uart",
+                                            args: "[<baud> <framing> <on|off>]",
+                                            help: "Shows or changes the line settings",
+                                            run: uart },
+                                  Command { name: "txpolicy",
+                                            args: "<block|drop>",
+                                            help: "Changes what happens to output when the UART is busy",
+                                            run: txpolicy },
+                                  Command { name: "dmesg",
+                                            args: "",
+                                            help: "Prints the log kept in memory",
+                                            run: dmesg }];
+
+/// Reads and runs commands forever, waiting for input without blocking other
+/// tasks.
+pub async fn run()
+{
+    Uart.write_async(b"Type help for a list of commands\r\n").await;
+    let mut buf = [0; LINE_LEN];
+    loop {
+        Uart.write_async(b"> ").await;
+        let line = match Uart.read_line_async(&mut buf).await {
+            Ok(line) => line,
+            Err(err) => {
+                println!("{err}");
+                continue;
+            }
+        };
+        let mut args = line.split_whitespace();
+        let Some(name) = args.next() else {
+            continue;
+        };
+        let Some(cmd) = COMMANDS.iter().find(|cmd| cmd.name == name) else {
+            println!("Unknown command: {name}");
+            continue;
+        };
+        if let Err(msg) = (cmd.run)(&mut args) {
+            println!("{msg}");
+            println!("Usage: {} {}", cmd.name, cmd.args);
+        }
+    }
+}
+
+/// Lists the available commands.
+fn help(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    for cmd in COMMANDS.iter() {
+        println!("{:<9} {:<27} {}", cmd.name, cmd.args, cmd.help);
+    }
+    Ok(())
+}
+
+/// Sends a property with the specified tag and request words through the
+/// mailbox and prints the response words.
+fn mbox(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let tag = word(args.next())?;
+    let mut input = [0u32; MBOX_WORDS];
+    for slot in input.iter_mut() {
+        let Some(arg) = args.next() else {
+            break;
+        };
+        *slot = word(Some(arg))?;
+    }
+    no_more(args)?;
+    let mut msg = Message::new();
+    msg.add_property(&Property::<[u32; MBOX_WORDS], [u32; MBOX_WORDS]>::new(tag, input));
+    if let Err(err) = Mailbox.exchange(&mut msg) {
+        println!("{err}");
+        return Ok(());
+    }
+    let prop = msg.find_property::<[u32; MBOX_WORDS], [u32; MBOX_WORDS]>(tag);
+    let Some(size) = prop.response_size() else {
+        println!("No response for tag 0x{tag:X}");
+        return Ok(());
+    };
+    if size > MBOX_WORDS * 4 {
+        println!("Response to tag 0x{tag:X} is too large: {size} bytes");
+        return Ok(());
+    }
+    for (idx, word) in prop.payload()[.. size.div_ceil(4)].iter().enumerate() {
+        println!("{idx:>2}: 0x{word:08X} ({word})");
+    }
+    Ok(())
+}
+
+/// Dumps the HDMI audio registers.
+fn regs(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    hdmi::dump_registers();
+    Ok(())
+}
+
+/// Changes the frequencies of the tones played on each channel.
+fn tone(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let mut tone = hdmi::tone();
+    for freq in tone.freqs.iter_mut() {
+        *freq = word(args.next())?;
+        if *freq == 0 || *freq > MAX_TONE_FREQ {
+            return Err("Frequency out of range");
+        }
+    }
+    no_more(args)?;
+    hdmi::set_tone(tone);
+    Ok(())
+}
+
+/// Changes the volume of the tones.
+fn volume(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let mut tone = hdmi::tone();
+    tone.volume = word(args.next())?;
+    no_more(args)?;
+    if tone.volume > MAX_VOLUME {
+        return Err("Volume out of range");
+    }
+    hdmi::set_tone(tone);
+    Ok(())
+}
+
+/// Starts playing audio.
+fn start(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    hdmi::start_audio();
+    Ok(())
+}
+
+/// Stops playing audio.
+fn stop(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    hdmi::stop_audio();
+    Ok(())
+}
+
+/// Plays audio streamed by the host until it ends the stream.
+fn stream(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    println!("Waiting for the host to stream audio");
+    stream::run();
+    println!("Stream ended");
+    Ok(())
+}
+
+/// Reads a 32 bit MMIO register.
+fn peek(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let addr = address(args.next())?;
+    no_more(args)?;
+    let val = unsafe { (addr as *const u32).read_volatile() };
+    println!("0x{addr:X}: 0x{val:08X}");
+    Ok(())
+}
+
+/// Writes a 32 bit MMIO register.
+fn poke(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let addr = address(args.next())?;
+    let val = word(args.next())?;
+    no_more(args)?;
+    unsafe { (addr as *mut u32).write_volatile(val) };
+    Ok(())
+}
+
+/// Runs a closure on each secondary core in turn and prints how long each
+/// took to start running it.
+fn cores(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    for (core, latency) in CORE_LATENCIES.iter().enumerate().skip(1) {
+        latency.store(0, Ordering::Relaxed);
+        let requested = Instant::now();
+        let res = smp::start(core, move || {
+            // Never zero, so that it's distinguishable from not running.
+            let us = requested.elapsed().as_micros().max(1) as u64;
+            CORE_LATENCIES[smp::core_id()].store(us, Ordering::Relaxed);
+        });
+        if let Err(err) = res {
+            println!("Core {core}: {err}");
+            continue;
+        }
+        if poll(CORE_TIMEOUT, || !smp::is_running(core)).is_err() {
+            println!("Core {core}: Timed out");
+            continue;
+        }
+        match latency.load(Ordering::Relaxed) {
+            0 => println!("Core {core}: Closure ran on the wrong core"),
+            us => println!("Core {core}: Started after {us} us"),
+        }
+    }
+    Ok(())
+}
+
+/// Reboots the system.
+fn reboot(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    pm::reboot()
+}
+
+/// Receives a kernel image from the host and boots it.
+fn chainload(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    println!("Waiting for the host to send a kernel image");
+    let image = chainload::receive();
+    println!("Booting {} byte kernel image", image.len());
+    chainload::boot(&image)
+}
+
+/// Shows the log configuration, or changes the default log level or that of a
+/// module.
+fn log(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let Some(level) = args.next() else {
+        log::dump_config();
+        return Ok(());
+    };
+    let level = match level {
+        "off" => None,
+        level => Some(level.parse::<Level>()?),
+    };
+    let module = args.next();
+    no_more(args)?;
+    log::set_level(module, level)
+}
+
+/// Enables or disables a log sink.
+fn logsink(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let name = args.next().ok_or("Missing argument")?;
+    let enabled = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(_) => return Err("Invalid state"),
+        None => return Err("Missing argument"),
+    };
+    no_more(args)?;
+    log::set_sink_enabled(name, enabled)
+}
+
+/// Shows the UART line configuration and dropped output, or changes the line
+/// configuration.
+fn uart(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let Some(baud_rate) = args.next() else {
+        let config = Uart.config();
+        let data_bits = match config.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match config.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+            Parity::Mark => 'M',
+            Parity::Space => 'S',
+        };
+        let stop_bits = match config.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let flow_control = if config.flow_control { "on" } else { "off" };
+        println!("{} {data_bits}{parity}{stop_bits}, flow control {flow_control}",
+                 config.baud_rate);
+        println!("{} bytes dropped", Uart.dropped());
+        return Ok(());
+    };
+    let baud_rate = word(Some(baud_rate))?;
+    let framing = args.next().ok_or("Missing argument")?.as_bytes();
+    let &[data_bits, parity, stop_bits] = framing else {
+        return Err("Invalid framing");
+    };
+    let data_bits = match data_bits {
+        b'5' => DataBits::Five,
+        b'6' => DataBits::Six,
+        b'7' => DataBits::Seven,
+        b'8' => DataBits::Eight,
+        _ => return Err("Invalid data bits"),
+    };
+    let parity = match parity.to_ascii_uppercase() {
+        b'N' => Parity::None,
+        b'O' => Parity::Odd,
+        b'E' => Parity::Even,
+        b'M' => Parity::Mark,
+        b'S' => Parity::Space,
+        _ => return Err("Invalid parity"),
+    };
+    let stop_bits = match stop_bits {
+        b'1' => StopBits::One,
+        b'2' => StopBits::Two,
+        _ => return Err("Invalid stop bits"),
+    };
+    let flow_control = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(_) => return Err("Invalid flow control"),
+        None => return Err("Missing argument"),
+    };
+    no_more(args)?;
+    let config = UartConfig { baud_rate,
+                              data_bits,
+                              parity,
+                              stop_bits,
+                              flow_control };
+    if let Err(err) = Uart.configure(config) {
+        println!("{err}");
+    }
+    Ok(())
+}
+
+/// Changes what happens to output written when the UART transmit ring buffer
+/// is full.
+fn txpolicy(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    let policy = match args.next() {
+        Some("block") => TxPolicy::Block,
+        Some("drop") => TxPolicy::Drop,
+        Some(_) => return Err("Invalid policy"),
+        None => return Err("Missing argument"),
+    };
+    no_more(args)?;
+    Uart.set_tx_policy(policy);
+    Ok(())
+}
+
+/// Prints the log records kept in memory.
+fn dmesg(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    no_more(args)?;
+    log::dump_history();
+    Ok(())
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+///
+/// * `arg`: Argument to parse.
+///
+/// Returns the parsed number.
+fn number(arg: Option<&str>) -> Result<usize, &'static str>
+{
+    let arg = arg.ok_or("Missing argument")?;
+    let res = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    res.map_err(|_| "Invalid number")
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal 32 bit number.
+///
+/// * `arg`: Argument to parse.
+///
+/// Returns the parsed number.
+fn word(arg: Option<&str>) -> Result<u32, &'static str>
+{
+    u32::try_from(number(arg)?).map_err(|_| "Value does not fit in 32 bits")
+}
+
+/// Parses the address of a 32 bit register in one of the peripheral windows,
+/// which are identity mapped at boot, so that a mistyped address is rejected
+/// rather than faulting.
+///
+/// * `arg`: Argument to parse.
+///
+/// Returns the parsed address.
+fn address(arg: Option<&str>) -> Result<usize, &'static str>
+{
+    let addr = number(arg)?;
+    if addr & 0x3 != 0 {
+        return Err("Address is not 32 bit aligned");
+    }
+    if !PERIPHERALS.iter()
+                   .any(|start| addr >= *start && addr - start < PERIPHERALS_LEN)
+    {
+        return Err("Address is outside the peripheral windows");
+    }
+    Ok(addr)
+}
+
+/// Checks that all the arguments have been consumed.
+///
+/// * `args`: Remaining arguments.
+fn no_more(args: &mut SplitWhitespace) -> Result<(), &'static str>
+{
+    match args.next() {
+        Some(_) => Err("Too many arguments"),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,362 @@
+//! Secondary core bring-up.
+//!
+//! The firmware keeps the secondary cores powered off until they're turned on
+//! through PSCI, or on boards without PSCI parks them until they're released
+//! through a spin table, after which they run the boot code to set up their
+//! own stacks, exception vectors and MMU like the boot core, and then run the
+//! closure they were started with.  Once the closure returns, the core powers
+//! itself off, or if it can't, parks itself outside the kernel image waiting on
+//! the spin table like the firmware does, so that it can be started again by
+//! this kernel or by one chainloaded in its place.
+
+use alloc::boxed::Box;
+use core::arch::{asm, global_asm};
+use core::fmt::{self, Display, Formatter};
+use core::ptr::{addr_of, null_mut};
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use core::time::Duration;
+
+use crate::board::smp::{CORE_SHIFT, ENABLE_METHOD};
+use crate::board::EnableMethod;
+use crate::sync::Once;
+use crate::timer::{poll, TimedOut};
+use crate::{debug, error, halt, irq, mmu};
+
+/// Number of cores.
+pub const CORES: usize = 4;
+/// Size of each guard page.
+pub const GUARD_SIZE: usize = 0x1000;
+/// Size of the stacks of each core, laid out by the boot code as a guard page
+/// followed by the ELn stack, and another guard page followed by the EL0 stack.
+const CORE_STACKS_SIZE: usize = 0x4000;
+/// Offset of the EL0 guard page in the stacks of each core.
+const EL0_GUARD: usize = 0x2000;
+/// PSCI function that powers on a core, with 64 bit arguments.
+const CPU_ON: usize = 0xC4000003;
+/// PSCI function that powers off the calling core.
+const CPU_OFF: usize = 0x84000002;
+/// PSCI function that reports the power state of a core, with 64 bit
+/// arguments.
+const AFFINITY_INFO: usize = 0xC4000004;
+/// Power state reported by [`AFFINITY_INFO`] for a core that is off.
+const AFFINITY_OFF: isize = 1;
+/// Mask of the MMU, data cache and instruction cache enable bits of the system
+/// control register.
+const SCTLR_MMU_CACHES: usize = 0x1005;
+/// Maximum time for a secondary core to stop running kernel code after
+/// finishing its closure.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Closure run by a secondary core.
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Closures waiting to be picked up by the cores that were started to run
+/// them, indexed by core.
+static TASKS: [AtomicPtr<Task>; CORES] = [const { AtomicPtr::new(null_mut()) }; CORES];
+/// Whether each core is running a closure, indexed by core.
+static RUNNING: [AtomicBool; CORES] = [const { AtomicBool::new(false) }; CORES];
+/// Whether the parking loop has been copied out of the kernel image.
+static PARK_LOOP: Once<()> = Once::new();
+
+extern "C" {
+    /// Stacks of every core, defined by the boot code.
+    static stacks: u8;
+    /// Secondary core entry point, defined by the boot code.
+    static secondary_boot: u8;
+    /// Start of the parking loop.
+    static park_loop: u8;
+    /// End of the parking loop.
+    static park_loop_end: u8;
+}
+
+// Parking loop for the cores released from a spin table, which is copied out of
+// the kernel image and waits for the release address of the core to be written
+// again like the firmware does.
+//
+// x0: Physical address of the release address of the core.
+//
+// Runs with the MMU and caches disabled, and clears the release address before
+// waiting, which tells the boot core that the core is no longer running kernel
+// code.
+global_asm!(".section .text",
+            ".balign 4",
+            "park_loop:",
+            "    str xzr, [x0]",
+            "    dsb sy",
+            "0:",
+            "    wfe",
+            "    ldr x1, [x0]",
+            "    cbz x1, 0b",
+            "    br x1",
+            "park_loop_end:");
+
+/// PSCI error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PsciError
+{
+    /// The function is not implemented.
+    NotSupported,
+    /// An argument was rejected.
+    InvalidParameters,
+    /// The firmware refused to perform the operation.
+    Denied,
+    /// The core is already powered on.
+    AlreadyOn,
+    /// The core is already being powered on.
+    OnPending,
+    /// The firmware failed internally.
+    InternalFailure,
+    /// The entry point address was rejected.
+    InvalidAddress,
+    /// Any other error code.
+    Other(isize),
+}
+
+impl PsciError
+{
+    /// Converts a PSCI return code.
+    ///
+    /// * `code`: Return code.
+    ///
+    /// Returns `Ok` on success, or the error.
+    fn check(code: isize) -> Result<(), Self>
+    {
+        match code {
+            0 => Ok(()),
+            -1 => Err(Self::NotSupported),
+            -2 => Err(Self::InvalidParameters),
+            -3 => Err(Self::Denied),
+            -4 => Err(Self::AlreadyOn),
+            -5 => Err(Self::OnPending),
+            -6 => Err(Self::InternalFailure),
+            -9 => Err(Self::InvalidAddress),
+            code => Err(Self::Other(code)),
+        }
+    }
+}
+
+impl Display for PsciError
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::NotSupported => fmt.write_str("PSCI function not supported"),
+            Self::InvalidParameters => fmt.write_str("Invalid PSCI parameters"),
+            Self::Denied => fmt.write_str("PSCI operation denied"),
+            Self::AlreadyOn => fmt.write_str("Core already on"),
+            Self::OnPending => fmt.write_str("Core already being powered on"),
+            Self::InternalFailure => fmt.write_str("PSCI internal failure"),
+            Self::InvalidAddress => fmt.write_str("Invalid PSCI entry point"),
+            Self::Other(code) => write!(fmt, "PSCI error {code}"),
+        }
+    }
+}
+
+/// Returns the index of the current core.
+pub fn core_id() -> usize
+{
+    let mpidr: usize;
+    unsafe {
+        asm!(
+            "mrs {mpidr}, mpidr_el1",
+            mpidr = out (reg) mpidr,
+            options (nomem, nostack, preserves_flags));
+    }
+    mpidr >> CORE_SHIFT & 0xFF
+}
+
+/// Returns the addresses of the guard pages below the ELn and EL0 stacks of a
+/// core.
+///
+/// * `core`: Index of the core.
+pub fn guard_pages(core: usize) -> [usize; 2]
+{
+    let base = addr_of!(stacks) as usize + core * CORE_STACKS_SIZE;
+    [base, base + EL0_GUARD]
+}
+
+/// Returns whether a secondary core is running a closure.
+///
+/// * `core`: Index of the core.
+pub fn is_running(core: usize) -> bool
+{
+    RUNNING[core].load(Ordering::Acquire)
+}
+
+/// Powers on or releases a secondary core to run a closure, after which the
+/// core powers itself off or parks itself until it's released again.
+///
+/// * `core`: Index of the core, which must not be the boot core.
+/// * `f`: Closure to run on the core, with IRQs masked until it unmasks them.
+///
+/// Returns an error if the core is still running a previous closure or hasn't
+/// stopped after it, or the firmware fails to power it on.
+///
+/// Panics if the core index is out of range.
+#[track_caller]
+pub fn start<F: FnOnce() + Send + 'static>(core: usize, f: F) -> Result<(), PsciError>
+{
+    assert!((1 .. CORES).contains(&core), "Core {core} is not a secondary core");
+    if RUNNING[core].swap(true, Ordering::AcqRel) {
+        return Err(PsciError::AlreadyOn);
+    }
+    // The core may still be on its way out after its previous closure.
+    if wait_stopped(core).is_err() {
+        RUNNING[core].store(false, Ordering::Release);
+        return Err(PsciError::AlreadyOn);
+    }
+    let task: Task = Box::new(f);
+    TASKS[core].store(Box::into_raw(Box::new(task)), Ordering::Release);
+    let entry = addr_of!(secondary_boot) as usize;
+    let res = match ENABLE_METHOD {
+        EnableMethod::Psci => unsafe { psci(CPU_ON, core << CORE_SHIFT, entry) },
+        EnableMethod::SpinTable { table, park } => {
+            release(table, park, core, entry);
+            0
+        }
+    };
+    if let Err(err) = PsciError::check(res) {
+        // The core never started, so the closure is still there.
+        drop(unsafe { Box::from_raw(TASKS[core].swap(null_mut(), Ordering::Acquire)) });
+        RUNNING[core].store(false, Ordering::Release);
+        return Err(err);
+    }
+    debug!("Started core {core}");
+    Ok(())
+}
+
+/// Calls a PSCI function through the secure monitor.
+///
+/// * `func`: Function ID.
+/// * `arg0`: First argument.
+/// * `arg1`: Second argument.
+///
+/// Returns the return code of the function.
+unsafe fn psci(func: usize, arg0: usize, arg1: usize) -> isize
+{
+    let res: isize;
+    asm!(
+        "smc #0",
+        inout ("x0") func => res,
+        in ("x1") arg0,
+        in ("x2") arg1,
+        in ("x3") 0,
+        clobber_abi ("C"),
+        options (nostack));
+    res
+}
+
+/// Waits for a secondary core that isn't running a closure to stop running
+/// kernel code, after which the kernel image can be overwritten and the core
+/// can be started again.
+///
+/// * `core`: Index of the core.
+///
+/// Returns an error if the core is still running kernel code after
+/// [`STOP_TIMEOUT`].
+pub fn wait_stopped(core: usize) -> Result<(), TimedOut>
+{
+    let res = match ENABLE_METHOD {
+        EnableMethod::Psci => poll(STOP_TIMEOUT, || {
+            let res = unsafe { psci(AFFINITY_INFO, core << CORE_SHIFT, 0) };
+            // The core can't be waited for without the power state, so it's up
+            // to powering it on again to fail if it's still on.
+            if let Err(err) = PsciError::check(res.min(0)) {
+                error!("Failed to query the power state of core {core}: {err}");
+                return true;
+            }
+            res == AFFINITY_OFF
+        }),
+        EnableMethod::SpinTable { table, .. } => {
+            let slot = mmu::map_device(table + core * 8, 8);
+            let res = poll(STOP_TIMEOUT, || unsafe { slot.reg::<u64>(0).read_volatile() } == 0);
+            mmu::unmap_device(slot, 8);
+            res
+        }
+    };
+    if res.is_err() {
+        error!("Core {core} didn't stop in time");
+    }
+    res
+}
+
+/// Releases a secondary core parked by the firmware or by the parking loop in a
+/// spin table.
+///
+/// * `table`: Physical address of the spin table.
+/// * `park`: Physical address of the parking loop.
+/// * `core`: Index of the core.
+/// * `entry`: Physical address of the entry point.
+fn release(table: usize, park: usize, core: usize, entry: usize)
+{
+    PARK_LOOP.call_once(|| {
+                 let start = addr_of!(park_loop) as *const u32;
+                 let len = addr_of!(park_loop_end) as usize - start as usize;
+                 let dest = mmu::map_device(park, len);
+                 // A previous kernel may have left cores running the same loop.
+                 for idx in 0 .. len / 4 {
+                     unsafe {
+                         let insn = start.add(idx).read();
+                         if dest.reg::<u32>(idx * 4).read_volatile() != insn {
+                             dest.reg::<u32>(idx * 4).write_volatile(insn);
+                         }
+                     }
+                 }
+                 mmu::unmap_device(dest, len);
+             });
+    let slot = mmu::map_device(table + core * 8, 8);
+    unsafe {
+        slot.reg::<u64>(0).write_volatile(entry as u64);
+        // The core waits for an event with the MMU off, so the release address
+        // must reach memory before the event is signaled.
+        asm!("dsb sy", "sev", options(nostack, preserves_flags));
+    }
+    mmu::unmap_device(slot, 8);
+}
+
+/// Entry point of the secondary cores, called by the boot code with the MMU
+/// enabled.
+#[no_mangle]
+extern "C" fn secondary_start() -> !
+{
+    let core = core_id();
+    irq::init_core();
+    // The closure is always stored before the core is started.
+    let task = unsafe { Box::from_raw(TASKS[core].swap(null_mut(), Ordering::Acquire)) };
+    task();
+    irq::disable();
+    debug!("Core {core} finished");
+    RUNNING[core].store(false, Ordering::Release);
+    match ENABLE_METHOD {
+        EnableMethod::Psci => {
+            let res = unsafe { psci(CPU_OFF, 0, 0) };
+            // Powering off only returns on failure.
+            if let Err(err) = PsciError::check(res) {
+                error!("Core {core} failed to power off: {err}");
+            }
+            halt()
+        }
+        // Cores released from a spin table can't be powered off, so they
+        // wait outside the kernel image to be released again instead, where
+        // they're safe from a chainloaded kernel.  The kernel is identity
+        // mapped, so execution carries on seamlessly once the MMU is
+        // disabled.
+        EnableMethod::SpinTable { table, park } => unsafe {
+            asm!(
+                "msr daifset, #0xf",
+                "dsb sy",
+                "mrs x1, sctlr_el1",
+                "bic x1, x1, x2",
+                "msr sctlr_el1, x1",
+                "isb",
+                "ic iallu",
+                "dsb sy",
+                "isb",
+                "br x3",
+                in ("x0") table + core * 8,
+                in ("x2") SCTLR_MMU_CACHES,
+                in ("x3") park,
+                options (noreturn, nostack));
+        },
+    }
+}
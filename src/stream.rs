@@ -0,0 +1,125 @@
+//! Audio stream receiver.
+//!
+//! Receives PCM audio sent by the host over the UART using the framing
+//! protocol and plays it through HDMI.  Acknowledgements are delayed until the
+//! samples of each frame fit in the stream queue, which throttles the host to
+//! the playback rate.
+
+use crate::hdmi::{self, CHANNELS, SAMPLE_RATE};
+use crate::proto::{self, reply, Format, Kind, Link, RecvError, MAX_PAYLOAD, NAK_CORRUPT, NAK_FORMAT, NAK_UNEXPECTED,
+                   PCM_S16LE};
+use crate::uart::{RxError, Uart};
+
+/// UART transport that keeps the audio buffer fed while waiting for data.
+struct UartLink;
+
+impl Link for UartLink
+{
+    type Error = RxError;
+
+    fn read_byte(&mut self) -> Result<u8, RxError>
+    {
+        loop {
+            if let Some(res) = Uart.try_read_byte() {
+                return res;
+            }
+            hdmi::refill();
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RxError>
+    {
+        Uart.write_bytes(bytes);
+        Ok(())
+    }
+}
+
+/// Receives and plays an audio stream, returning once the host ends it.
+pub fn run()
+{
+    let mut link = UartLink;
+    let mut buf = [0; MAX_PAYLOAD];
+    // Channel count of the stream once it has started.
+    let mut channels = None;
+    let mut next = 0u8;
+    loop {
+        let frame = match proto::receive(&mut link, &mut buf) {
+            Ok(frame) => frame,
+            Err(RecvError::Link(_) | RecvError::Corrupt) => {
+                reply(&mut link, Kind::Nak, next, &[NAK_CORRUPT]);
+                continue;
+            }
+        };
+        match frame.kind {
+            Kind::Start => {
+                let Some(format) = Format::from_bytes(frame.payload).filter(is_supported) else {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_FORMAT]);
+                    continue;
+                };
+                // Restarting is harmless if the host is resending the start frame
+                // because the acknowledgement got lost, since no data has been
+                // played yet.
+                hdmi::start_stream();
+                channels = Some(format.channels);
+                next = frame.seq.wrapping_add(1);
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+            }
+            Kind::Data => {
+                let Some(channels) = channels else {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_UNEXPECTED]);
+                    continue;
+                };
+                if frame.seq == next.wrapping_sub(1) {
+                    // The acknowledgement got lost and the host resent a frame that has
+                    // already been queued.
+                    reply(&mut link, Kind::Ack, frame.seq, &[]);
+                    continue;
+                }
+                if frame.seq != next {
+                    reply(&mut link, Kind::Nak, frame.seq, &[NAK_UNEXPECTED]);
+                    continue;
+                }
+                queue(frame.payload, channels);
+                next = next.wrapping_add(1);
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+            }
+            Kind::End => {
+                reply(&mut link, Kind::Ack, frame.seq, &[]);
+                if channels.is_some() {
+                    hdmi::finish_stream();
+                }
+                return;
+            }
+            Kind::Ack | Kind::Nak => (),
+        }
+    }
+}
+
+/// Returns whether a stream format can be played.
+///
+/// * `format`: Format to check.
+fn is_supported(format: &Format) -> bool
+{
+    format.encoding == PCM_S16LE
+    && format.rate == SAMPLE_RATE
+    && format.channels > 0
+    && format.channels as u32 <= CHANNELS
+}
+
+/// Queues the samples of a data frame, waiting for room if necessary.
+///
+/// * `payload`: Interleaved signed 16 bit little endian samples.
+/// * `channels`: Number of channels in the stream, with mono streams being
+///   played on both channels.
+fn queue(payload: &[u8], channels: u8)
+{
+    for sample in payload.chunks_exact(2) {
+        let sample = u16::from_le_bytes([sample[0], sample[1]]);
+        for _ in 0 .. CHANNELS / channels as u32 {
+            // The stream receiver is the only producer.
+            while !unsafe { hdmi::push_sample(sample) } {
+                hdmi::refill();
+            }
+        }
+    }
+}
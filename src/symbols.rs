@@ -0,0 +1,74 @@
+//! Embedded symbol table.
+//!
+//! The build links the kernel twice, generating a table that maps code
+//! addresses to demangled function names from the first link with the
+//! `symtab` tool and embedding it in the second.  The table is placed after
+//! the code and data, so embedding it doesn't move any functions.
+//!
+//! The table starts with the number of symbols as a little endian 32 bit word,
+//! followed by pairs of little endian 32 bit words with the address of each
+//! symbol and the offset of its name sorted by address, a final pair with the
+//! end of the code and the total length of the names, and the names
+//! themselves.
+
+use core::ptr::addr_of;
+use core::slice::from_raw_parts as slice_from_raw_parts;
+use core::str::from_utf8;
+
+#[used]
+#[link_section = ".symbols"]
+static TABLE: [u8; include_bytes!(env!("RPI_HDMI_SYMBOLS")).len()] = *include_bytes!(env!("RPI_HDMI_SYMBOLS"));
+
+extern "C" {
+    /// Start of the symbol table, defined by the linker script.
+    static symbols_start: u8;
+    /// Size of the symbol table, defined by the linker script as an absolute
+    /// symbol so that the code doesn't depend on the table.
+    static symbols_size: u8;
+}
+
+/// Returns the function containing an address.
+///
+/// * `addr`: Address to look up.
+///
+/// Returns the name and start address of the function, or `None` if the
+/// address is not covered by the symbol table.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)>
+{
+    let table = unsafe { slice_from_raw_parts(addr_of!(symbols_start), addr_of!(symbols_size) as usize) };
+    let count = word(table, 0)? as usize;
+    let entry = |idx: usize| Some((word(table, 4 + idx * 8)? as usize, word(table, 8 + idx * 8)? as usize));
+    let names = table.get(4 + (count + 1) * 8 ..)?;
+    // Find the last symbol starting at or before the address.
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if entry(mid)?.0 <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo == 0 {
+        return None;
+    }
+    let (start, name_start) = entry(lo - 1)?;
+    let (end, name_end) = entry(lo)?;
+    if addr >= end {
+        return None;
+    }
+    let name = from_utf8(names.get(name_start .. name_end)?).ok()?;
+    Some((name, start))
+}
+
+/// Reads a little endian 32 bit word from the symbol table.
+///
+/// * `table`: Symbol table.
+/// * `offset`: Offset of the word in bytes.
+///
+/// Returns the word, or `None` if it's out of bounds.
+fn word(table: &[u8], offset: usize) -> Option<u32>
+{
+    let bytes = table.get(offset .. offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
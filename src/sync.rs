@@ -0,0 +1,369 @@
+//! Synchronization primitives.
+//!
+//! Implements a fair ticket spinlock that can optionally mask IRQs on the
+//! current core while held, a one time initializer, and a lock-free single
+//! producer single consumer queue for handing data from interrupt handlers to
+//! the code that consumes it.
+//!
+//! The spinlock remembers which core holds it, so that attempting to take it
+//! again on the same core, which would otherwise deadlock, panics instead.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::{irq, smp};
+
+/// Owner value of a spinlock that is not held by any core.
+const NO_OWNER: usize = usize::MAX;
+/// State of a one time initializer that hasn't run yet.
+const INCOMPLETE: usize = 0;
+/// State of a one time initializer that has completed.
+const COMPLETE: usize = 1;
+/// State of a one time initializer running on a core, added to the index of
+/// the core.
+const RUNNING: usize = 2;
+
+/// Fair spinlock that grants access to the protected data in the order in which
+/// it was requested.
+pub struct SpinLock<T: ?Sized>
+{
+    /// Next ticket to hand out.
+    next: AtomicU32,
+    /// Ticket currently being served.
+    serving: AtomicU32,
+    /// Index of the core holding the lock, or [`NO_OWNER`].
+    owner: AtomicUsize,
+    /// Protected data.
+    data: UnsafeCell<T>,
+}
+
+/// Exclusive access to the data protected by a [`SpinLock`], which is released
+/// when the guard goes out of scope.
+pub struct SpinLockGuard<'a, T: ?Sized>
+{
+    /// Lock being held.
+    lock: &'a SpinLock<T>,
+    /// Ticket that was served.
+    ticket: u32,
+}
+
+/// Exclusive access to the data protected by a [`SpinLock`] with IRQs masked on
+/// the current core, which releases the lock and restores the previous IRQ
+/// mask when it goes out of scope.
+pub struct IrqGuard<'a, T: ?Sized>
+{
+    /// Guard of the lock being held.
+    guard: ManuallyDrop<SpinLockGuard<'a, T>>,
+    /// Whether IRQs were unmasked before the lock was taken.
+    enabled: bool,
+}
+
+/// Value initialized at most once, the first time it's requested.
+pub struct Once<T>
+{
+    /// Initialization state.
+    state: AtomicUsize,
+    /// Value, initialized once the state is complete.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Lock-free fixed capacity queue that can be shared by one producer and one
+/// consumer, typically an interrupt handler and the code it feeds.
+pub struct Spsc<T: Copy, const N: usize>
+{
+    /// Storage.
+    buf: [UnsafeCell<MaybeUninit<T>>; N],
+    /// Total number of elements ever popped, wrapping around.
+    head: AtomicUsize,
+    /// Total number of elements ever pushed, wrapping around.
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+unsafe impl<T: ?Sized + Sync> Sync for SpinLockGuard<'_, T> {}
+
+unsafe impl<T: Send> Send for Once<T> {}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+unsafe impl<T: Copy + Send, const N: usize> Sync for Spsc<T, N> {}
+
+impl<T> SpinLock<T>
+{
+    /// Creates and initializes a new unlocked spinlock.
+    ///
+    /// * `data`: Data to protect.
+    ///
+    /// Returns the newly created spinlock.
+    pub const fn new(data: T) -> Self
+    {
+        Self { next: AtomicU32::new(0),
+               serving: AtomicU32::new(0),
+               owner: AtomicUsize::new(NO_OWNER),
+               data: UnsafeCell::new(data) }
+    }
+}
+
+impl<T: ?Sized> SpinLock<T>
+{
+    /// Waits for the lock to become available and takes it.
+    ///
+    /// Returns a guard granting exclusive access to the protected data.
+    ///
+    /// Panics if the current core already holds the lock.
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T>
+    {
+        let core = smp::core_id();
+        assert!(self.owner.load(Ordering::Relaxed) != core,
+                "Lock already held by core {core}");
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+        self.owner.store(core, Ordering::Relaxed);
+        SpinLockGuard { lock: self, ticket }
+    }
+
+    /// Masks IRQs on the current core, then waits for the lock to become
+    /// available and takes it, which is required for locks also taken by
+    /// interrupt handlers.
+    ///
+    /// Returns a guard granting exclusive access to the protected data.
+    ///
+    /// Panics if the current core already holds the lock.
+    #[track_caller]
+    pub fn lock_irq(&self) -> IrqGuard<'_, T>
+    {
+        let enabled = irq::is_enabled();
+        irq::disable();
+        IrqGuard { guard: ManuallyDrop::new(self.lock()),
+                   enabled }
+    }
+
+    /// Takes the lock if it's available.
+    ///
+    /// Returns a guard granting exclusive access to the protected data, or
+    /// `None` if the lock is held.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>>
+    {
+        let ticket = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(ticket, ticket.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        self.owner.store(smp::core_id(), Ordering::Relaxed);
+        Some(SpinLockGuard { lock: self, ticket })
+    }
+
+    /// Returns whether the current core holds the lock.
+    pub fn is_held_here(&self) -> bool
+    {
+        self.owner.load(Ordering::Relaxed) == smp::core_id()
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T>
+{
+    fn drop(&mut self)
+    {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.serving.store(self.ticket.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<T: ?Sized> Deref for IrqGuard<'_, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqGuard<'_, T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqGuard<'_, T>
+{
+    fn drop(&mut self)
+    {
+        // The lock must be released before an interrupt handler can try to take
+        // it.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.enabled {
+            irq::enable();
+        }
+    }
+}
+
+impl<T> Once<T>
+{
+    /// Creates and initializes a new uninitialized value.
+    ///
+    /// Returns the newly created initializer.
+    pub const fn new() -> Self
+    {
+        Self { state: AtomicUsize::new(INCOMPLETE),
+               value: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+
+    /// Initializes the value if that hasn't been done yet, waiting for any
+    /// initialization in progress on another core to complete.
+    ///
+    /// * `init`: Closure that computes the value.
+    ///
+    /// Returns the value, or `None` if it's being initialized by the current
+    /// core, which happens when the initialization ends up requesting the value
+    /// itself.
+    pub fn call_once<F: FnOnce() -> T>(&self, init: F) -> Option<&T>
+    {
+        let running = RUNNING + smp::core_id();
+        loop {
+            match self.state
+                      .compare_exchange(INCOMPLETE, running, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    unsafe { (*self.value.get()).write(init()) };
+                    self.state.store(COMPLETE, Ordering::Release);
+                    break;
+                }
+                Err(COMPLETE) => break,
+                Err(state) if state == running => return None,
+                Err(_) => spin_loop(),
+            }
+        }
+        Some(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Returns the value, or `None` if it hasn't been initialized yet.
+    pub fn get(&self) -> Option<&T>
+    {
+        if self.state.load(Ordering::Acquire) != COMPLETE {
+            return None;
+        }
+        Some(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+}
+
+impl<T> Default for Once<T>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T>
+{
+    fn drop(&mut self)
+    {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> Spsc<T, N>
+{
+    /// Creates and initializes a new empty queue.
+    ///
+    /// Returns the newly created queue.
+    pub const fn new() -> Self
+    {
+        assert!(N.is_power_of_two(), "Queue capacity must be a power of two");
+        Self { buf: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+               head: AtomicUsize::new(0),
+               tail: AtomicUsize::new(0) }
+    }
+
+    /// Appends an element to the end of the queue.
+    ///
+    /// * `val`: Element to append.
+    ///
+    /// Returns whether the element was appended, which fails if the queue is
+    /// full.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be the only producer, so no other code may push to the
+    /// queue at the same time.
+    pub unsafe fn push(&self, val: T) -> bool
+    {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == N {
+            return false;
+        }
+        (*self.buf[tail & (N - 1)].get()).write(val);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Removes an element from the front of the queue.
+    ///
+    /// Returns the removed element, or `None` if the queue is empty.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be the only consumer, so no other code may pop from the
+    /// queue at the same time.
+    pub unsafe fn pop(&self) -> Option<T>
+    {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let val = (*self.buf[head & (N - 1)].get()).assume_init();
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(val)
+    }
+
+    /// Returns the number of elements in the queue, which may be out of date
+    /// by the time it's used unless called by the producer or the consumer.
+    pub fn len(&self) -> usize
+    {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head)
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Spsc<T, N>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
@@ -0,0 +1,224 @@
+//! ARM generic timer.
+//!
+//! Measures time with the system counter, which runs at a fixed frequency
+//! regardless of the CPU clock, and provides delays and timeout based polling
+//! built on top of it.
+
+use core::arch::asm;
+use core::fmt::{self, Display, Formatter};
+use core::hint::spin_loop;
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+/// Nanoseconds per second.
+const NANOS_PER_SEC: u64 = 1000000000;
+
+/// Point in time measured by the system counter.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Instant
+{
+    /// Counter value.
+    ticks: u64,
+}
+
+/// Error returned when a condition is not met in time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimedOut;
+
+/// Error returned when a hardware register doesn't reach the expected state in
+/// time, identifying the register that wedged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterTimeout
+{
+    /// Register name.
+    pub name: &'static str,
+    /// Register address.
+    pub addr: usize,
+    /// Last value read from the register.
+    pub value: u32,
+}
+
+impl Instant
+{
+    /// Returns the current time.
+    pub fn now() -> Self
+    {
+        let ticks: u64;
+        unsafe {
+            // The barrier prevents the counter from being read ahead of the code that
+            // precedes this function.
+            asm!(
+                "isb",
+                "mrs {ticks}, cntpct_el0",
+                ticks = out (reg) ticks,
+                options (nomem, nostack, preserves_flags));
+        }
+        Self { ticks }
+    }
+
+    /// Returns the time elapsed since this instant.
+    pub fn elapsed(self) -> Duration
+    {
+        Self::now() - self
+    }
+
+    /// Returns the time elapsed since an earlier instant, or zero if the other
+    /// instant is later than this one.
+    ///
+    /// * `earlier`: Earlier instant.
+    pub fn duration_since(self, earlier: Self) -> Duration
+    {
+        ticks_to_duration(self.ticks.saturating_sub(earlier.ticks))
+    }
+}
+
+impl Add<Duration> for Instant
+{
+    type Output = Self;
+
+    fn add(self, duration: Duration) -> Self
+    {
+        Self { ticks: self.ticks + duration_to_ticks(duration) }
+    }
+}
+
+impl Sub for Instant
+{
+    type Output = Duration;
+
+    fn sub(self, earlier: Self) -> Duration
+    {
+        self.duration_since(earlier)
+    }
+}
+
+impl Display for TimedOut
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        fmt.write_str("Timed out")
+    }
+}
+
+impl Display for RegisterTimeout
+{
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result
+    {
+        write!(fmt,
+               "Timed out waiting on {} at 0x{:X}, last read 0x{:X}",
+               self.name, self.addr, self.value)
+    }
+}
+
+/// Returns the frequency of the system counter in Hz.
+pub fn frequency() -> u64
+{
+    let freq: u64;
+    unsafe {
+        asm!(
+            "mrs {freq}, cntfrq_el0",
+            freq = out (reg) freq,
+            options (nomem, nostack, preserves_flags));
+    }
+    freq
+}
+
+/// Returns the time elapsed since the system counter was started at power
+/// on.
+pub fn uptime() -> Duration
+{
+    Instant::now() - Instant { ticks: 0 }
+}
+
+/// Waits for the specified amount of time.
+///
+/// * `duration`: Time to wait.
+pub fn delay(duration: Duration)
+{
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        spin_loop();
+    }
+}
+
+/// Waits for the specified number of milliseconds.
+///
+/// * `ms`: Milliseconds to wait.
+pub fn delay_ms(ms: u64)
+{
+    delay(Duration::from_millis(ms));
+}
+
+/// Repeatedly evaluates a condition until it holds or a timeout expires.
+///
+/// * `timeout`: Maximum time to wait.
+/// * `cond`: Condition to wait for.
+///
+/// Returns an error if the condition still doesn't hold after the timeout.
+pub fn poll<F: FnMut() -> bool>(timeout: Duration, mut cond: F) -> Result<(), TimedOut>
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if cond() {
+            return Ok(());
+        }
+        // Check the condition once more after the deadline, in case the wait was
+        // cut short by something else hogging the CPU.
+        if Instant::now() >= deadline {
+            return if cond() { Ok(()) } else { Err(TimedOut) };
+        }
+        spin_loop();
+    }
+}
+
+/// Repeatedly reads a hardware register until its value satisfies a condition
+/// or a timeout expires.
+///
+/// * `name`: Register name to report on timeout.
+/// * `reg`: Register to read.
+/// * `timeout`: Maximum time to wait.
+/// * `cond`: Condition to wait for, given the register value.
+///
+/// Returns the value that satisfied the condition, or an error identifying the
+/// register along with the last value read from it.
+///
+/// # Safety
+///
+/// `reg` must point to a readable register that tolerates repeated reads.
+pub unsafe fn poll_register<F: FnMut(u32) -> bool>(name: &'static str, reg: *const u32, timeout: Duration, mut cond: F)
+                                                   -> Result<u32, RegisterTimeout>
+{
+    let mut value = 0;
+    match poll(timeout, || {
+              value = reg.read_volatile();
+              cond(value)
+          }) {
+        Ok(()) => Ok(value),
+        Err(TimedOut) => Err(RegisterTimeout { name,
+                                               addr: reg as usize,
+                                               value }),
+    }
+}
+
+/// Converts a number of system counter ticks to a duration.
+///
+/// * `ticks`: Ticks to convert.
+///
+/// Returns the converted duration.
+fn ticks_to_duration(ticks: u64) -> Duration
+{
+    // Split the conversion to avoid overflowing the intermediate product.
+    let freq = frequency();
+    Duration::new(ticks / freq, (ticks % freq * NANOS_PER_SEC / freq) as u32)
+}
+
+/// Converts a duration to a number of system counter ticks, rounding up.
+///
+/// * `duration`: Duration to convert.
+///
+/// Returns the converted number of ticks.
+fn duration_to_ticks(duration: Duration) -> u64
+{
+    let freq = frequency();
+    duration.as_secs() * freq + (duration.subsec_nanos() as u64 * freq).div_ceil(NANOS_PER_SEC)
+}
@@ -1,35 +1,157 @@
 //! AMBA PL011 UART driver.
+//!
+//! The driver starts in polled mode, busy waiting on the FIFOs, and can be
+//! switched to interrupt driven mode, in which data is exchanged with the FIFOs
+//! through software ring buffers by the interrupt handler.
+//!
+//! The driver state is protected by a spinlock taken with IRQs masked, except
+//! for received data, which the interrupt handler hands over through a
+//! lock-free queue.  Panics are reported through [`DirectUart`], which doesn't
+//! take any locks.
+//!
+//! Tasks can read and write without blocking the other tasks on their core,
+//! in which case the interrupt handler wakes them when data arrives or room
+//! becomes available in the transmit ring buffer.
 
-use core::fmt::{Result, Write};
+use core::fmt::{self, Display, Formatter, Write};
+use core::future::poll_fn;
 use core::hint::spin_loop;
+use core::str::from_utf8_unchecked;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+
+use crate::board::uart as board;
+use crate::executor::WakerSlot;
+use crate::fdt::{Fdt, RegisterBlock};
+use crate::ring::Ring;
+use crate::sync::{Once, SpinLock, Spsc};
+use crate::timer::{poll_register, RegisterTimeout};
+use crate::{debug, mbox, warn};
 
-/// Base address.
-const BASE: usize = 0x107D001000;
 /// Data FIFO register.
-const DATA: *mut u32 = BASE as _;
+const DATA: usize = 0x0;
+/// Receive status and error clear register.
+const RX_STATUS: usize = 0x4;
 /// Flags register.
-const FLAGS: *mut u32 = (BASE + 0x18) as _;
+const FLAGS: usize = 0x18;
 /// Integer clock divisor.
-const INT_DIV: *mut u32 = (BASE + 0x24) as _;
+const INT_DIV: usize = 0x24;
 /// Fractional clock divisor.
-const FRAC_DIV: *mut u32 = (BASE + 0x28) as _;
+const FRAC_DIV: usize = 0x28;
+/// Line control register.
+const LINE_CTL: usize = 0x2C;
 /// Control register.
-const CTL: *mut u32 = (BASE + 0x30) as _;
-/// Clock rate.
-const CLOCK_RATE: u32 = 9216000;
-/// Desired BAUD rate.
-const BAUD_RATE: u32 = 115200;
+const CTL: usize = 0x30;
+/// Interrupt FIFO level select register.
+const FIFO_LEVEL: usize = 0x34;
+/// Interrupt mask register.
+const INT_MASK: usize = 0x38;
+/// Masked interrupt status register.
+const INT_STATUS: usize = 0x40;
+/// Interrupt clear register.
+const INT_CLEAR: usize = 0x44;
+/// UART clock ID in the mailbox clock properties.
+const CLOCK_ID: u32 = 0x2;
+/// Get clock rate property tag.
+const GET_CLOCK_RATE_TAG: u32 = 0x30002;
+/// Busy transmitting flag.
+const BUSY_FLAG: u32 = 0x8;
+/// Receive FIFO empty flag.
+const RXFE_FLAG: u32 = 0x10;
+/// Transmit FIFO full flag.
+const TXFF_FLAG: u32 = 0x20;
+/// Framing error bit in the data and receive status registers.
+const FRAMING_ERROR: u32 = 0x1;
+/// Parity error bit in the data and receive status registers.
+const PARITY_ERROR: u32 = 0x2;
+/// Break condition bit in the data and receive status registers.
+const BREAK_ERROR: u32 = 0x4;
+/// Overrun error bit in the data and receive status registers.
+const OVERRUN_ERROR: u32 = 0x8;
+/// UART enable bit in the control register.
+const UART_ENABLE: u32 = 0x1;
+/// Transmit enable bit in the control register.
+const TX_ENABLE: u32 = 0x100;
+/// Receive enable bit in the control register.
+const RX_ENABLE: u32 = 0x200;
+/// RTS hardware flow control enable bit in the control register.
+const RTS_ENABLE: u32 = 0x4000;
+/// CTS hardware flow control enable bit in the control register.
+const CTS_ENABLE: u32 = 0x8000;
+/// Parity enable bit in the line control register.
+const PARITY_ENABLE: u32 = 0x2;
+/// Even parity select bit in the line control register.
+const EVEN_PARITY: u32 = 0x4;
+/// Two stop bits select bit in the line control register.
+const TWO_STOP_BITS: u32 = 0x8;
+/// FIFO enable bit in the line control register.
+const FIFO_ENABLE: u32 = 0x10;
+/// Stick parity select bit in the line control register.
+const STICK_PARITY: u32 = 0x80;
+/// Receive interrupt bit.
+const RX_INT: u32 = 0x10;
+/// Transmit interrupt bit.
+const TX_INT: u32 = 0x20;
+/// Receive timeout interrupt bit.
+const RX_TIMEOUT_INT: u32 = 0x40;
+/// Framing, parity, break, and overrun error interrupt bits.
+const ERROR_INTS: u32 = 0x780;
+/// All interrupt bits.
+const ALL_INTS: u32 = 0x7FF;
+/// Maximum time to wait for room in the transmit FIFO, which only runs out if
+/// the receiver holds off transmission with flow control.
+const TX_TIMEOUT: Duration = Duration::from_millis(100);
+/// Maximum time to wait for the transmit FIFO to drain, long enough for a full
+/// FIFO at slow BAUD rates.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Transmit ring buffer capacity.
+const TX_RING_LEN: usize = 4096;
+/// Receive ring buffer capacity.
+const RX_RING_LEN: usize = 256;
+/// Backspace character.
+const BACKSPACE: u8 = 0x8;
+/// Delete character, sent by most terminals when the backspace key is pressed.
+const DELETE: u8 = 0x7F;
 
-/// Whether the driver has been initialized.
-static mut INIT: bool = false;
+/// Registers.
+static BASE: RegisterBlock = RegisterBlock::new(board::BASE);
+/// Interrupt ID at the GIC.
+static IRQ: AtomicU32 = AtomicU32::new(board::IRQ);
+/// Driver initialization.
+static INIT: Once<()> = Once::new();
+/// Driver state.
+static STATE: SpinLock<State> = SpinLock::new(State { clock_rate: board::CLOCK_RATE,
+                                                      config: UartConfig::DEFAULT,
+                                                      irq_mode: false,
+                                                      tx_policy: TxPolicy::Block,
+                                                      tx_ring: Ring::new(0) });
+/// Raw data register values moved out of the receive FIFO by the interrupt
+/// handler, which is the only producer, and consumed with the driver state
+/// locked.
+static RX_RING: Spsc<u16, RX_RING_LEN> = Spsc::new();
+/// Whether received data was lost because the receive ring buffer or FIFO was
+/// full.
+static RX_LOST: AtomicBool = AtomicBool::new(false);
+/// Whether the last byte applied to a line was a carriage return, in which case
+/// a line feed right after it completes the same line ending.
+static AFTER_CR: AtomicBool = AtomicBool::new(false);
+/// Number of bytes dropped because the transmit ring buffer was full or the
+/// transmission stalled.
+static TX_DROPPED: AtomicUsize = AtomicUsize::new(0);
+/// Task waiting for data to arrive.
+static RX_WAKER: WakerSlot = WakerSlot::new();
+/// Task waiting for room in the transmit ring buffer.
+static TX_WAKER: WakerSlot = WakerSlot::new();
 
 /// Send formatted diagnostic messages over the UART.
 #[macro_export]
 macro_rules! println {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        write!($crate::uart::Uart, $($arg)*).unwrap();
-        $crate::uart::Uart.write_str("\r\n").unwrap();
+        // Dropped bytes are accounted for in the transmit statistics.
+        let _ = write!($crate::uart::Uart, $($arg)*);
+        let _ = $crate::uart::Uart.write_str("\r\n");
     }};
 }
 
@@ -39,41 +161,767 @@ macro_rules! println {
 macro_rules! print {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        write!($crate::uart::Uart, $($arg)*).unwrap();
+        // Dropped bytes are accounted for in the transmit statistics.
+        let _ = write!($crate::uart::Uart, $($arg)*);
     }};
 }
 
 /// AMBA PL011 UART driver.
 pub struct Uart;
 
+/// Polled UART writer that bypasses the driver state, used to report panics
+/// regardless of which locks are held.
+pub struct DirectUart;
+
+/// Driver state.
+struct State
+{
+    /// UART clock rate.
+    clock_rate: u32,
+    /// Current configuration.
+    config: UartConfig,
+    /// Whether the driver is in interrupt driven mode.
+    irq_mode: bool,
+    /// What to do when the transmit ring buffer is full.
+    tx_policy: TxPolicy,
+    /// Bytes waiting to be moved to the transmit FIFO.
+    tx_ring: Ring<u8, TX_RING_LEN>,
+}
+
+/// Line configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UartConfig
+{
+    /// BAUD rate.
+    pub baud_rate: u32,
+    /// Number of data bits per character.
+    pub data_bits: DataBits,
+    /// Parity bit.
+    pub parity: Parity,
+    /// Number of stop bits.
+    pub stop_bits: StopBits,
+    /// Whether to use the RTS and CTS lines for hardware flow control.
+    pub flow_control: bool,
+}
+
+/// Number of data bits per character.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum DataBits
+{
+    /// Five bits.
+    Five = 0,
+    /// Six bits.
+    Six = 1,
+    /// Seven bits.
+    Seven = 2,
+    /// Eight bits.
+    Eight = 3,
+}
+
+/// Parity bit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Parity
+{
+    /// No parity bit.
+    None,
+    /// Odd parity.
+    Odd,
+    /// Even parity.
+    Even,
+    /// Parity bit always set.
+    Mark,
+    /// Parity bit always clear.
+    Space,
+}
+
+/// Number of stop bits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopBits
+{
+    /// One stop bit.
+    One,
+    /// Two stop bits.
+    Two,
+}
+
+/// FIFO fill level at which interrupts are raised.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+// Every level the hardware supports is listed, even those the kernel doesn't
+// use.
+#[allow(dead_code)]
+pub enum FifoLevel
+{
+    /// One eighth full.
+    OneEighth = 0,
+    /// One quarter full.
+    OneQuarter = 1,
+    /// Half full.
+    Half = 2,
+    /// Three quarters full.
+    ThreeQuarters = 3,
+    /// Seven eighths full.
+    SevenEighths = 4,
+}
+
+/// What to do with data written in interrupt driven mode when the transmit
+/// ring buffer is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxPolicy
+{
+    /// Wait for room, transmitting directly if necessary, and drop the data
+    /// only if the transmission stalls.
+    Block,
+    /// Drop the data that doesn't fit.
+    Drop,
+}
+
+/// Transmit error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxError
+{
+    /// The transmit ring buffer was full and the transmit policy is to drop
+    /// data.
+    Full,
+    /// The transmit FIFO didn't have room in time, usually because the receiver
+    /// holds off transmission with flow control.
+    Stalled(RegisterTimeout),
+}
+
+/// Error programming a line configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BaudRateError
+{
+    /// Requested BAUD rate.
+    pub baud_rate: u32,
+    /// Rate of the UART clock.
+    pub clock_rate: u32,
+}
+
+/// Receive error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RxError
+{
+    /// The receive FIFO was full when more data arrived, so data was lost.
+    Overrun,
+    /// The receive line was held low for longer than a whole frame.
+    Break,
+    /// The parity of a received character didn't match the configuration.
+    Parity,
+    /// A received character didn't have a valid stop bit.
+    Framing,
+}
+
 impl Uart
 {
     fn init_or_nop()
     {
+        // Talking to the firmware might log through the UART, in which case the
+        // firmware's configuration is used until this is done.
+        INIT.call_once(|| {
+                // The built in clock rate is kept if the firmware doesn't
+                // respond.
+                let mut clock_rate = [0u32; 2];
+                let _ = mbox! {GET_CLOCK_RATE_TAG: CLOCK_ID => clock_rate};
+                let mut state = STATE.lock_irq();
+                if clock_rate[1] != 0 {
+                    state.clock_rate = clock_rate[1];
+                }
+                // The firmware's configuration is kept if the clock can't
+                // produce the default BAUD rate, since this can't log.
+                let config = state.config;
+                let _ = state.apply(config);
+            });
+    }
+
+    /// Switches to the UART described by the device tree, which is the console
+    /// chosen by the firmware if it's a PL011 UART or else the first PL011
+    /// UART, taking its registers, interrupt and clock rate from it, and
+    /// keeping the built in values for anything that it doesn't describe.
+    ///
+    /// * `fdt`: Device tree.
+    ///
+    /// Must be called before switching to interrupt driven mode.
+    pub fn probe(&mut self, fdt: &Fdt)
+    {
+        let console = fdt.find_stdout()
+                         .filter(|node| node.is_compatible(board::COMPATIBLE) && node.is_enabled());
+        let Some(node) = console.or_else(|| fdt.find_compatible(board::COMPATIBLE)) else {
+            warn!("No PL011 UART in the device tree");
+            return;
+        };
+        Self::init_or_nop();
+        if let Some((addr, len)) = node.reg(0) {
+            // Whatever was already written goes out through the old UART.
+            let _ = Self::wait_drained();
+            BASE.set(addr, len);
+        }
+        if let Some(irq) = node.interrupt(0) {
+            IRQ.store(irq, Ordering::Relaxed);
+        }
+        let mut state = STATE.lock_irq();
+        if let Some(clock_rate) = node.clock_rate(0) {
+            state.clock_rate = clock_rate;
+        }
+        let (clock_rate, config) = (state.clock_rate, state.config);
+        let res = state.apply(config);
+        drop(state);
+        if let Err(err) = res {
+            warn!("Failed to configure the UART: {err}");
+        }
+        debug!("Found UART at 0x{:X} with IRQ {} and a {clock_rate}Hz clock",
+               BASE.phys(0),
+               Self::irq());
+    }
+
+    /// Returns the interrupt ID of the UART at the GIC.
+    pub fn irq() -> u32
+    {
+        IRQ.load(Ordering::Relaxed)
+    }
+
+    /// Changes the line configuration, waiting for any pending transmission
+    /// to finish first.
+    ///
+    /// * `config`: New configuration.
+    ///
+    /// Returns an error, keeping the current configuration, if the BAUD rate
+    /// cannot be derived from the UART clock.
+    pub fn configure(&mut self, config: UartConfig) -> Result<(), BaudRateError>
+    {
+        Self::init_or_nop();
+        STATE.lock_irq().apply(config)
+    }
+
+    /// Returns the current line configuration.
+    pub fn config(&self) -> UartConfig
+    {
+        STATE.lock_irq().config
+    }
+
+    /// Waits for a byte to arrive.
+    ///
+    /// Returns the received byte, or the error detected while receiving it.
+    pub fn read_byte(&mut self) -> Result<u8, RxError>
+    {
+        loop {
+            if let Some(res) = self.try_read_byte() {
+                return res;
+            }
+            spin_loop();
+        }
+    }
+
+    /// Reads a byte if one has already arrived.
+    ///
+    /// Returns the received byte or the error detected while receiving it, or
+    /// `None` if no data is available.
+    pub fn try_read_byte(&mut self) -> Option<Result<u8, RxError>>
+    {
+        Self::init_or_nop();
+        // Locking the state makes this the only consumer of the receive ring
+        // buffer.
+        let state = STATE.lock_irq();
+        if RX_LOST.swap(false, Ordering::Relaxed) {
+            return Some(Err(RxError::Overrun));
+        }
+        let data = match unsafe { RX_RING.pop() } {
+            Some(data) => data as u32,
+            None if state.irq_mode => return None,
+            None => Self::read_fifo()?,
+        };
+        Some(Self::decode(data))
+    }
+
+    /// Waits for a byte to arrive without blocking other tasks.
+    ///
+    /// Returns the received byte, or the error detected while receiving it.
+    pub async fn read_byte_async(&mut self) -> Result<u8, RxError>
+    {
+        poll_fn(|cx| {
+            // Registering first ensures that data arriving right after checking
+            // wakes the task.
+            RX_WAKER.register(cx.waker());
+            if let Some(res) = self.try_read_byte() {
+                return Poll::Ready(res);
+            }
+            // Nothing wakes the task in polled mode, so it has to keep checking.
+            if !STATE.lock_irq().irq_mode {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        }).await
+    }
+
+    /// Reads a line of printable ASCII text without blocking other tasks,
+    /// echoing it back and handling backspace.
+    ///
+    /// * `buf`: Buffer to store the line, without the terminating CrLf.
+    ///
+    /// Returns the line, or the first error detected while receiving it.
+    /// Characters that don't fit in the buffer are discarded.
+    pub async fn read_line_async<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a str, RxError>
+    {
+        let mut len = 0;
+        loop {
+            let byte = self.read_byte_async().await?;
+            if self.edit_line(buf, &mut len, byte) {
+                break;
+            }
+        }
+        // Only printable ASCII characters are stored in the buffer.
+        Ok(unsafe { from_utf8_unchecked(&buf[.. len]) })
+    }
+
+    /// Sends raw bytes, waiting for room in the transmit FIFO in polled mode or
+    /// queuing them according to the transmit policy in interrupt driven mode.
+    ///
+    /// * `bytes`: Bytes to send.
+    pub fn write_bytes(&mut self, bytes: &[u8])
+    {
+        // Dropped bytes are accounted for in the transmit statistics.
+        let _ = self.write_queued(bytes);
+    }
+
+    /// Sends raw bytes without waiting for their transmission in interrupt
+    /// driven mode, in which case they are queued in the transmit ring buffer
+    /// and the transmit policy decides what happens when it is full.
+    ///
+    /// * `bytes`: Bytes to send.
+    ///
+    /// Returns an error if some of the bytes had to be dropped, in which case
+    /// the ones before them were sent or queued and the rest were counted as
+    /// dropped.
+    pub fn write_queued(&mut self, bytes: &[u8]) -> Result<(), TxError>
+    {
+        Self::init_or_nop();
+        let mut state = STATE.lock_irq();
+        if !state.irq_mode {
+            for (count, byte) in bytes.iter().enumerate() {
+                if let Err(err) = Self::wait_tx_room() {
+                    TX_DROPPED.fetch_add(bytes.len() - count, Ordering::Relaxed);
+                    return Err(TxError::Stalled(err));
+                }
+                unsafe { BASE.reg(DATA).write_volatile(*byte as _) };
+            }
+            return Ok(());
+        }
+        for (count, byte) in bytes.iter().enumerate() {
+            while !state.tx_ring.push(*byte) {
+                let res = match state.tx_policy {
+                    TxPolicy::Drop => Err(TxError::Full),
+                    // Make room by transmitting directly, since interrupts are masked
+                    // while waiting.
+                    TxPolicy::Block => {
+                        state.fill_fifo();
+                        Self::wait_tx_room().map_err(TxError::Stalled)
+                    }
+                };
+                if let Err(err) = res {
+                    TX_DROPPED.fetch_add(bytes.len() - count, Ordering::Relaxed);
+                    state.fill_fifo();
+                    return Err(err);
+                }
+                state.fill_fifo();
+            }
+        }
+        // The transmit interrupt is only raised when the FIFO level drops below the
+        // threshold, so the FIFO must be primed for the transmission to start.
+        state.fill_fifo();
+        Ok(())
+    }
+
+    /// Sends raw bytes without blocking other tasks, waiting for room in the
+    /// transmit ring buffer in interrupt driven mode regardless of the transmit
+    /// policy.  In polled mode the bytes are sent as by [`Self::write_bytes`].
+    ///
+    /// * `bytes`: Bytes to send.
+    ///
+    /// Dropping the future before it completes sends only the bytes that were
+    /// already queued, since the bytes are copied into the ring buffer.
+    pub async fn write_async(&mut self, bytes: &[u8])
+    {
+        Self::init_or_nop();
+        let mut sent = 0;
+        poll_fn(|cx| {
+            TX_WAKER.register(cx.waker());
+            let mut state = STATE.lock_irq();
+            if !state.irq_mode {
+                drop(state);
+                self.write_bytes(&bytes[sent ..]);
+                return Poll::Ready(());
+            }
+            while sent < bytes.len() && state.tx_ring.push(bytes[sent]) {
+                sent += 1;
+            }
+            state.fill_fifo();
+            if sent < bytes.len() {
+                return Poll::Pending;
+            }
+            Poll::Ready(())
+        }).await
+    }
+
+    /// Switches to interrupt driven mode.
+    ///
+    /// * `tx_level`: Transmit FIFO level at or below which to request more
+    ///   data.
+    /// * `rx_level`: Receive FIFO level at or above which to request draining.
+    ///
+    /// [`Self::handle_interrupt`] must be called whenever the UART raises an
+    /// interrupt from now on.
+    pub fn enable_interrupts(&mut self, tx_level: FifoLevel, rx_level: FifoLevel)
+    {
+        Self::init_or_nop();
+        let mut state = STATE.lock_irq();
         unsafe {
-            if INIT {
-                return;
+            BASE.reg(FIFO_LEVEL)
+                .write_volatile((rx_level as u32) << 3 | tx_level as u32);
+            BASE.reg(INT_CLEAR).write_volatile(ALL_INTS);
+            BASE.reg(INT_MASK)
+                .write_volatile(RX_INT | TX_INT | RX_TIMEOUT_INT | ERROR_INTS);
+        }
+        state.irq_mode = true;
+    }
+
+    /// Switches back to polled mode, transmitting anything still queued.
+    /// Data already received remains available for reading.
+    pub fn disable_interrupts(&mut self)
+    {
+        let mut state = STATE.lock_irq();
+        if state.irq_mode {
+            state.leave_irq_mode();
+        }
+    }
+
+    /// Waits for all the data written so far to be transmitted, switching back
+    /// to polled mode.
+    ///
+    /// Returns an error identifying the register that wedged if the
+    /// transmission stalls, in which case the data not yet transmitted is
+    /// dropped.
+    pub fn flush(&mut self) -> Result<(), RegisterTimeout>
+    {
+        self.disable_interrupts();
+        Self::wait_drained()
+    }
+
+    /// Changes what happens to data written in interrupt driven mode when the
+    /// transmit ring buffer is full.
+    ///
+    /// * `policy`: New policy.
+    pub fn set_tx_policy(&mut self, policy: TxPolicy)
+    {
+        STATE.lock_irq().tx_policy = policy;
+    }
+
+    /// Returns the number of bytes dropped so far because the transmit ring
+    /// buffer was full or the transmission stalled.
+    pub fn dropped(&self) -> usize
+    {
+        TX_DROPPED.load(Ordering::Relaxed)
+    }
+
+    /// Moves data between the FIFOs and the ring buffers in response to an
+    /// interrupt.
+    pub fn handle_interrupt()
+    {
+        let status = unsafe { BASE.reg(INT_STATUS).read_volatile() };
+        if status & (RX_INT | RX_TIMEOUT_INT | ERROR_INTS) != 0 {
+            while let Some(data) = Self::read_fifo() {
+                // The interrupt is only routed to one core, so this is the only
+                // producer.
+                if !unsafe { RX_RING.push(data as u16) } {
+                    RX_LOST.store(true, Ordering::Relaxed);
+                }
             }
-            let quot = CLOCK_RATE * 8 / BAUD_RATE;
-            INT_DIV.write_volatile(quot >> 6);
-            FRAC_DIV.write_volatile(quot & 0x3F);
-            CTL.write_volatile(0x101);
-            INIT = true;
         }
+        if !RX_RING.is_empty() {
+            RX_WAKER.wake();
+        }
+        // IRQs are already masked in interrupt handlers.
+        let mut state = STATE.lock();
+        state.fill_fifo();
+        // The receive interrupts are cleared by draining the FIFO and the transmit
+        // interrupt by filling it, except when there is nothing left to send.
+        let mut clear = status & ERROR_INTS;
+        if state.tx_ring.is_empty() {
+            clear |= TX_INT;
+        }
+        unsafe { BASE.reg(INT_CLEAR).write_volatile(clear) };
+        let full = state.tx_ring.is_full();
+        drop(state);
+        if !full {
+            TX_WAKER.wake();
+        }
+    }
+
+    /// Applies a received byte to a line being read, echoing it back, and
+    /// ignores a line feed that follows a carriage return so that CrLf ends a
+    /// single line.
+    ///
+    /// * `buf`: Buffer storing the line.
+    /// * `len`: Length of the line read so far.
+    /// * `byte`: Received byte.
+    ///
+    /// Returns whether the byte ends the line.
+    fn edit_line(&mut self, buf: &mut [u8], len: &mut usize, byte: u8) -> bool
+    {
+        let after_cr = AFTER_CR.swap(byte == b'\r', Ordering::Relaxed);
+        match byte {
+            b'\n' if after_cr => (),
+            b'\r' | b'\n' => {
+                self.write_bytes(b"\r\n");
+                return true;
+            }
+            BACKSPACE | DELETE if *len > 0 => {
+                *len -= 1;
+                self.write_bytes(b"\x08 \x08");
+            }
+            byte @ 0x20 ..= 0x7E if *len < buf.len() => {
+                buf[*len] = byte;
+                *len += 1;
+                self.write_bytes(&[byte]);
+            }
+            _ => (),
+        }
+        false
+    }
+
+    /// Reads the data register if the receive FIFO is not empty.
+    ///
+    /// Returns the data byte along with its error bits.
+    fn read_fifo() -> Option<u32>
+    {
+        unsafe {
+            if BASE.reg(FLAGS).read_volatile() & RXFE_FLAG != 0 {
+                return None;
+            }
+            Some(BASE.reg(DATA).read_volatile() & 0xFFF)
+        }
+    }
+
+    /// Waits for room in the transmit FIFO.
+    ///
+    /// Returns an error identifying the flags register if there is still no
+    /// room after the timeout.
+    fn wait_tx_room() -> Result<(), RegisterTimeout>
+    {
+        unsafe { poll_register("FLAGS", BASE.reg(FLAGS), TX_TIMEOUT, |flags| flags & TXFF_FLAG == 0)? };
+        Ok(())
+    }
+
+    /// Waits for the transmit FIFO to drain and the last byte to leave the
+    /// shift register.
+    ///
+    /// Returns an error identifying the flags register if the UART is still
+    /// busy after the timeout.
+    fn wait_drained() -> Result<(), RegisterTimeout>
+    {
+        unsafe { poll_register("FLAGS", BASE.reg(FLAGS), DRAIN_TIMEOUT, |flags| flags & BUSY_FLAG == 0)? };
+        Ok(())
+    }
+
+    /// Decodes a data register value.
+    ///
+    /// * `data`: Data byte along with its error bits.
+    ///
+    /// Returns the received byte, or the error detected while receiving it.
+    fn decode(data: u32) -> Result<u8, RxError>
+    {
+        let status = data >> 8 & 0xF;
+        if status == 0 {
+            return Ok(data as u8);
+        }
+        // Writing any value clears all the error bits.
+        unsafe { BASE.reg(RX_STATUS).write_volatile(0) };
+        // An overrun means that the data that arrived after this byte was lost
+        // while the byte itself is intact, so it's reported by the next read.
+        if status & OVERRUN_ERROR != 0 {
+            RX_LOST.store(true, Ordering::Relaxed);
+        }
+        // Report the most serious error first, since a break also sets the framing
+        // error bit.
+        let err = if status & BREAK_ERROR != 0 {
+            RxError::Break
+        } else if status & PARITY_ERROR != 0 {
+            RxError::Parity
+        } else if status & FRAMING_ERROR != 0 {
+            RxError::Framing
+        } else {
+            return Ok(data as u8);
+        };
+        Err(err)
     }
 }
 
 impl Write for Uart
 {
-    fn write_str(&mut self, msg: &str) -> Result
+    fn write_str(&mut self, msg: &str) -> fmt::Result
     {
-        Self::init_or_nop();
-        for byte in msg.as_bytes() {
-            while unsafe { FLAGS.read_volatile() } & 0x20 != 0 {
-                spin_loop();
+        self.write_queued(msg.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl DirectUart
+{
+    /// Takes over the UART to report a panic, switching it to polled mode and
+    /// transmitting whatever is still queued unless the driver state is
+    /// locked.
+    ///
+    /// Returns the writer.
+    pub fn take_over() -> Self
+    {
+        unsafe { BASE.reg(INT_MASK).write_volatile(0) };
+        if let Some(mut state) = STATE.try_lock() {
+            if state.irq_mode {
+                state.leave_irq_mode();
             }
-            unsafe { DATA.write_volatile(*byte as _) };
         }
+        Self
+    }
+}
+
+impl Write for DirectUart
+{
+    fn write_str(&mut self, msg: &str) -> fmt::Result
+    {
+        for byte in msg.bytes() {
+            Uart::wait_tx_room().map_err(|_| fmt::Error)?;
+            unsafe { BASE.reg(DATA).write_volatile(byte as _) };
+        }
+        Ok(())
+    }
+}
+
+impl State
+{
+    /// Programs the line configuration into the hardware.
+    ///
+    /// * `config`: Configuration to program.
+    ///
+    /// Returns an error without touching the hardware if the BAUD rate cannot
+    /// be derived from the UART clock.
+    fn apply(&mut self, config: UartConfig) -> Result<(), BaudRateError>
+    {
+        // The divisor is expressed in units of 16 clock cycles with a 6 bit
+        // fractional part.
+        let clock_rate = self.clock_rate;
+        let baud_rate = config.baud_rate.max(1);
+        let div = (clock_rate as u64 * 4 + baud_rate as u64 / 2) / baud_rate as u64;
+        if !(0x40 ..= 0x3FFFFF).contains(&div) {
+            return Err(BaudRateError { baud_rate: config.baud_rate,
+                                       clock_rate });
+        }
+        let mut line_ctl = FIFO_ENABLE | (config.data_bits as u32) << 5;
+        line_ctl |= match config.parity {
+            Parity::None => 0,
+            Parity::Odd => PARITY_ENABLE,
+            Parity::Even => PARITY_ENABLE | EVEN_PARITY,
+            Parity::Mark => PARITY_ENABLE | STICK_PARITY,
+            Parity::Space => PARITY_ENABLE | EVEN_PARITY | STICK_PARITY,
+        };
+        if config.stop_bits == StopBits::Two {
+            line_ctl |= TWO_STOP_BITS;
+        }
+        let mut ctl = UART_ENABLE | TX_ENABLE | RX_ENABLE;
+        if config.flow_control {
+            ctl |= RTS_ENABLE | CTS_ENABLE;
+        }
+        // The UART must be disabled while being configured, and writing the line
+        // control register is what latches the divisor.  Data that can't be
+        // transmitted before that is lost anyway.
+        let _ = Uart::wait_drained();
+        unsafe {
+            BASE.reg(CTL).write_volatile(0);
+            BASE.reg(INT_DIV).write_volatile((div >> 6) as u32);
+            BASE.reg(FRAC_DIV).write_volatile(div as u32 & 0x3F);
+            BASE.reg(LINE_CTL).write_volatile(line_ctl);
+            BASE.reg(CTL).write_volatile(ctl);
+        }
+        self.config = config;
         Ok(())
     }
+
+    /// Switches back to polled mode, transmitting anything still queued.
+    fn leave_irq_mode(&mut self)
+    {
+        unsafe { BASE.reg(INT_MASK).write_volatile(0) };
+        self.irq_mode = false;
+        while let Some(byte) = self.tx_ring.pop() {
+            if Uart::wait_tx_room().is_err() {
+                TX_DROPPED.fetch_add(self.tx_ring.len() + 1, Ordering::Relaxed);
+                self.tx_ring = Ring::new(0);
+                break;
+            }
+            unsafe { BASE.reg(DATA).write_volatile(byte as _) };
+        }
+    }
+
+    /// Moves data from the transmit ring buffer to the FIFO until either the
+    /// ring buffer is empty or the FIFO is full.
+    fn fill_fifo(&mut self)
+    {
+        while unsafe { BASE.reg(FLAGS).read_volatile() } & TXFF_FLAG == 0 {
+            let Some(byte) = self.tx_ring.pop() else {
+                break;
+            };
+            unsafe { BASE.reg(DATA).write_volatile(byte as _) };
+        }
+    }
+}
+
+impl UartConfig
+{
+    /// Default configuration: 115200 BAUD, 8 data bits, no parity, 1 stop bit,
+    /// and no flow control.
+    pub const DEFAULT: Self = Self { baud_rate: 115200,
+                                     data_bits: DataBits::Eight,
+                                     parity: Parity::None,
+                                     stop_bits: StopBits::One,
+                                     flow_control: false };
+}
+
+impl Default for UartConfig
+{
+    fn default() -> Self
+    {
+        Self::DEFAULT
+    }
+}
+
+impl Display for TxError
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::Full => f.write_str("Transmit ring buffer full"),
+            Self::Stalled(err) => write!(f, "Transmission stalled: {err}"),
+        }
+    }
+}
+
+impl Display for BaudRateError
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        write!(f,
+               "BAUD rate {} is not attainable with a {}Hz UART clock",
+               self.baud_rate, self.clock_rate)
+    }
+}
+
+impl Display for RxError
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        let msg = match self {
+            Self::Overrun => "Receive FIFO overrun",
+            Self::Break => "Break condition",
+            Self::Parity => "Parity error",
+            Self::Framing => "Framing error",
+        };
+        f.write_str(msg)
+    }
 }
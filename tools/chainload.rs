@@ -0,0 +1,43 @@
+//! Sends a kernel image to the board over a serial device and boots it.
+
+use std::env::args;
+use std::process::exit;
+use std::{fs, io};
+
+// The protocol is shared with the kernel, which uses the other half of it.
+#[allow(dead_code)]
+#[path = "../src/proto.rs"]
+mod proto;
+mod serial;
+
+use proto::{crc32_update, Image, MAX_PAYLOAD};
+use serial::Serial;
+
+fn main()
+{
+    let args = args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <serial device> <kernel8.img>", args[0]);
+        exit(2);
+    }
+    if let Err(err) = chainload(&args[1], &args[2]) {
+        eprintln!("{err}");
+        exit(1);
+    }
+}
+
+/// Sends a kernel image to the board, which boots it once it arrives intact.
+///
+/// * `dev`: Path to the serial device.
+/// * `path`: Path to the kernel image.
+fn chainload(dev: &str, path: &str) -> io::Result<()>
+{
+    let image = fs::read(path)?;
+    let size =
+        u32::try_from(image.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Image is too large"))?;
+    let header = Image { size,
+                         crc: !crc32_update(!0, &image) };
+    let mut serial = Serial::open(dev)?;
+    serial.command("chainload")?;
+    serial.transfer(&header.to_bytes(), &image, MAX_PAYLOAD)
+}
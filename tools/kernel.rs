@@ -0,0 +1,65 @@
+//! Host tests for the kernel modules that don't depend on the hardware.
+//!
+//! The modules are compiled from the kernel sources along with host versions
+//! of the kernel facilities that they use, and contain their own tests.  Board
+//! specific modules are built for the Raspberry Pi 5.
+
+// Only the parts of the modules covered by tests are used here.
+#[allow(dead_code)]
+#[path = "../src/board.rs"]
+mod board;
+#[allow(dead_code)]
+#[path = "../src/mmu.rs"]
+mod mmu;
+#[allow(dead_code)]
+#[path = "../src/proto.rs"]
+mod proto;
+
+/// Host versions of the kernel's synchronization primitives.
+mod sync
+{
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Lock backed by a host mutex.
+    pub struct SpinLock<T>(Mutex<T>);
+
+    impl<T> SpinLock<T>
+    {
+        /// Creates and initializes a new unlocked lock.
+        ///
+        /// * `data`: Data to protect.
+        ///
+        /// Returns the newly created lock.
+        pub const fn new(data: T) -> Self
+        {
+            Self(Mutex::new(data))
+        }
+
+        /// Waits for the lock to become available and takes it, ignoring
+        /// panics in previous holders, which tests can expect.
+        ///
+        /// Returns a guard granting exclusive access to the protected data.
+        pub fn lock_irq(&self) -> MutexGuard<'_, T>
+        {
+            self.0.lock().unwrap_or_else(|err| err.into_inner())
+        }
+    }
+}
+
+/// Host version of the kernel's secondary core module.
+mod smp
+{
+    /// Number of cores.
+    pub const CORES: usize = 4;
+    /// Size of each guard page.
+    pub const GUARD_SIZE: usize = 0x1000;
+}
+
+/// Discards a log record.
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+use debug;
@@ -0,0 +1,120 @@
+//! Serial device transport shared by the host tools.
+//!
+//! The serial device must be configured beforehand for raw operation with a
+//! read timeout, for example with `stty -F <device> 115200 raw -echo min 0
+//! time 10` on Linux.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::proto::{self, Frame, Kind, Link, RecvError, MAX_PAYLOAD, NAK_CHECKSUM, NAK_FORMAT, NAK_TOO_LARGE};
+
+/// Number of times a frame is sent before giving up.
+const ATTEMPTS: usize = 10;
+
+/// Serial device transport.
+pub struct Serial
+{
+    /// Open serial device.
+    dev: File,
+}
+
+impl Serial
+{
+    /// Opens a serial device.
+    ///
+    /// * `path`: Path to the device.
+    ///
+    /// Returns the newly created transport.
+    pub fn open(path: &str) -> io::Result<Self>
+    {
+        let dev = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self { dev })
+    }
+
+    /// Runs a command in the board's shell.
+    ///
+    /// * `cmd`: Command line.
+    pub fn command(&mut self, cmd: &str) -> io::Result<()>
+    {
+        // The leading carriage return submits anything left at the prompt first.
+        self.dev.write_all(format!("\r{cmd}\r").as_bytes())
+    }
+
+    /// Performs a complete transfer, sending a start frame, the data split
+    /// into as many frames as necessary, and an end frame.
+    ///
+    /// * `header`: Payload of the start frame.
+    /// * `data`: Data to send.
+    /// * `chunk_len`: Maximum length of the data in each frame, which must not
+    ///   exceed [`MAX_PAYLOAD`].
+    pub fn transfer(&mut self, header: &[u8], data: &[u8], chunk_len: usize) -> io::Result<()>
+    {
+        let mut seq = 0u8;
+        self.exchange(Kind::Start, seq, header)?;
+        let chunks = data.chunks(chunk_len);
+        let count = chunks.len();
+        for (idx, chunk) in chunks.enumerate() {
+            seq = seq.wrapping_add(1);
+            self.exchange(Kind::Data, seq, chunk)?;
+            eprint!("\rSent {} of {count} frames", idx + 1);
+        }
+        eprintln!();
+        self.exchange(Kind::End, seq.wrapping_add(1), &[])
+    }
+
+    /// Sends a frame and waits for it to be acknowledged, resending it if it
+    /// gets corrupted or the acknowledgement doesn't arrive.
+    ///
+    /// * `kind`: Frame kind.
+    /// * `seq`: Sequence number.
+    /// * `payload`: Payload.
+    fn exchange(&mut self, kind: Kind, seq: u8, payload: &[u8]) -> io::Result<()>
+    {
+        let mut buf = [0; MAX_PAYLOAD];
+        for _ in 0 .. ATTEMPTS {
+            proto::send(self, &Frame { kind, seq, payload })?;
+            loop {
+                let reply = match proto::receive(self, &mut buf) {
+                    Ok(reply) => reply,
+                    Err(RecvError::Link(err)) if err.kind() == ErrorKind::TimedOut => break,
+                    Err(RecvError::Link(err)) => return Err(err),
+                    Err(RecvError::Corrupt) => break,
+                };
+                if reply.seq != seq {
+                    // Stale reply to a frame that was resent.
+                    continue;
+                }
+                let reason = match (reply.kind, reply.payload) {
+                    (Kind::Ack, _) => return Ok(()),
+                    (Kind::Nak, [NAK_FORMAT]) => "The board does not support the stream format",
+                    (Kind::Nak, [NAK_TOO_LARGE]) => "The image is too large for the board",
+                    (Kind::Nak, [NAK_CHECKSUM]) => "The board received a corrupted image",
+                    _ => break,
+                };
+                return Err(io::Error::new(ErrorKind::InvalidInput, reason));
+            }
+        }
+        Err(io::Error::new(ErrorKind::TimedOut,
+                           format!("Frame #{seq} was not acknowledged after {ATTEMPTS} attempts")))
+    }
+}
+
+impl Link for Serial
+{
+    type Error = io::Error;
+
+    fn read_byte(&mut self) -> io::Result<u8>
+    {
+        let mut byte = [0];
+        match self.dev.read(&mut byte)? {
+            0 => Err(io::Error::new(ErrorKind::TimedOut, "Timed out waiting for the board")),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>
+    {
+        self.dev.write_all(bytes)
+    }
+}
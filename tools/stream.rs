@@ -0,0 +1,104 @@
+//! Streams a WAV file to the board over a serial device.
+
+use std::env::args;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::process::exit;
+
+// The protocol is shared with the kernel, which uses the other half of it.
+#[allow(dead_code)]
+#[path = "../src/proto.rs"]
+mod proto;
+mod serial;
+
+use proto::{Format, MAX_PAYLOAD, PCM_S16LE};
+use serial::Serial;
+
+/// Decoded WAV file.
+struct Wav
+{
+    /// Stream format.
+    format: Format,
+    /// Interleaved signed 16 bit little endian samples.
+    data: Vec<u8>,
+}
+
+fn main()
+{
+    let args = args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <serial device> <file.wav>", args[0]);
+        exit(2);
+    }
+    if let Err(err) = stream(&args[1], &args[2]) {
+        eprintln!("{err}");
+        exit(1);
+    }
+}
+
+/// Streams a WAV file to the board.
+///
+/// * `dev`: Path to the serial device.
+/// * `path`: Path to the WAV file.
+fn stream(dev: &str, path: &str) -> io::Result<()>
+{
+    let wav = parse_wav(&fs::read(path)?)?;
+    let mut serial = Serial::open(dev)?;
+    serial.command("stream")?;
+    // Frames must contain whole sample frames.
+    let chunk_len = MAX_PAYLOAD - MAX_PAYLOAD % (2 * wav.format.channels as usize);
+    serial.transfer(&wav.format.to_bytes(), &wav.data, chunk_len)
+}
+
+/// Extracts the format and samples from a WAV file.
+///
+/// * `bytes`: Contents of the file.
+///
+/// Returns the decoded file, or an error if it's not a 48000Hz 16 bit PCM mono
+/// or stereo WAV file.
+fn parse_wav(bytes: &[u8]) -> io::Result<Wav>
+{
+    let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+    if bytes.len() < 12 || &bytes[0 .. 4] != b"RIFF" || &bytes[8 .. 12] != b"WAVE" {
+        return Err(invalid("Not a WAV file"));
+    }
+    let mut format = None;
+    let mut rest = &bytes[12 ..];
+    while rest.len() >= 8 {
+        let id = &rest[0 .. 4];
+        let len = u32::from_le_bytes(rest[4 .. 8].try_into().unwrap()) as usize;
+        let body = rest.get(8 .. 8 + len).ok_or_else(|| invalid("Truncated WAV chunk"))?;
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err(invalid("Truncated WAV format chunk"));
+                }
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let rate = u32::from_le_bytes(body[4 .. 8].try_into().unwrap());
+                let depth = u16::from_le_bytes([body[14], body[15]]);
+                if tag != 1 || depth != 16 {
+                    return Err(invalid("Only 16 bit PCM WAV files are supported"));
+                }
+                if channels != 1 && channels != 2 {
+                    return Err(invalid("Only mono and stereo WAV files are supported"));
+                }
+                if rate != 48000 {
+                    return Err(invalid("Only 48000Hz WAV files are supported"));
+                }
+                format = Some(Format { encoding: PCM_S16LE,
+                                       channels: channels as u8,
+                                       rate });
+            }
+            b"data" => {
+                let format = format.ok_or_else(|| invalid("WAV data precedes the format"))?;
+                return Ok(Wav { format,
+                                data: body.to_vec() });
+            }
+            _ => (),
+        }
+        // Chunks are padded to an even length.
+        rest = rest.get(8 + len + (len & 0x1) ..).unwrap_or(&[]);
+    }
+    Err(invalid("WAV file has no data"))
+}
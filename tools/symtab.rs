@@ -0,0 +1,271 @@
+//! Generates the symbol table embedded in the kernel from a linked ELF file,
+//! and converts the ELF file to a flat kernel image.
+//!
+//! The table format is documented in `src/symbols.rs`.
+
+use std::env::args;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::process::exit;
+
+use rustc_demangle::demangle;
+
+/// Program header type of loadable segments.
+const PT_LOAD: u32 = 1;
+/// Section header type of symbol tables.
+const SHT_SYMTAB: u32 = 2;
+/// Section flag of sections containing code.
+const SHF_EXECINSTR: u64 = 0x4;
+/// Symbol type of functions.
+const STT_FUNC: u8 = 2;
+/// First reserved section index.
+const SHN_LORESERVE: u16 = 0xFF00;
+
+/// Parsed ELF file.
+struct Elf<'a>
+{
+    /// File contents.
+    data: &'a [u8],
+}
+
+/// Section header.
+struct Section
+{
+    /// Type.
+    kind: u32,
+    /// Flags.
+    flags: u64,
+    /// Address in memory.
+    addr: u64,
+    /// Offset in the file.
+    offset: u64,
+    /// Size in bytes.
+    size: u64,
+    /// Index of the associated section, which is the string table for symbol
+    /// tables.
+    link: u32,
+}
+
+/// Function symbol.
+struct Symbol
+{
+    /// Address.
+    addr: u32,
+    /// Demangled name.
+    name: String,
+}
+
+fn main()
+{
+    let args = args().collect::<Vec<_>>();
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("Usage: {} <kernel.elf> <symbols.bin> [<kernel8.img>]", args[0]);
+        eprintln!("Writes the symbol table of the kernel, or checks that it matches the embedded table and writes the \
+                   kernel image if an image path is specified.");
+        exit(2);
+    }
+    if let Err(err) = run(&args[1], &args[2], args.get(3).map(String::as_str)) {
+        eprintln!("{err}");
+        exit(1);
+    }
+}
+
+/// Writes the symbol table or the checked kernel image.
+///
+/// * `elf_path`: Path to the linked kernel.
+/// * `table_path`: Path to the symbol table.
+/// * `image_path`: Path to the kernel image to write, if any.
+fn run(elf_path: &str, table_path: &str, image_path: Option<&str>) -> io::Result<()>
+{
+    let data = fs::read(elf_path)?;
+    let elf = Elf::parse(&data)?;
+    let table = elf.symbol_table()?;
+    let Some(image_path) = image_path else {
+        return fs::write(table_path, table);
+    };
+    if fs::read(table_path)? != table {
+        return Err(io::Error::new(ErrorKind::InvalidData,
+                                  "The embedded symbol table doesn't match the kernel, since the code moved between \
+                                   links"));
+    }
+    fs::write(image_path, elf.image()?)
+}
+
+impl<'a> Elf<'a>
+{
+    /// Validates the header of a little endian 64 bit ELF file.
+    ///
+    /// * `data`: File contents.
+    ///
+    /// Returns the parsed file.
+    fn parse(data: &'a [u8]) -> io::Result<Self>
+    {
+        if data.len() < 0x40 || data[.. 4] != *b"\x7FELF" || data[4] != 2 || data[5] != 1 {
+            return Err(invalid("Not a little endian 64 bit ELF file"));
+        }
+        Ok(Self { data })
+    }
+
+    /// Generates the symbol table embedded in the kernel.
+    ///
+    /// Returns the encoded table.
+    fn symbol_table(&self) -> io::Result<Vec<u8>>
+    {
+        let sections = self.sections()?;
+        let end = sections.iter()
+                          .filter(|section| section.flags & SHF_EXECINSTR != 0)
+                          .map(|section| section.addr + section.size)
+                          .max()
+                          .ok_or_else(|| invalid("No code sections"))?;
+        let mut symbols = Vec::new();
+        for symtab in sections.iter().filter(|section| section.kind == SHT_SYMTAB) {
+            let strtab = sections.get(symtab.link as usize)
+                                 .ok_or_else(|| invalid("Symbol table without a string table"))?;
+            let strings = self.slice(strtab.offset, strtab.size)?;
+            let entries = self.slice(symtab.offset, symtab.size)?;
+            for entry in entries.chunks_exact(24) {
+                let kind = entry[4] & 0xF;
+                let shndx = u16::from_le_bytes([entry[6], entry[7]]);
+                if kind != STT_FUNC || shndx == 0 || shndx >= SHN_LORESERVE {
+                    continue;
+                }
+                let section = sections.get(shndx as usize)
+                                      .ok_or_else(|| invalid("Symbol in a missing section"))?;
+                let name = string(strings, u32::from_le_bytes(entry[.. 4].try_into().unwrap()) as usize)?;
+                if section.flags & SHF_EXECINSTR == 0 || name.is_empty() {
+                    continue;
+                }
+                let addr = u64::from_le_bytes(entry[8 .. 16].try_into().unwrap());
+                let addr = u32::try_from(addr).map_err(|_| invalid("Symbol address doesn't fit in 32 bits"))?;
+                // The suffixes added to local symbols by LLVM depend on the contents of
+                // the module, which includes the table itself.
+                let name = name.split(".llvm.").next().unwrap();
+                symbols.push(Symbol { addr,
+                                      name: format!("{:#}", demangle(name)) });
+            }
+        }
+        // Identical functions might have been folded into one.
+        symbols.sort_by_key(|symbol| symbol.addr);
+        symbols.dedup_by_key(|symbol| symbol.addr);
+        let end = u32::try_from(end).map_err(|_| invalid("Code doesn't fit below 4GB"))?;
+        let mut table = Vec::new();
+        let mut names = Vec::new();
+        table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+        for symbol in &symbols {
+            table.extend_from_slice(&symbol.addr.to_le_bytes());
+            table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+            names.extend_from_slice(symbol.name.as_bytes());
+        }
+        table.extend_from_slice(&end.to_le_bytes());
+        table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        table.extend_from_slice(&names);
+        Ok(table)
+    }
+
+    /// Lays out the loadable segments in a flat image starting at the lowest
+    /// load address, like the linker does when outputting a binary.
+    ///
+    /// Returns the image.
+    fn image(&self) -> io::Result<Vec<u8>>
+    {
+        let (phoff, phentsize, phnum) = (self.u64(0x20)?, self.u16(0x36)? as u64, self.u16(0x38)? as u64);
+        let mut segments = Vec::new();
+        for idx in 0 .. phnum {
+            let header = phoff + idx * phentsize;
+            let (offset, paddr, filesz) = (self.u64(header + 8)?, self.u64(header + 24)?, self.u64(header + 32)?);
+            if self.u32(header)? == PT_LOAD && filesz != 0 {
+                segments.push((paddr, self.slice(offset, filesz)?));
+            }
+        }
+        let base = segments.iter()
+                           .map(|(paddr, _)| *paddr)
+                           .min()
+                           .ok_or_else(|| invalid("No loadable segments"))?;
+        let end = segments.iter()
+                          .map(|(paddr, data)| paddr + data.len() as u64)
+                          .max()
+                          .unwrap();
+        let mut image = vec![0; (end - base) as usize];
+        for (paddr, data) in segments {
+            let start = (paddr - base) as usize;
+            image[start .. start + data.len()].copy_from_slice(data);
+        }
+        Ok(image)
+    }
+
+    /// Parses the section headers.
+    ///
+    /// Returns the section headers in index order.
+    fn sections(&self) -> io::Result<Vec<Section>>
+    {
+        let (shoff, shentsize, shnum) = (self.u64(0x28)?, self.u16(0x3A)? as u64, self.u16(0x3C)? as u64);
+        (0 .. shnum).map(|idx| {
+                        let header = shoff + idx * shentsize;
+                        Ok(Section { kind: self.u32(header + 4)?,
+                                     flags: self.u64(header + 8)?,
+                                     addr: self.u64(header + 16)?,
+                                     offset: self.u64(header + 24)?,
+                                     size: self.u64(header + 32)?,
+                                     link: self.u32(header + 40)? })
+                    })
+                    .collect()
+    }
+
+    /// Returns a range of the file, or an error if it's out of bounds.
+    ///
+    /// * `offset`: Start of the range.
+    /// * `len`: Length of the range.
+    fn slice(&self, offset: u64, len: u64) -> io::Result<&'a [u8]>
+    {
+        usize::try_from(offset).ok()
+                               .zip(usize::try_from(len).ok())
+                               .and_then(|(offset, len)| self.data.get(offset .. offset.checked_add(len)?))
+                               .ok_or_else(|| invalid("Truncated ELF file"))
+    }
+
+    /// Reads a little endian 16 bit value.
+    ///
+    /// * `offset`: Offset of the value.
+    fn u16(&self, offset: u64) -> io::Result<u16>
+    {
+        Ok(u16::from_le_bytes(self.slice(offset, 2)?.try_into().unwrap()))
+    }
+
+    /// Reads a little endian 32 bit value.
+    ///
+    /// * `offset`: Offset of the value.
+    fn u32(&self, offset: u64) -> io::Result<u32>
+    {
+        Ok(u32::from_le_bytes(self.slice(offset, 4)?.try_into().unwrap()))
+    }
+
+    /// Reads a little endian 64 bit value.
+    ///
+    /// * `offset`: Offset of the value.
+    fn u64(&self, offset: u64) -> io::Result<u64>
+    {
+        Ok(u64::from_le_bytes(self.slice(offset, 8)?.try_into().unwrap()))
+    }
+}
+
+/// Reads a NUL terminated string from a string table.
+///
+/// * `strings`: String table.
+/// * `offset`: Offset of the string.
+///
+/// Returns the string, or an error if it's out of bounds or not valid UTF-8.
+fn string(strings: &[u8], offset: usize) -> io::Result<&str>
+{
+    let bytes = strings.get(offset ..)
+                       .ok_or_else(|| invalid("Symbol name out of bounds"))?;
+    let len = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[.. len]).map_err(|_| invalid("Symbol name is not valid UTF-8"))
+}
+
+/// Creates an error for malformed input.
+///
+/// * `msg`: Error message.
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(ErrorKind::InvalidData, msg)
+}